@@ -0,0 +1,246 @@
+use std::sync::atomic::AtomicBool;
+
+use crate::config::RunConfig;
+use crate::flow::{self, Program, Token};
+use crate::minilang;
+use crate::runner::{self, Outcome, Tone};
+use crate::semantic_tokens::LexKind;
+
+/// Samples per second of exported audio
+const SAMPLE_RATE: u32 = 44100;
+
+/// Square wave level, a quarter of full scale to leave headroom
+const AMPLITUDE: i16 = 8192;
+
+/// Longest audio to export, so an endless tune still makes a file
+const MAX_SECONDS: f64 = 600.0;
+
+/// SOUND durations are in clock ticks
+const TICKS_PER_SECOND: f64 = 18.2;
+
+/// Frequencies the speaker plays; SOUND 32767 is the usual way to rest
+const AUDIBLE_FREQUENCIES: (f64, f64) = (37.0, 32766.0);
+
+/// BEEP is an 800 Hz tone for a quarter second
+const BEEP: Tone = Tone {
+    frequency: 800.0,
+    seconds: 0.25,
+};
+
+/// Tones of the PLAY, SOUND and BEEP statements whose arguments are
+/// constants, in source order. Jumps and loops aren't followed, so this
+/// is the music as written rather than as played
+pub fn extract(source: &str) -> Vec<Tone> {
+    let program = Program::build(source);
+    let mut tones = Vec::new();
+
+    for statement in &program.statements {
+        let arguments = statement.arguments();
+        match statement.keyword() {
+            Some("PLAY") => {
+                if let [[string]] = arguments.as_slice() {
+                    if string.kind == LexKind::String {
+                        tones.extend(play_tones(string.text.trim_matches('"')));
+                    }
+                }
+            }
+            Some("SOUND") => {
+                if let [frequency, duration] = arguments.as_slice() {
+                    if let (Some(frequency), Some(ticks)) = (number(frequency), number(duration)) {
+                        tones.push(Tone {
+                            frequency,
+                            seconds: ticks / TICKS_PER_SECOND,
+                        });
+                    }
+                }
+            }
+            Some("BEEP") => tones.push(BEEP),
+            _ => {}
+        }
+    }
+
+    tones
+}
+
+/// Run a program headlessly and collect the tones it plays
+pub fn record(source: &str, limits: &RunConfig) -> (Outcome, Vec<Tone>) {
+    let mut tones = Vec::new();
+    let (outcome, _) = runner::run_with(
+        source,
+        Vec::new(),
+        limits,
+        Box::new(|_| {}),
+        &AtomicBool::new(false),
+        |debuggee, _, done| {
+            if done {
+                tones = debuggee.tones();
+            }
+        },
+    );
+    (outcome, tones)
+}
+
+/// A PLAY string as tones, each note followed by the silence its
+/// articulation leaves
+fn play_tones(content: &str) -> Vec<Tone> {
+    let mut tones = Vec::new();
+    for note in minilang::parse_play(content) {
+        let sounded = note.seconds * note.sounded;
+        if sounded > 0.0 {
+            tones.push(Tone {
+                frequency: note.frequency,
+                seconds: sounded,
+            });
+        }
+        if note.seconds > sounded {
+            tones.push(Tone {
+                frequency: 0.0,
+                seconds: note.seconds - sounded,
+            });
+        }
+    }
+    tones
+}
+
+/// Value of an argument that is a literal number, fractions kept
+fn number(tokens: &[Token]) -> Option<f64> {
+    match tokens {
+        [number] if number.kind == LexKind::Number => {
+            let text = number.text.trim_end_matches(['!', '#']);
+            text.replace('D', "E").parse().ok()
+        }
+        _ => flow::constant(tokens).map(|value| value as f64),
+    }
+}
+
+/// Total length of some tones in seconds, as exported
+pub fn duration(tones: &[Tone]) -> f64 {
+    tones
+        .iter()
+        .map(|t| t.seconds)
+        .sum::<f64>()
+        .min(MAX_SECONDS)
+}
+
+/// Tones as square waves in a 16-bit mono WAV file
+pub fn encode_wav(tones: &[Tone]) -> Vec<u8> {
+    let samples = synthesize(tones);
+    let data_len = samples.len() as u32 * 2;
+
+    let mut wav = Vec::with_capacity(44 + data_len as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_len).to_le_bytes());
+    wav.extend_from_slice(b"WAVE");
+
+    wav.extend_from_slice(b"fmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes());
+    // PCM, one channel
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&1u16.to_le_bytes());
+    wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes());
+    // Two bytes per sample frame, 16 bits per sample
+    wav.extend_from_slice(&2u16.to_le_bytes());
+    wav.extend_from_slice(&16u16.to_le_bytes());
+
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_len.to_le_bytes());
+    for sample in samples {
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+    wav
+}
+
+fn synthesize(tones: &[Tone]) -> Vec<i16> {
+    let rate = SAMPLE_RATE as f64;
+    let max_samples = (MAX_SECONDS * rate) as usize;
+    let mut samples = Vec::new();
+    let mut elapsed = 0.0;
+    // Where in its cycle the wave is, from 0 to 1
+    let mut phase = 0.0f64;
+
+    for tone in tones {
+        // Ends are rounded from the running total so lengths don't drift
+        elapsed += tone.seconds.max(0.0);
+        let end = ((elapsed * rate).round() as usize).min(max_samples);
+        let (low, high) = AUDIBLE_FREQUENCIES;
+        let audible = tone.frequency >= low && tone.frequency <= high;
+
+        while samples.len() < end {
+            if audible {
+                samples.push(if phase < 0.5 { AMPLITUDE } else { -AMPLITUDE });
+                phase = (phase + tone.frequency / rate).fract();
+            } else {
+                samples.push(0);
+            }
+        }
+        if end == max_samples {
+            break;
+        }
+    }
+
+    samples
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn constant_statements_make_tones() {
+        let tones = extract("10 SOUND 440, 18.2\n20 BEEP\n30 SOUND F, 5\n40 PLAY \"ML C\"\n");
+        assert_eq!(tones.len(), 3);
+        assert_eq!(tones[0].frequency, 440.0);
+        assert!((tones[0].seconds - 1.0).abs() < 1e-9);
+        assert_eq!(tones[1], BEEP);
+        assert!((tones[2].frequency - 523.25).abs() < 0.01);
+        assert_eq!(tones[2].seconds, 0.5);
+    }
+
+    #[test]
+    fn notes_rest_for_their_articulation() {
+        let tones = play_tones("C");
+        assert_eq!(tones.len(), 2);
+        assert_eq!(tones[0].seconds, 0.4375);
+        assert_eq!(tones[1].frequency, 0.0);
+        assert_eq!(tones[1].seconds, 0.0625);
+    }
+
+    #[test]
+    fn wav_files_hold_square_waves() {
+        let tones = [
+            Tone {
+                frequency: 441.0,
+                seconds: 0.01,
+            },
+            Tone {
+                frequency: 32767.0,
+                seconds: 0.01,
+            },
+        ];
+        let wav = encode_wav(&tones);
+        assert_eq!(&wav[0..4], b"RIFF");
+        assert_eq!(&wav[36..40], b"data");
+        assert_eq!(u32::from_le_bytes(wav[40..44].try_into().unwrap()), 882 * 2);
+        assert_eq!(wav.len(), 44 + 882 * 2);
+
+        let samples = synthesize(&tones);
+        // 441 Hz is 100 samples a cycle, high for the first half
+        assert_eq!(samples[0], AMPLITUDE);
+        assert_eq!(samples[50], -AMPLITUDE);
+        assert!(samples[441..].iter().all(|&s| s == 0));
+    }
+
+    #[test]
+    fn endless_tunes_are_cut_short() {
+        let tones = [Tone {
+            frequency: 0.0,
+            seconds: 1e6,
+        }];
+        assert_eq!(duration(&tones), MAX_SECONDS);
+        assert_eq!(
+            synthesize(&tones).len(),
+            (MAX_SECONDS * SAMPLE_RATE as f64) as usize
+        );
+    }
+}
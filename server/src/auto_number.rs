@@ -0,0 +1,183 @@
+use std::collections::HashMap;
+use tower_lsp::lsp_types::*;
+
+use crate::renumber::{byte_offset, leading_line_number, utf16_len, MAX_LINE_NUMBER};
+
+/// Step used when the program doesn't suggest one
+const DEFAULT_STEP: u32 = 10;
+
+/// Result of numbering a line after Enter
+pub enum AutoNumber {
+    /// Edits to apply to the document
+    Edits(Vec<TextEdit>),
+    /// No free line number exists after this line; the program needs renumbering
+    NoRoom(u32),
+}
+
+/// Insert the next line number after Enter is pressed, the way AUTO does.
+///
+/// The new number is the previous line number plus `step` (or the step
+/// detected from the program when `step` is 0). When that would collide with
+/// the following line, the midpoint between the two is used instead.
+/// Pressing Enter on a line holding only a number removes that number,
+/// ending auto-numbering for this block.
+pub fn on_newline(source: &str, position: Position, step: u32) -> Option<AutoNumber> {
+    let lines: Vec<&str> = source
+        .split('\n')
+        .map(|l| l.trim_end_matches('\r'))
+        .collect();
+    let line_idx = position.line as usize;
+    let current = lines.get(line_idx)?;
+
+    // Only number fresh lines: nothing typed before the cursor and no number yet
+    let cursor = byte_offset(current, position.character);
+    if line_idx == 0 || !current[..cursor].trim().is_empty() {
+        return None;
+    }
+    if leading_line_number(current).is_some() {
+        return None;
+    }
+
+    // A line holding only a number ends auto-numbering
+    let previous = lines[line_idx - 1];
+    if let Some((_, _, end)) = leading_line_number(previous) {
+        if previous[end..].trim().is_empty() {
+            return Some(AutoNumber::Edits(vec![TextEdit {
+                range: Range {
+                    start: Position {
+                        line: position.line - 1,
+                        character: 0,
+                    },
+                    end: Position {
+                        line: position.line - 1,
+                        character: utf16_len(previous),
+                    },
+                },
+                new_text: String::new(),
+            }]));
+        }
+    }
+
+    let prev_num = lines[..line_idx]
+        .iter()
+        .rev()
+        .find_map(|l| leading_line_number(l))
+        .map(|(num, _, _)| num)?;
+    let next_num = lines[line_idx + 1..]
+        .iter()
+        .find_map(|l| leading_line_number(l))
+        .map(|(num, _, _)| num);
+
+    let step = if step == 0 { detect_step(&lines) } else { step };
+    let number = match next_line_number(prev_num, next_num, step) {
+        Some(number) => number,
+        None => return Some(AutoNumber::NoRoom(prev_num)),
+    };
+
+    Some(AutoNumber::Edits(vec![TextEdit {
+        range: Range {
+            start: Position {
+                line: position.line,
+                character: 0,
+            },
+            end: Position {
+                line: position.line,
+                character: utf16_len(&current[..cursor]),
+            },
+        },
+        new_text: format!("{} ", number),
+    }]))
+}
+
+/// Pick a line number after `prev` that stays below `next`
+fn next_line_number(prev: u32, next: Option<u32>, step: u32) -> Option<u32> {
+    let limit = next.unwrap_or(MAX_LINE_NUMBER + 1).min(MAX_LINE_NUMBER + 1);
+    if limit <= prev + 1 {
+        return None;
+    }
+
+    let candidate = prev + step.max(1);
+    if candidate < limit {
+        return Some(candidate);
+    }
+
+    // Squeeze the line in between its neighbours
+    Some(prev + (limit - prev) / 2)
+}
+
+/// Find the most common increment between consecutive line numbers
+fn detect_step(lines: &[&str]) -> u32 {
+    let numbers: Vec<u32> = lines
+        .iter()
+        .filter_map(|l| leading_line_number(l))
+        .map(|(num, _, _)| num)
+        .collect();
+
+    let mut counts: HashMap<u32, usize> = HashMap::new();
+    for pair in numbers.windows(2) {
+        if pair[1] > pair[0] {
+            *counts.entry(pair[1] - pair[0]).or_default() += 1;
+        }
+    }
+
+    counts
+        .into_iter()
+        .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)))
+        .map(|(step, _)| step)
+        .unwrap_or(DEFAULT_STEP)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_line(source: &str, line: u32, step: u32) -> Option<AutoNumber> {
+        on_newline(source, Position { line, character: 0 }, step)
+    }
+
+    fn inserted(result: Option<AutoNumber>) -> String {
+        match result {
+            Some(AutoNumber::Edits(edits)) => edits[0].new_text.clone(),
+            _ => panic!("expected edits"),
+        }
+    }
+
+    #[test]
+    fn numbers_follow_the_previous_line() {
+        assert_eq!(inserted(new_line("10 PRINT\n\n", 1, 10)), "20 ");
+        assert_eq!(inserted(new_line("10 PRINT\n\n15 END", 1, 10)), "12 ");
+        assert_eq!(inserted(new_line("100 A\n105 B\n110 C\n\n", 3, 0)), "115 ");
+    }
+
+    #[test]
+    fn full_blocks_need_renumbering() {
+        assert!(matches!(
+            new_line("10 PRINT\n\n11 END", 1, 10),
+            Some(AutoNumber::NoRoom(10))
+        ));
+        assert!(matches!(
+            new_line("65529 END\n\n", 1, 10),
+            Some(AutoNumber::NoRoom(65529))
+        ));
+    }
+
+    #[test]
+    fn a_bare_number_ends_numbering() {
+        let Some(AutoNumber::Edits(edits)) = new_line("10 PRINT\n20 \n", 2, 10) else {
+            panic!("expected edits");
+        };
+        assert_eq!(edits[0].range.start.line, 1);
+        assert_eq!(edits[0].new_text, "");
+    }
+
+    #[test]
+    fn typed_lines_are_left_alone() {
+        let position = Position {
+            line: 1,
+            character: 2,
+        };
+        assert!(on_newline("10 PRINT\nPRINT\n", position, 10).is_none());
+        assert!(on_newline("10 PRINT\n😀\n", position, 10).is_none());
+        assert!(on_newline("10 PRINT\n  é", position, 10).is_some());
+    }
+}
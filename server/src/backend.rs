@@ -1,44 +1,697 @@
-use std::collections::HashMap;
-use std::sync::RwLock;
-use tower_lsp::jsonrpc::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use tower_lsp::jsonrpc::{Error, Result};
+use tower_lsp::lsp_types::notification::Notification;
 use tower_lsp::lsp_types::*;
 use tower_lsp::{Client, LanguageServer};
 
+use crate::audio;
+use crate::auto_number::{self, AutoNumber};
 use crate::completion;
+use crate::config::Config;
+use crate::coverage;
 use crate::definition;
 use crate::diagnostics;
 use crate::folding;
+use crate::formatting;
+use crate::golden;
 use crate::hover;
+use crate::lenses;
 use crate::references;
 use crate::rename;
+use crate::renumber;
+use crate::runner;
+use crate::screen;
+use crate::selection;
 use crate::semantic_tokens;
 use crate::signature;
 use crate::symbols;
+use crate::tokenized;
+
+/// Command that renumbers a document: arguments are `[uri, start?, step?]`
+const RENUMBER_COMMAND: &str = "basica.renumber";
+
+/// Command that converts a file between tokenized and ASCII form:
+/// arguments are `[uri, targetUri]`, where the target is a different file
+const CONVERT_COMMAND: &str = "basica.convert";
+
+/// Command that runs a document with the embedded interpreter: arguments
+/// are `[uri]`, and the result is `{ status, message? }`
+const RUN_COMMAND: &str = "basica.run";
+
+/// Command that runs a document and records how often each line runs:
+/// arguments are `[uri, inputUri?]`, where the input script answers INPUT
+/// one line at a time. The result adds `{ executed, lines }`
+const TRACE_COMMAND: &str = "basica.trace";
+
+/// Command that runs golden-output tests: arguments are `[uri?]`, a
+/// program or folder, defaulting to the whole workspace. The result is
+/// the `basica/testResults` notification's parameters
+const TEST_COMMAND: &str = "basica.test";
+
+/// Command that runs a document headlessly and returns its graphics
+/// screen: arguments are `[uri, frames?]`. The result is `{ status,
+/// message?, width, height, frames }`, with each frame a base64 PNG and the
+/// final screen last
+const RENDER_COMMAND: &str = "basica.render";
+
+/// Command that writes a document's PLAY, SOUND and BEEP music to a WAV
+/// file: arguments are `[uri, targetUri, run?]`. Without `run` the tones
+/// come from constant arguments, without running anything. The result is
+/// `{ status?, message?, seconds }`
+const EXPORT_AUDIO_COMMAND: &str = "basica.exportAudio";
+
+/// Parameters of the `basica/detokenize` request
+#[derive(Debug, Deserialize)]
+pub struct DetokenizeParams {
+    pub uri: Url,
+}
+
+/// Result of the `basica/detokenize` request: the program as text
+#[derive(Debug, Serialize)]
+pub struct DetokenizeResult {
+    pub text: String,
+}
+
+/// Parameters of the `basica/evaluate` request: a direct statement like
+/// `? LEFT$("HELLO",2)`, or an expression to print
+#[derive(Debug, Deserialize)]
+pub struct EvaluateParams {
+    pub text: String,
+    /// Start a fresh session, forgetting variables, like NEW
+    #[serde(default)]
+    pub reset: bool,
+}
+
+/// Result of the `basica/evaluate` request
+#[derive(Debug, Serialize)]
+pub struct EvaluateResult {
+    /// What the statement printed
+    pub output: String,
+    /// The error it stopped with, as BASICA would print it
+    pub error: Option<String>,
+}
+
+/// Text printed by a program started with `basica.run`
+pub enum OutputNotification {}
+
+impl Notification for OutputNotification {
+    type Params = OutputParams;
+    const METHOD: &'static str = "basica/output";
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct OutputParams {
+    pub uri: Url,
+    pub text: String,
+}
+
+/// Results of a `basica.test` run
+pub enum TestResultsNotification {}
+
+impl Notification for TestResultsNotification {
+    type Params = TestResultsParams;
+    const METHOD: &'static str = "basica/testResults";
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct TestResultsParams {
+    pub results: Vec<TestResultItem>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TestResultItem {
+    pub uri: Url,
+    pub expected_uri: Url,
+    pub passed: bool,
+    pub message: Option<String>,
+    pub mismatch: Option<golden::Mismatch>,
+}
 
 pub struct BasicaBackend {
     client: Client,
-    documents: RwLock<HashMap<Url, String>>,
+    documents: Arc<RwLock<HashMap<Url, String>>>,
+    config: RwLock<Config>,
+    /// Last full semantic tokens sent per document, for delta requests
+    semantic_tokens: RwLock<HashMap<Url, SemanticTokens>>,
+    next_result_id: AtomicU64,
+    /// Whether the client answers `workspace/configuration`
+    pull_configuration: AtomicBool,
+    /// Documents opened as the raw bytes of a tokenized program. They get no
+    /// language features; the extension shows the program as a
+    /// `basica-detokenized:` document instead
+    tokenized: RwLock<HashSet<Url>>,
+    /// Stop flags of programs still running, per document
+    runs: RwLock<HashMap<Url, Arc<AtomicBool>>>,
+    /// The error the last run of each document stopped with
+    runtime_errors: RwLock<HashMap<Url, Diagnostic>>,
+    /// Line hit counts from the last trace of each document
+    coverage: RwLock<HashMap<Url, runner::Hits>>,
+    /// Programs whose golden-output test failed last time
+    test_failures: RwLock<HashMap<Url, Diagnostic>>,
+    /// Workspace folders, where tests are looked for
+    roots: RwLock<Vec<PathBuf>>,
+    /// The `basica/evaluate` session, locked for a whole request so
+    /// statements run one at a time and in order
+    immediate: tokio::sync::Mutex<Option<runner::Immediate>>,
 }
 
 impl BasicaBackend {
     pub fn new(client: Client) -> Self {
         Self {
             client,
-            documents: RwLock::new(HashMap::new()),
+            documents: Arc::new(RwLock::new(HashMap::new())),
+            config: RwLock::new(Config::default()),
+            semantic_tokens: RwLock::new(HashMap::new()),
+            next_result_id: AtomicU64::new(1),
+            pull_configuration: AtomicBool::new(false),
+            tokenized: RwLock::new(HashSet::new()),
+            runs: RwLock::new(HashMap::new()),
+            runtime_errors: RwLock::new(HashMap::new()),
+            coverage: RwLock::new(HashMap::new()),
+            test_failures: RwLock::new(HashMap::new()),
+            roots: RwLock::new(Vec::new()),
+            immediate: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    /// Handle `basica/detokenize`: read a file, detokenizing it if needed,
+    /// for the extension to show as a read-only document
+    pub async fn detokenize(&self, params: DetokenizeParams) -> Result<DetokenizeResult> {
+        let path = params
+            .uri
+            .to_file_path()
+            .map_err(|_| Error::invalid_params("Only files on disk can be detokenized"))?;
+        tokio::task::spawn_blocking(move || tokenized::read_program(&path))
+            .await
+            .map_err(|_| Error::internal_error())?
+            .map(|text| DetokenizeResult { text })
+            .map_err(Error::invalid_params)
+    }
+
+    /// Handle `basica/evaluate`: run a direct statement in the immediate
+    /// session, which keeps its variables from one request to the next
+    pub async fn evaluate(&self, params: EvaluateParams) -> Result<EvaluateResult> {
+        let mut immediate = self.immediate.lock().await;
+        let mut session = match immediate.take().filter(|_| !params.reset) {
+            Some(session) => session,
+            None => runner::Immediate::new().map_err(|message| Error {
+                message: message.into(),
+                ..Error::internal_error()
+            })?,
+        };
+        let limits = self.config.read().unwrap().run.clone();
+
+        let result = tokio::task::spawn_blocking(move || {
+            let (output, outcome) = session.execute(&params.text, &limits);
+            (session, output, outcome.message(&limits))
+        })
+        .await;
+        // The session went down with the failed task; the next line gets a
+        // fresh one instead of state left halfway through a statement
+        let Ok((session, output, error)) = result else {
+            *immediate = None;
+            return Err(Error {
+                message: "The interpreter failed; variables were cleared".into(),
+                ..Error::internal_error()
+            });
+        };
+        *immediate = Some(session);
+
+        Ok(EvaluateResult { output, error })
+    }
+
+    /// Convert a file between tokenized and ASCII form into another file
+    async fn convert(&self, arguments: Vec<Value>) -> Result<Option<Value>> {
+        let mut args = arguments
+            .into_iter()
+            .map(|v| serde_json::from_value::<Url>(v).ok());
+        let Some(uri) = args.next().flatten() else {
+            return Ok(None);
+        };
+        let Some(target) = args.next().flatten() else {
+            return Err(Error::invalid_params("Missing the file to write"));
+        };
+        let (Ok(source_path), Ok(target_path)) = (uri.to_file_path(), target.to_file_path()) else {
+            return Err(Error::invalid_params("Only files on disk can be converted"));
+        };
+        if source_path == target_path {
+            return Err(Error::invalid_params(
+                "Save the converted program to another file",
+            ));
+        }
+
+        let is_tokenized = tokio::task::spawn_blocking(move || {
+            let bytes = std::fs::read(&source_path)
+                .map_err(|e| format!("{}: {}", source_path.display(), e))?;
+            let (converted, is_tokenized) = tokenized::convert(&bytes)?;
+            std::fs::write(&target_path, converted)
+                .map_err(|e| format!("{}: {}", target_path.display(), e))?;
+            Ok::<_, String>(is_tokenized)
+        })
+        .await
+        .map_err(|_| Error::internal_error())?
+        .map_err(Error::invalid_params)?;
+
+        Ok(Some(serde_json::json!({
+            "uri": target,
+            "tokenized": is_tokenized,
+        })))
+    }
+
+    /// Tag full semantic tokens with a fresh result id and remember them
+    fn cache_semantic_tokens(&self, uri: &Url, mut tokens: SemanticTokens) -> SemanticTokens {
+        let id = self.next_result_id.fetch_add(1, Ordering::Relaxed);
+        tokens.result_id = Some(id.to_string());
+        self.semantic_tokens
+            .write()
+            .unwrap()
+            .insert(uri.clone(), tokens.clone());
+        tokens
+    }
+
+    /// Handle `basica.run`: run a document and report a runtime error on
+    /// the line it happened on
+    async fn run(&self, arguments: Vec<Value>) -> Result<Option<Value>> {
+        let Some((uri, source)) = self.command_document(&arguments) else {
+            return Ok(None);
+        };
+        let (outcome, _, message) = self.execute(&uri, &source, Vec::new()).await?;
+        if outcome.diagnostic(&source).is_some() {
+            self.revalidate(&uri).await;
+        }
+
+        Ok(Some(serde_json::json!({
+            "status": outcome.status(),
+            "message": message,
+        })))
+    }
+
+    /// Handle `basica.trace`: run a document with INPUT answered from a
+    /// script file, then show how often each line ran
+    async fn trace(&self, arguments: Vec<Value>) -> Result<Option<Value>> {
+        let Some((uri, source)) = self.command_document(&arguments) else {
+            return Ok(None);
+        };
+        let input = match arguments
+            .get(1)
+            .and_then(|v| serde_json::from_value::<Url>(v.clone()).ok())
+        {
+            Some(script) => {
+                let path = script
+                    .to_file_path()
+                    .map_err(|_| Error::invalid_params("The input script must be a file"))?;
+                let text = std::fs::read_to_string(&path)
+                    .map_err(|e| Error::invalid_params(format!("{}: {}", path.display(), e)))?;
+                text.lines().map(str::to_string).collect()
+            }
+            None => Vec::new(),
+        };
+
+        let (outcome, hits, message) = self.execute(&uri, &source, input).await?;
+        let lines = coverage::numbered_lines(&source);
+        let executed = lines.iter().filter(|l| hits.contains_key(l)).count();
+        self.coverage.write().unwrap().insert(uri.clone(), hits);
+        self.revalidate(&uri).await;
+        let _ = self.client.code_lens_refresh().await;
+
+        Ok(Some(serde_json::json!({
+            "status": outcome.status(),
+            "message": message,
+            "executed": executed,
+            "lines": lines.len(),
+        })))
+    }
+
+    /// Handle `basica.test`: run the golden-output tests for a program, a
+    /// folder or the workspace, and report failures on the programs
+    async fn test(&self, arguments: Vec<Value>) -> Result<Option<Value>> {
+        let paths = match arguments
+            .first()
+            .and_then(|v| serde_json::from_value::<Url>(v.clone()).ok())
+        {
+            Some(uri) => vec![uri
+                .to_file_path()
+                .map_err(|_| Error::invalid_params("Only files on disk can be tested"))?],
+            None => self.roots.read().unwrap().clone(),
+        };
+        let limits = self.config.read().unwrap().run.clone();
+
+        let results = tokio::task::spawn_blocking(move || {
+            paths
+                .iter()
+                .flat_map(|path| golden::discover(path))
+                .map(|fixture| golden::run(fixture, &limits))
+                .collect::<Vec<_>>()
+        })
+        .await
+        .map_err(|_| Error::internal_error())?;
+
+        let mut items = Vec::new();
+        for result in results {
+            let (Ok(uri), Ok(expected_uri)) = (
+                Url::from_file_path(&result.fixture.program),
+                Url::from_file_path(&result.fixture.expected),
+            ) else {
+                continue;
+            };
+            let open = self.documents.read().unwrap().get(&uri).cloned();
+            let source = match &open {
+                Some(text) => text.clone(),
+                None => tokenized::read_program(&result.fixture.program).unwrap_or_default(),
+            };
+            let failure = golden::diagnostic(&result, &source);
+            match &failure {
+                Some(d) => self
+                    .test_failures
+                    .write()
+                    .unwrap()
+                    .insert(uri.clone(), d.clone()),
+                None => self.test_failures.write().unwrap().remove(&uri),
+            };
+            match open {
+                Some(text) => self.validate(&uri, &text).await,
+                None => {
+                    self.client
+                        .publish_diagnostics(uri.clone(), failure.into_iter().collect(), None)
+                        .await
+                }
+            }
+
+            items.push(TestResultItem {
+                uri,
+                expected_uri,
+                passed: result.passed,
+                message: result.message,
+                mismatch: result.mismatch,
+            });
+        }
+
+        let params = TestResultsParams { results: items };
+        let value = serde_json::to_value(&params).ok();
+        self.client
+            .send_notification::<TestResultsNotification>(params)
+            .await;
+        Ok(value)
+    }
+
+    /// Handle `basica.render`: run a document with a virtual screen and
+    /// return what it drew as PNG images
+    async fn render(&self, arguments: Vec<Value>) -> Result<Option<Value>> {
+        let Some((_, source)) = self.command_document(&arguments) else {
+            return Ok(None);
+        };
+        let frames = arguments.get(1).and_then(Value::as_bool).unwrap_or(false);
+        let limits = self.config.read().unwrap().run.clone();
+
+        let render = {
+            let limits = limits.clone();
+            tokio::task::spawn_blocking(move || screen::render(&source, &limits, frames))
+                .await
+                .map_err(|_| Error::internal_error())?
+        };
+        let message = if render.frames.is_empty() {
+            Some("The program didn't draw in a graphics SCREEN mode".to_string())
+        } else {
+            render.outcome.message(&limits)
+        };
+
+        Ok(Some(serde_json::json!({
+            "status": render.outcome.status(),
+            "message": message,
+            "width": render.width,
+            "height": render.height,
+            "frames": render.frames.iter().map(|png| screen::base64(png)).collect::<Vec<_>>(),
+        })))
+    }
+
+    /// Handle `basica.exportAudio`: synthesize a document's music and save
+    /// it as a WAV file
+    async fn export_audio(&self, arguments: Vec<Value>) -> Result<Option<Value>> {
+        let Some((_, source)) = self.command_document(&arguments) else {
+            return Ok(None);
+        };
+        let Some(target) = arguments
+            .get(1)
+            .and_then(|v| serde_json::from_value::<Url>(v.clone()).ok())
+        else {
+            return Err(Error::invalid_params("Missing the WAV file to write"));
+        };
+        let Ok(target_path) = target.to_file_path() else {
+            return Err(Error::invalid_params(
+                "Audio can only be saved to a file on disk",
+            ));
+        };
+        let run = arguments.get(2).and_then(Value::as_bool).unwrap_or(false);
+
+        let (status, message, tones) = if run {
+            let limits = self.config.read().unwrap().run.clone();
+            let (outcome, tones) = {
+                let limits = limits.clone();
+                tokio::task::spawn_blocking(move || audio::record(&source, &limits))
+                    .await
+                    .map_err(|_| Error::internal_error())?
+            };
+            let message = if tones.is_empty() {
+                Some("The program played nothing".to_string())
+            } else {
+                outcome.message(&limits)
+            };
+            (Some(outcome.status()), message, tones)
+        } else {
+            let tones = audio::extract(&source);
+            let message = tones
+                .is_empty()
+                .then(|| "No PLAY or SOUND statements with constant arguments".to_string());
+            (None, message, tones)
+        };
+
+        if !tones.is_empty() {
+            std::fs::write(&target_path, audio::encode_wav(&tones))
+                .map_err(|e| Error::invalid_params(format!("{}: {}", target_path.display(), e)))?;
+        }
+
+        Ok(Some(serde_json::json!({
+            "status": status,
+            "message": message,
+            "seconds": audio::duration(&tones),
+        })))
+    }
+
+    /// The document named by a command's first argument, with its text
+    fn command_document(&self, arguments: &[Value]) -> Option<(Url, String)> {
+        let uri = serde_json::from_value::<Url>(arguments.first()?.clone()).ok()?;
+        let source = self.documents.read().unwrap().get(&uri).cloned()?;
+        Some((uri, source))
+    }
+
+    /// Run a document in the background, streaming its output to the
+    /// client, and remember the runtime error it stops with
+    async fn execute(
+        &self,
+        uri: &Url,
+        source: &str,
+        input: Vec<String>,
+    ) -> Result<(runner::Outcome, runner::Hits, Option<String>)> {
+        let limits = self.config.read().unwrap().run.clone();
+
+        // Running a program again stops the previous run
+        let stop = Arc::new(AtomicBool::new(false));
+        if let Some(previous) = self.runs.write().unwrap().insert(uri.clone(), stop.clone()) {
+            previous.store(true, Ordering::Relaxed);
+        }
+        if self.runtime_errors.write().unwrap().remove(uri).is_some() {
+            self.validate(uri, source).await;
+        }
+
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel::<String>();
+        let task = {
+            let (source, limits, stop) = (source.to_string(), limits.clone(), stop.clone());
+            let output = Box::new(move |text: &str| {
+                let _ = sender.send(text.to_string());
+            });
+            tokio::task::spawn_blocking(move || runner::run(&source, input, &limits, output, &stop))
+        };
+        // The channel closes when the interpreter is dropped
+        while let Some(text) = receiver.recv().await {
+            self.client
+                .send_notification::<OutputNotification>(OutputParams {
+                    uri: uri.clone(),
+                    text,
+                })
+                .await;
+        }
+        let (outcome, hits) = task.await.map_err(|_| Error::internal_error())?;
+
+        {
+            let mut runs = self.runs.write().unwrap();
+            if runs.get(uri).is_some_and(|s| Arc::ptr_eq(s, &stop)) {
+                runs.remove(uri);
+            }
+        }
+        let message = outcome.message(&limits);
+        if let Some(message) = &message {
+            self.client
+                .send_notification::<OutputNotification>(OutputParams {
+                    uri: uri.clone(),
+                    text: format!("{}\n", message),
+                })
+                .await;
+        }
+        if let Some(diagnostic) = outcome.diagnostic(source) {
+            self.runtime_errors
+                .write()
+                .unwrap()
+                .insert(uri.clone(), diagnostic);
+        }
+        Ok((outcome, hits, message))
+    }
+
+    /// Publish diagnostics again for an open document
+    async fn revalidate(&self, uri: &Url) {
+        let text = self.documents.read().unwrap().get(uri).cloned();
+        if let Some(text) = text {
+            self.validate(uri, &text).await;
         }
     }
 
     async fn validate(&self, uri: &Url, text: &str) {
-        let diagnostics = diagnostics::check(text);
+        let diagnostics = {
+            let config = self.config.read().unwrap();
+            if config.features.diagnostics {
+                let mut diagnostics = diagnostics::check(text, uri, &config);
+                let mut extra = Vec::new();
+                if let Some(error) = self.runtime_errors.read().unwrap().get(uri) {
+                    extra.push(error.clone());
+                }
+                if let Some(failure) = self.test_failures.read().unwrap().get(uri) {
+                    extra.push(failure.clone());
+                }
+                if let Some(hits) = self.coverage.read().unwrap().get(uri) {
+                    extra.extend(coverage::check(text, hits));
+                }
+                diagnostics.extend(diagnostics::configure(extra, &config.diagnostics));
+                diagnostics
+            } else {
+                Vec::new()
+            }
+        };
         self.client
             .publish_diagnostics(uri.clone(), diagnostics, None)
             .await;
     }
+
+    /// Re-read the `basica` settings from the client, or take them from
+    /// `settings` when it can't be asked, then refresh open documents
+    async fn reload_config(&self, settings: Option<Value>) {
+        let mut value = settings
+            .filter(|s| !s.is_null())
+            .map(|s| match s.get("basica") {
+                Some(section) => section.clone(),
+                None => s,
+            });
+        if self.pull_configuration.load(Ordering::Relaxed) {
+            let item = ConfigurationItem {
+                scope_uri: None,
+                section: Some("basica".to_string()),
+            };
+            if let Ok(mut values) = self.client.configuration(vec![item]).await {
+                value = values.pop().or(value);
+            }
+        }
+        let Some(value) = value else { return };
+        match Config::from_value(value) {
+            Ok(config) => *self.config.write().unwrap() = config,
+            Err(message) => {
+                let message = format!("{}; keeping the previous settings", message);
+                self.client
+                    .show_message(MessageType::WARNING, message)
+                    .await;
+                return;
+            }
+        }
+
+        let documents: Vec<(Url, String)> = self
+            .documents
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(uri, text)| (uri.clone(), text.clone()))
+            .collect();
+        for (uri, text) in documents {
+            self.validate(&uri, &text).await;
+        }
+        // The dialect decides which words are keywords
+        let _ = self.client.semantic_tokens_refresh().await;
+    }
+}
+
+/// Renumber a document and ask the client to apply the edits
+async fn apply_renumber(
+    client: &Client,
+    documents: &RwLock<HashMap<Url, String>>,
+    uri: Url,
+    start: u32,
+    step: u32,
+) -> std::result::Result<(), String> {
+    let edits = match documents.read().unwrap().get(&uri) {
+        Some(text) => renumber::renumber_edits(text, start, step)?,
+        None => return Ok(()),
+    };
+    if edits.is_empty() {
+        return Ok(());
+    }
+
+    let mut changes = HashMap::new();
+    changes.insert(uri, edits);
+    let _ = client
+        .apply_edit(WorkspaceEdit {
+            changes: Some(changes),
+            document_changes: None,
+            change_annotations: None,
+        })
+        .await;
+    Ok(())
 }
 
 #[tower_lsp::async_trait]
 impl LanguageServer for BasicaBackend {
-    async fn initialize(&self, _: InitializeParams) -> Result<InitializeResult> {
+    async fn initialize(&self, params: InitializeParams) -> Result<InitializeResult> {
+        if let Some(value) = params.initialization_options {
+            match Config::from_value(value) {
+                Ok(config) => *self.config.write().unwrap() = config,
+                Err(message) => {
+                    let message = format!("{}; using the default settings", message);
+                    self.client
+                        .show_message(MessageType::WARNING, message)
+                        .await;
+                }
+            }
+        }
+        let pull_configuration = params
+            .capabilities
+            .workspace
+            .as_ref()
+            .and_then(|w| w.configuration)
+            .unwrap_or(false);
+        self.pull_configuration
+            .store(pull_configuration, Ordering::Relaxed);
+        #[allow(deprecated)]
+        let roots = match (params.workspace_folders, params.root_uri) {
+            (Some(folders), _) => folders.into_iter().map(|f| f.uri).collect(),
+            (None, Some(root)) => vec![root],
+            (None, None) => Vec::new(),
+        };
+        *self.roots.write().unwrap() = roots
+            .iter()
+            .filter_map(|uri| uri.to_file_path().ok())
+            .collect();
+
         Ok(InitializeResult {
             capabilities: ServerCapabilities {
                 text_document_sync: Some(TextDocumentSyncCapability::Kind(
@@ -61,7 +714,28 @@ impl LanguageServer for BasicaBackend {
                     prepare_provider: Some(true),
                     work_done_progress_options: Default::default(),
                 })),
+                document_on_type_formatting_provider: Some(DocumentOnTypeFormattingOptions {
+                    first_trigger_character: "\n".to_string(),
+                    more_trigger_character: None,
+                }),
+                execute_command_provider: Some(ExecuteCommandOptions {
+                    commands: vec![
+                        RENUMBER_COMMAND.to_string(),
+                        CONVERT_COMMAND.to_string(),
+                        RUN_COMMAND.to_string(),
+                        TRACE_COMMAND.to_string(),
+                        TEST_COMMAND.to_string(),
+                        RENDER_COMMAND.to_string(),
+                        EXPORT_AUDIO_COMMAND.to_string(),
+                    ],
+                    work_done_progress_options: Default::default(),
+                }),
                 folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+                selection_range_provider: Some(SelectionRangeProviderCapability::Simple(true)),
+                document_formatting_provider: Some(OneOf::Left(true)),
+                code_lens_provider: Some(CodeLensOptions {
+                    resolve_provider: Some(true),
+                }),
                 semantic_tokens_provider: Some(
                     SemanticTokensServerCapabilities::SemanticTokensOptions(
                         SemanticTokensOptions {
@@ -69,8 +743,8 @@ impl LanguageServer for BasicaBackend {
                                 token_types: semantic_tokens::TOKEN_TYPES.to_vec(),
                                 token_modifiers: semantic_tokens::TOKEN_MODIFIERS.to_vec(),
                             },
-                            full: Some(SemanticTokensFullOptions::Bool(true)),
-                            range: None,
+                            full: Some(SemanticTokensFullOptions::Delta { delta: Some(true) }),
+                            range: Some(true),
                             work_done_progress_options: Default::default(),
                         },
                     ),
@@ -85,6 +759,11 @@ impl LanguageServer for BasicaBackend {
         self.client
             .log_message(MessageType::INFO, "basica LSP initialized")
             .await;
+        self.reload_config(None).await;
+    }
+
+    async fn did_change_configuration(&self, params: DidChangeConfigurationParams) {
+        self.reload_config(Some(params.settings)).await;
     }
 
     async fn shutdown(&self) -> Result<()> {
@@ -94,6 +773,17 @@ impl LanguageServer for BasicaBackend {
     async fn did_open(&self, params: DidOpenTextDocumentParams) {
         let uri = params.text_document.uri;
         let text = params.text_document.text;
+
+        // A tokenized program arrives as undecodable bytes. Leave it alone:
+        // the extension opens its detokenized text as a document of its own
+        if uri.scheme() == "file" && text.starts_with(['\u{FFFD}', '\u{FF}', '\u{FE}']) {
+            self.tokenized.write().unwrap().insert(uri.clone());
+            self.client
+                .log_message(MessageType::INFO, format!("{} is a tokenized program", uri))
+                .await;
+            return;
+        }
+
         self.documents
             .write()
             .unwrap()
@@ -103,7 +793,14 @@ impl LanguageServer for BasicaBackend {
 
     async fn did_change(&self, params: DidChangeTextDocumentParams) {
         let uri = params.text_document.uri;
+        if self.tokenized.read().unwrap().contains(&uri) {
+            return;
+        }
         if let Some(change) = params.content_changes.into_iter().next() {
+            // The program the error and hit counts came from has changed
+            self.runtime_errors.write().unwrap().remove(&uri);
+            self.coverage.write().unwrap().remove(&uri);
+            self.test_failures.write().unwrap().remove(&uri);
             self.documents
                 .write()
                 .unwrap()
@@ -113,10 +810,33 @@ impl LanguageServer for BasicaBackend {
     }
 
     async fn did_close(&self, params: DidCloseTextDocumentParams) {
+        if let Some(stop) = self.runs.write().unwrap().remove(&params.text_document.uri) {
+            stop.store(true, Ordering::Relaxed);
+        }
+        self.runtime_errors
+            .write()
+            .unwrap()
+            .remove(&params.text_document.uri);
+        self.coverage
+            .write()
+            .unwrap()
+            .remove(&params.text_document.uri);
+        self.test_failures
+            .write()
+            .unwrap()
+            .remove(&params.text_document.uri);
+        self.tokenized
+            .write()
+            .unwrap()
+            .remove(&params.text_document.uri);
         self.documents
             .write()
             .unwrap()
             .remove(&params.text_document.uri);
+        self.semantic_tokens
+            .write()
+            .unwrap()
+            .remove(&params.text_document.uri);
     }
 
     async fn goto_definition(
@@ -135,9 +855,16 @@ impl LanguageServer for BasicaBackend {
     async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
         let uri = &params.text_document_position_params.text_document.uri;
         let pos = params.text_document_position_params.position;
+        let (enabled, dialect) = {
+            let config = self.config.read().unwrap();
+            (config.features.hover, config.dialect)
+        };
+        if !enabled {
+            return Ok(None);
+        }
         let docs = self.documents.read().unwrap();
         if let Some(text) = docs.get(uri) {
-            return Ok(hover::get_hover(text, pos));
+            return Ok(hover::get_hover(text, pos, dialect));
         }
         Ok(None)
     }
@@ -145,9 +872,16 @@ impl LanguageServer for BasicaBackend {
     async fn completion(&self, params: CompletionParams) -> Result<Option<CompletionResponse>> {
         let uri = &params.text_document_position.text_document.uri;
         let pos = params.text_document_position.position;
+        let (enabled, dialect) = {
+            let config = self.config.read().unwrap();
+            (config.features.completion, config.dialect)
+        };
+        if !enabled {
+            return Ok(None);
+        }
         let docs = self.documents.read().unwrap();
         if let Some(text) = docs.get(uri) {
-            let items = completion::get_completions(text, pos);
+            let items = completion::get_completions(text, pos, dialect);
             return Ok(Some(CompletionResponse::Array(items)));
         }
         Ok(None)
@@ -182,6 +916,9 @@ impl LanguageServer for BasicaBackend {
     async fn signature_help(&self, params: SignatureHelpParams) -> Result<Option<SignatureHelp>> {
         let uri = &params.text_document_position_params.text_document.uri;
         let pos = params.text_document_position_params.position;
+        if !self.config.read().unwrap().features.signature_help {
+            return Ok(None);
+        }
         let docs = self.documents.read().unwrap();
         if let Some(text) = docs.get(uri) {
             return Ok(signature::get_signature_help(text, pos));
@@ -213,8 +950,50 @@ impl LanguageServer for BasicaBackend {
         Ok(None)
     }
 
+    async fn code_lens(&self, params: CodeLensParams) -> Result<Option<Vec<CodeLens>>> {
+        let uri = &params.text_document.uri;
+        if !self.config.read().unwrap().features.code_lens {
+            return Ok(None);
+        }
+        let docs = self.documents.read().unwrap();
+        let Some(text) = docs.get(uri) else {
+            return Ok(None);
+        };
+        let mut code_lenses = lenses::code_lenses(text, uri);
+        if let Some(hits) = self.coverage.read().unwrap().get(uri) {
+            code_lenses.extend(coverage::code_lenses(text, hits));
+        }
+        Ok(Some(code_lenses))
+    }
+
+    async fn code_lens_resolve(&self, params: CodeLens) -> Result<CodeLens> {
+        let Some(uri) = lenses::lens_uri(&params) else {
+            return Ok(params);
+        };
+        let docs = self.documents.read().unwrap();
+        match docs.get(&uri) {
+            Some(text) => Ok(lenses::resolve(params, text)),
+            None => Ok(params),
+        }
+    }
+
+    async fn selection_range(
+        &self,
+        params: SelectionRangeParams,
+    ) -> Result<Option<Vec<SelectionRange>>> {
+        let uri = &params.text_document.uri;
+        let dialect = self.config.read().unwrap().dialect;
+        let docs = self.documents.read().unwrap();
+        Ok(docs
+            .get(uri)
+            .map(|text| selection::selection_ranges(text, &params.positions, dialect)))
+    }
+
     async fn folding_range(&self, params: FoldingRangeParams) -> Result<Option<Vec<FoldingRange>>> {
         let uri = &params.text_document.uri;
+        if !self.config.read().unwrap().features.folding {
+            return Ok(None);
+        }
         let docs = self.documents.read().unwrap();
         if let Some(text) = docs.get(uri) {
             let ranges = folding::get_folding_ranges(text);
@@ -230,10 +1009,195 @@ impl LanguageServer for BasicaBackend {
         params: SemanticTokensParams,
     ) -> Result<Option<SemanticTokensResult>> {
         let uri = &params.text_document.uri;
+        let (enabled, dialect) = {
+            let config = self.config.read().unwrap();
+            (config.features.semantic_tokens, config.dialect)
+        };
+        if !enabled {
+            return Ok(None);
+        }
+        let tokens = match self.documents.read().unwrap().get(uri) {
+            Some(text) => semantic_tokens::get_semantic_tokens(text, dialect),
+            None => return Ok(None),
+        };
+        let tokens = self.cache_semantic_tokens(uri, tokens);
+        Ok(Some(SemanticTokensResult::Tokens(tokens)))
+    }
+
+    async fn semantic_tokens_full_delta(
+        &self,
+        params: SemanticTokensDeltaParams,
+    ) -> Result<Option<SemanticTokensFullDeltaResult>> {
+        let uri = &params.text_document.uri;
+        let (enabled, dialect) = {
+            let config = self.config.read().unwrap();
+            (config.features.semantic_tokens, config.dialect)
+        };
+        if !enabled {
+            return Ok(None);
+        }
+        let tokens = match self.documents.read().unwrap().get(uri) {
+            Some(text) => semantic_tokens::get_semantic_tokens(text, dialect),
+            None => return Ok(None),
+        };
+
+        let previous = self.semantic_tokens.read().unwrap().get(uri).cloned();
+        let tokens = self.cache_semantic_tokens(uri, tokens);
+
+        // Fall back to a full result if we no longer have the client's version
+        match previous {
+            Some(prev) if prev.result_id == Some(params.previous_result_id) => Ok(Some(
+                SemanticTokensFullDeltaResult::TokensDelta(SemanticTokensDelta {
+                    edits: semantic_tokens::diff_tokens(&prev.data, &tokens.data),
+                    result_id: tokens.result_id,
+                }),
+            )),
+            _ => Ok(Some(SemanticTokensFullDeltaResult::Tokens(tokens))),
+        }
+    }
+
+    async fn semantic_tokens_range(
+        &self,
+        params: SemanticTokensRangeParams,
+    ) -> Result<Option<SemanticTokensRangeResult>> {
+        let uri = &params.text_document.uri;
+        let (enabled, dialect) = {
+            let config = self.config.read().unwrap();
+            (config.features.semantic_tokens, config.dialect)
+        };
+        if !enabled {
+            return Ok(None);
+        }
         let docs = self.documents.read().unwrap();
         if let Some(text) = docs.get(uri) {
-            return Ok(Some(semantic_tokens::get_semantic_tokens(text)));
+            let tokens = semantic_tokens::get_semantic_tokens_range(text, params.range, dialect);
+            return Ok(Some(SemanticTokensRangeResult::Tokens(tokens)));
         }
         Ok(None)
     }
+
+    async fn formatting(&self, params: DocumentFormattingParams) -> Result<Option<Vec<TextEdit>>> {
+        let uri = &params.text_document.uri;
+        let config = self.config.read().unwrap();
+        if !config.features.formatting {
+            return Ok(None);
+        }
+        let docs = self.documents.read().unwrap();
+        if let Some(text) = docs.get(uri) {
+            let edits = formatting::format_edits(text, &config.format, config.dialect);
+            return Ok(Some(edits));
+        }
+        Ok(None)
+    }
+
+    async fn on_type_formatting(
+        &self,
+        params: DocumentOnTypeFormattingParams,
+    ) -> Result<Option<Vec<TextEdit>>> {
+        let uri = params.text_document_position.text_document.uri;
+        let pos = params.text_document_position.position;
+        let auto_number = self.config.read().unwrap().auto_number.clone();
+        if !auto_number.enabled || params.ch != "\n" {
+            return Ok(None);
+        }
+
+        let result = {
+            let docs = self.documents.read().unwrap();
+            match docs.get(&uri) {
+                Some(text) => auto_number::on_newline(text, pos, auto_number.step),
+                None => return Ok(None),
+            }
+        };
+
+        match result {
+            Some(AutoNumber::Edits(edits)) => Ok(Some(edits)),
+            Some(AutoNumber::NoRoom(after)) => {
+                // Offer a renumber without blocking the edit request
+                let client = self.client.clone();
+                let documents = Arc::clone(&self.documents);
+                let step = match auto_number.step {
+                    0 => 10,
+                    step => step,
+                };
+                tokio::spawn(async move {
+                    let action = MessageActionItem {
+                        title: "Renumber".to_string(),
+                        properties: HashMap::new(),
+                    };
+                    let choice = client
+                        .show_message_request(
+                            MessageType::INFO,
+                            format!("No free line number after {}. Renumber the program?", after),
+                            Some(vec![action]),
+                        )
+                        .await;
+                    if let Ok(Some(item)) = choice {
+                        if item.title == "Renumber" {
+                            if let Err(message) =
+                                apply_renumber(&client, &documents, uri, step, step).await
+                            {
+                                client.show_message(MessageType::ERROR, message).await;
+                            }
+                        }
+                    }
+                });
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn execute_command(&self, params: ExecuteCommandParams) -> Result<Option<Value>> {
+        if params.command == CONVERT_COMMAND {
+            return self.convert(params.arguments).await;
+        }
+        if params.command == RUN_COMMAND {
+            return self.run(params.arguments).await;
+        }
+        if params.command == TRACE_COMMAND {
+            return self.trace(params.arguments).await;
+        }
+        if params.command == TEST_COMMAND {
+            return self.test(params.arguments).await;
+        }
+        if params.command == RENDER_COMMAND {
+            return self.render(params.arguments).await;
+        }
+        if params.command == EXPORT_AUDIO_COMMAND {
+            return self.export_audio(params.arguments).await;
+        }
+        if params.command != RENUMBER_COMMAND {
+            return Ok(None);
+        }
+
+        let mut args = params.arguments.into_iter();
+        let uri = match args
+            .next()
+            .and_then(|v| serde_json::from_value::<Url>(v).ok())
+        {
+            Some(uri) => uri,
+            None => return Ok(None),
+        };
+        let mut number = |name: &str| match args.next().filter(|v| !v.is_null()) {
+            None => Ok(10),
+            Some(value) => value
+                .as_u64()
+                .filter(|n| (1..=renumber::MAX_LINE_NUMBER as u64).contains(n))
+                .map(|n| n as u32)
+                .ok_or_else(|| {
+                    Error::invalid_params(format!(
+                        "The renumber {} needs a number from 1 to {}",
+                        name,
+                        renumber::MAX_LINE_NUMBER
+                    ))
+                }),
+        };
+        let start = number("start")?;
+        let step = number("step")?;
+
+        apply_renumber(&self.client, &self.documents, uri, start, step)
+            .await
+            .map_err(Error::invalid_params)?;
+        Ok(None)
+    }
 }
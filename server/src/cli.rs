@@ -0,0 +1,814 @@
+use serde_json::{json, Value};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use tower_lsp::lsp_types::*;
+
+use crate::codes::{self, CHECKS};
+use crate::config::Config;
+use crate::dap;
+use crate::diagnostics;
+use crate::formatting;
+use crate::golden;
+use crate::renumber;
+use crate::tokenized;
+
+const USAGE: &str = "\
+Usage: basica-lsp                  Run the language server on stdin/stdout
+       basica-lsp check [options] <files or directories...>
+       basica-lsp fmt [options] [files or directories...]
+       basica-lsp renum [options] [files or directories...]
+       basica-lsp test [options] [files or directories...]
+       basica-lsp dap                     Run the debug adapter on stdin/stdout
+
+fmt and renum rewrite files in place, or stdin to stdout when given no
+files or `-`. test runs each PROG.BAS that has a PROG.out next to it,
+answering INPUT from PROG.in, and compares the output.
+
+Options:
+  --format <text|json|sarif>   check: output format (default: text)
+  --start <n>                  renum: first line number (default: 10)
+  --step <n>                   renum: increment (default: 10)
+  --check                      fmt, renum: only report files that would change
+  --dialect <name>             basica, gw-basic or ibm-basica (check, fmt)
+  --config <file>              JSON file with the `basica` settings object
+                               (check, fmt, test)";
+
+/// How `check` prints what it finds
+#[derive(Clone, Copy, PartialEq)]
+enum Format {
+    Text,
+    Json,
+    Sarif,
+}
+
+/// Command-line options shared by the subcommands
+struct Options {
+    format: Format,
+    start: u32,
+    step: u32,
+    check: bool,
+    config: Config,
+    paths: Vec<PathBuf>,
+}
+
+/// One file's results
+struct Report {
+    path: PathBuf,
+    source: String,
+    diagnostics: Vec<Diagnostic>,
+}
+
+/// Run a subcommand, or return `None` to start the language server
+pub fn run(args: &[String]) -> Option<i32> {
+    let (command, rest) = args.split_first()?;
+    let allowed: &[&str] = match command.as_str() {
+        "check" => &["--format", "--dialect", "--config"],
+        "fmt" => &["--check", "--dialect", "--config"],
+        "renum" => &["--start", "--step", "--check"],
+        "test" => &["--config"],
+        "dap" => return Some(dap::serve()),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            return Some(0);
+        }
+        _ => return Some(usage_error(&format!("unknown command '{}'", command))),
+    };
+    let options = match parse_options(rest, allowed) {
+        Ok(options) => options,
+        Err(message) => return Some(usage_error(&message)),
+    };
+
+    let code = match command.as_str() {
+        "check" => check(options),
+        "test" => test(options),
+        "fmt" => {
+            let (style, dialect) = (options.config.format.clone(), options.config.dialect);
+            rewrite(&options, ("reformat", "reformatted"), |source| {
+                Ok(apply_line_edits(
+                    source,
+                    formatting::format_edits(source, &style, dialect),
+                ))
+            })
+        }
+        _ => {
+            let (start, step) = (options.start, options.step);
+            rewrite(&options, ("renumber", "renumbered"), |source| {
+                renumber::renumber_edits(source, start, step)
+                    .map(|edits| apply_line_edits(source, edits))
+            })
+        }
+    };
+    Some(code)
+}
+
+fn usage_error(message: &str) -> i32 {
+    eprintln!("basica-lsp: {}\n\n{}", message, USAGE);
+    2
+}
+
+fn parse_options(args: &[String], allowed: &[&str]) -> Result<Options, String> {
+    let mut options = Options {
+        format: Format::Text,
+        start: 10,
+        step: 10,
+        check: false,
+        config: Config::default(),
+        paths: Vec::new(),
+    };
+    let mut dialect = None;
+    let mut config_path = None;
+
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg.len() > 1 && arg.starts_with('-') && !allowed.contains(&arg.as_str()) {
+            return Err(format!("unknown option '{}'", arg));
+        }
+        if arg == "--check" {
+            options.check = true;
+            continue;
+        }
+        if !arg.starts_with("--") {
+            options.paths.push(PathBuf::from(arg));
+            continue;
+        }
+
+        let value = args
+            .next()
+            .ok_or_else(|| format!("{} needs a value", arg))?;
+        let number = || {
+            value
+                .parse::<u32>()
+                .ok()
+                .filter(|n| (1..=65529).contains(n))
+                .ok_or_else(|| format!("{} needs a number from 1 to 65529", arg))
+        };
+        match arg.as_str() {
+            "--format" => {
+                options.format = match value.as_str() {
+                    "text" => Format::Text,
+                    "json" => Format::Json,
+                    "sarif" => Format::Sarif,
+                    _ => return Err(format!("unknown format '{}'", value)),
+                }
+            }
+            "--start" => options.start = number()?,
+            "--step" => options.step = number()?,
+            "--dialect" => dialect = Some(value.clone()),
+            _ => config_path = Some(value.clone()),
+        }
+    }
+
+    if let Some(path) = config_path {
+        let text = std::fs::read_to_string(&path).map_err(|e| format!("{}: {}", path, e))?;
+        options.config = serde_json::from_str(&text).map_err(|e| format!("{}: {}", path, e))?;
+    }
+    if let Some(name) = dialect {
+        options.config.dialect = serde_json::from_value(Value::String(name.clone()))
+            .map_err(|_| format!("unknown dialect '{}'", name))?;
+    }
+    Ok(options)
+}
+
+/// The files named on the command line, with directories expanded
+fn expand_paths(paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for path in paths {
+        if path.is_dir() {
+            collect_programs(path, &mut files);
+        } else {
+            files.push(path.clone());
+        }
+    }
+    files
+}
+
+/// Check programs with the rules the editor applies: exits 1 if any has
+/// errors, 2 if the arguments or files couldn't be read
+fn check(options: Options) -> i32 {
+    if options.paths.is_empty() {
+        return usage_error("no files to check");
+    }
+
+    let mut reports = Vec::new();
+    let mut failed = false;
+    for path in expand_paths(&options.paths) {
+        let source = match tokenized::read_program(&path) {
+            Ok(source) => source,
+            Err(message) => {
+                eprintln!("basica-lsp: {}", message);
+                failed = true;
+                continue;
+            }
+        };
+        let mut diagnostics = match file_uri(&path) {
+            Some(uri) => diagnostics::check(&source, &uri, &options.config),
+            None => Vec::new(),
+        };
+        diagnostics.sort_by_key(|d| (d.range.start.line, d.range.start.character));
+        reports.push(Report {
+            path,
+            source,
+            diagnostics,
+        });
+    }
+
+    match options.format {
+        Format::Text => print_text(&reports),
+        Format::Json => print_json(&json_report(&reports)),
+        Format::Sarif => print_json(&sarif_report(&reports)),
+    }
+
+    let errors = reports
+        .iter()
+        .flat_map(|r| &r.diagnostics)
+        .any(|d| d.severity == Some(DiagnosticSeverity::ERROR));
+    if failed {
+        2
+    } else if errors {
+        1
+    } else {
+        0
+    }
+}
+
+/// Run golden-output tests: exits 1 if any fails, 2 if there are none
+fn test(options: Options) -> i32 {
+    let mut paths = options.paths;
+    if paths.is_empty() {
+        paths.push(PathBuf::from("."));
+    }
+    let fixtures: Vec<golden::Fixture> = paths.iter().flat_map(|p| golden::discover(p)).collect();
+    if fixtures.is_empty() {
+        eprintln!("basica-lsp: no programs with expected output (PROG.BAS and PROG.out) found");
+        return 2;
+    }
+
+    let total = fixtures.len();
+    let mut failed = 0;
+    for fixture in fixtures {
+        let result = golden::run(fixture, &options.config.run);
+        let program = result.fixture.program.display();
+        if result.passed {
+            println!("PASS {}", program);
+            continue;
+        }
+        failed += 1;
+        println!("FAIL {}: {}", program, result.message.unwrap_or_default());
+        if let Some(mismatch) = result.mismatch {
+            println!(
+                "  expected: {}",
+                mismatch.expected.as_deref().unwrap_or("(end of output)")
+            );
+            println!(
+                "  actual:   {}",
+                mismatch.actual.as_deref().unwrap_or("(end of output)")
+            );
+        }
+    }
+
+    eprintln!(
+        "{} test{}: {} passed, {} failed",
+        total,
+        plural(total),
+        total - failed,
+        failed
+    );
+    if failed > 0 {
+        1
+    } else {
+        0
+    }
+}
+
+/// Rewrite programs in place, or stdin to stdout. With `--check`, only
+/// list what would change and exit 1 if anything would. A program the
+/// transform fails on is left as it is, and the exit code is 2
+fn rewrite(
+    options: &Options,
+    verb: (&str, &str),
+    transform: impl Fn(&str) -> Result<String, String>,
+) -> i32 {
+    let (verb, done) = verb;
+
+    if options.paths.is_empty() || options.paths == [PathBuf::from("-")] {
+        let mut source = String::new();
+        if let Err(e) = std::io::stdin().read_to_string(&mut source) {
+            eprintln!("basica-lsp: stdin: {}", e);
+            return 2;
+        }
+        let output = match transform(&source) {
+            Ok(output) => output,
+            Err(message) => {
+                eprintln!("basica-lsp: stdin: {}", message);
+                return 2;
+            }
+        };
+        if options.check {
+            if output != source {
+                eprintln!("would {} stdin", verb);
+                return 1;
+            }
+            return 0;
+        }
+        return match std::io::stdout().write_all(output.as_bytes()) {
+            Ok(()) => 0,
+            Err(_) => 2,
+        };
+    }
+
+    let mut changed = false;
+    let mut failed = false;
+    for path in expand_paths(&options.paths) {
+        match rewrite_file(&path, options.check, &transform) {
+            Ok(false) => {}
+            Ok(true) if options.check => {
+                println!("would {} {}", verb, path.display());
+                changed = true;
+            }
+            Ok(true) => println!("{} {}", done, path.display()),
+            Err(message) => {
+                eprintln!("basica-lsp: {}: {}", path.display(), message);
+                failed = true;
+            }
+        }
+    }
+
+    if failed {
+        2
+    } else if changed {
+        1
+    } else {
+        0
+    }
+}
+
+/// Rewrite one file, keeping it tokenized if it was. Returns whether the
+/// program changed
+fn rewrite_file(
+    path: &Path,
+    dry_run: bool,
+    transform: impl Fn(&str) -> Result<String, String>,
+) -> Result<bool, String> {
+    let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+    let is_tokenized = tokenized::is_tokenized(&bytes);
+    let source = if is_tokenized {
+        tokenized::detokenize(&bytes)?
+    } else {
+        // Writing back a lossy decoding would mangle code page 437 text
+        String::from_utf8(bytes).map_err(|_| "not UTF-8 text, leaving it alone".to_string())?
+    };
+
+    let output = transform(&source)?;
+    if output == source {
+        return Ok(false);
+    }
+    if !dry_run {
+        let bytes = if is_tokenized {
+            tokenized::tokenize(&output)?
+        } else {
+            output.into_bytes()
+        };
+        std::fs::write(path, bytes).map_err(|e| e.to_string())?;
+    }
+    Ok(true)
+}
+
+/// Apply whole-line edits from the formatter or renumberer, keeping each
+/// line's ending
+fn apply_line_edits(source: &str, edits: Vec<TextEdit>) -> String {
+    let mut edits = edits.into_iter().peekable();
+    let mut output = String::with_capacity(source.len());
+
+    for (idx, line) in source.split_inclusive('\n').enumerate() {
+        let content = line.trim_end_matches(['\r', '\n']);
+        match edits.next_if(|e| e.range.start.line as usize == idx) {
+            Some(edit) => {
+                output.push_str(&edit.new_text);
+                output.push_str(&line[content.len()..]);
+            }
+            None => output.push_str(line),
+        }
+    }
+
+    output
+}
+
+/// Find `.bas` files under a directory, in a stable order
+fn collect_programs(dir: &Path, files: &mut Vec<PathBuf>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let mut entries: Vec<PathBuf> = entries.filter_map(|e| e.ok().map(|e| e.path())).collect();
+    entries.sort();
+    for path in entries {
+        if path.is_dir() {
+            collect_programs(&path, files);
+        } else if path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("bas"))
+        {
+            files.push(path);
+        }
+    }
+}
+
+fn file_uri(path: &Path) -> Option<Url> {
+    let path = std::fs::canonicalize(path).ok()?;
+    Url::from_file_path(path).ok()
+}
+
+fn severity_name(severity: Option<DiagnosticSeverity>) -> &'static str {
+    match severity {
+        Some(DiagnosticSeverity::WARNING) => "warning",
+        Some(DiagnosticSeverity::INFORMATION) => "info",
+        Some(DiagnosticSeverity::HINT) => "hint",
+        _ => "error",
+    }
+}
+
+fn code_of(diagnostic: &Diagnostic) -> Option<&str> {
+    match &diagnostic.code {
+        Some(NumberOrString::String(code)) => Some(code),
+        _ => None,
+    }
+}
+
+/// `PROG.BAS:12:5: warning[B003]: message`, with the source line under it
+fn print_text(reports: &[Report]) {
+    let mut errors = 0;
+    let mut warnings = 0;
+
+    for report in reports {
+        let lines: Vec<&str> = report.source.lines().collect();
+        for d in &report.diagnostics {
+            let severity = severity_name(d.severity);
+            match severity {
+                "error" => errors += 1,
+                "warning" => warnings += 1,
+                _ => {}
+            }
+            let code = code_of(d).map(|c| format!("[{}]", c)).unwrap_or_default();
+            println!(
+                "{}:{}:{}: {}{}: {}",
+                report.path.display(),
+                d.range.start.line + 1,
+                d.range.start.character + 1,
+                severity,
+                code,
+                d.message
+            );
+            if let Some(line) = lines.get(d.range.start.line as usize) {
+                println!("    {}", line);
+                println!("    {}", caret(line, d.range));
+            }
+            for related in d.related_information.iter().flatten() {
+                let path = related
+                    .location
+                    .uri
+                    .to_file_path()
+                    .unwrap_or_else(|_| report.path.clone());
+                println!(
+                    "  {}:{}:{}: note: {}",
+                    path.display(),
+                    related.location.range.start.line + 1,
+                    related.location.range.start.character + 1,
+                    related.message
+                );
+            }
+        }
+    }
+
+    eprintln!(
+        "{} file{} checked: {} error{}, {} warning{}",
+        reports.len(),
+        plural(reports.len()),
+        errors,
+        plural(errors),
+        warnings,
+        plural(warnings)
+    );
+}
+
+/// Carets under the part of `line` a range covers, kept within the line
+/// since some checks mark to the end with a large column
+fn caret(line: &str, range: Range) -> String {
+    let width = line.chars().count();
+    let start = (range.start.character as usize).min(width);
+    let end = if range.end.line == range.start.line {
+        range.end.character as usize
+    } else {
+        width
+    };
+    let end = end.min(width).max(start + 1);
+    format!("{}{}", " ".repeat(start), "^".repeat(end - start))
+}
+
+fn plural(count: usize) -> &'static str {
+    if count == 1 {
+        ""
+    } else {
+        "s"
+    }
+}
+
+fn print_json(value: &Value) {
+    println!(
+        "{}",
+        serde_json::to_string_pretty(value).unwrap_or_default()
+    );
+}
+
+/// One object per diagnostic, with 1-based lines and columns
+fn json_report(reports: &[Report]) -> Value {
+    let position = |p: Position| json!({ "line": p.line + 1, "column": p.character + 1 });
+    let diagnostics: Vec<Value> = reports
+        .iter()
+        .flat_map(|report| {
+            report.diagnostics.iter().map(move |d| {
+                json!({
+                    "file": report.path.display().to_string(),
+                    "start": position(d.range.start),
+                    "end": position(d.range.end),
+                    "severity": severity_name(d.severity),
+                    "code": code_of(d),
+                    "name": codes::check_of(d).map(|c| c.name),
+                    "message": d.message,
+                })
+            })
+        })
+        .collect();
+    Value::Array(diagnostics)
+}
+
+/// A SARIF 2.1.0 log, which code scanning services can import
+fn sarif_report(reports: &[Report]) -> Value {
+    let region = |range: Range| {
+        json!({
+            "startLine": range.start.line + 1,
+            "startColumn": range.start.character + 1,
+            "endLine": range.end.line + 1,
+            "endColumn": range.end.character + 1,
+        })
+    };
+    let location = |path: &Path, range: Range| {
+        json!({
+            "physicalLocation": {
+                "artifactLocation": { "uri": sarif_path(path) },
+                "region": region(range),
+            }
+        })
+    };
+
+    let rules: Vec<Value> = CHECKS
+        .iter()
+        .map(|check| {
+            json!({
+                "id": check.id,
+                "name": check.name,
+                "helpUri": format!("{}{}.md", codes::ONLINE_DOCS, check.id),
+            })
+        })
+        .collect();
+
+    let mut results = Vec::new();
+    for report in reports {
+        for d in &report.diagnostics {
+            let level = match d.severity {
+                Some(DiagnosticSeverity::WARNING) => "warning",
+                Some(DiagnosticSeverity::INFORMATION) | Some(DiagnosticSeverity::HINT) => "note",
+                _ => "error",
+            };
+            let related: Vec<Value> = d
+                .related_information
+                .iter()
+                .flatten()
+                .map(|r| {
+                    let path = r
+                        .location
+                        .uri
+                        .to_file_path()
+                        .unwrap_or_else(|_| report.path.clone());
+                    let mut location = location(&path, r.location.range);
+                    location["message"] = json!({ "text": r.message });
+                    location
+                })
+                .collect();
+            let mut result = json!({
+                "level": level,
+                "message": { "text": d.message },
+                "locations": [location(&report.path, d.range)],
+            });
+            if let Some(code) = code_of(d) {
+                result["ruleId"] = json!(code);
+                if let Some(index) = CHECKS.iter().position(|c| c.id == code) {
+                    result["ruleIndex"] = json!(index);
+                }
+            }
+            if !related.is_empty() {
+                result["relatedLocations"] = Value::Array(related);
+            }
+            results.push(result);
+        }
+    }
+
+    json!({
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "version": "2.1.0",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "basica-lsp",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "informationUri": "https://github.com/larssg/basica-vscode",
+                    "rules": rules,
+                }
+            },
+            "results": results,
+        }]
+    })
+}
+
+/// SARIF wants forward-slash URIs, relative to the working directory when
+/// possible so results line up with the checkout
+fn sarif_path(path: &Path) -> String {
+    let relative = std::env::current_dir()
+        .ok()
+        .and_then(|cwd| {
+            let absolute = std::fs::canonicalize(path).ok()?;
+            absolute.strip_prefix(cwd).ok().map(Path::to_path_buf)
+        })
+        .unwrap_or_else(|| path.to_path_buf());
+    relative.to_string_lossy().replace('\\', "/")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: u32, end_line: u32, end: u32) -> Range {
+        Range {
+            start: Position {
+                line: 0,
+                character: start,
+            },
+            end: Position {
+                line: end_line,
+                character: end,
+            },
+        }
+    }
+
+    fn report(source: &str) -> Report {
+        let path = PathBuf::from("PROG.BAS");
+        let uri = Url::parse("file:///PROG.BAS").unwrap();
+        let diagnostics = diagnostics::check(source, &uri, &Config::default());
+        Report {
+            path,
+            source: source.to_string(),
+            diagnostics,
+        }
+    }
+
+    #[test]
+    fn carets_stay_within_the_line() {
+        assert_eq!(caret("10 GOTO 99", range(8, 0, 10)), "        ^^");
+        assert_eq!(caret("10 GOTO 99", range(3, 0, 1000)), "   ^^^^^^^");
+        assert_eq!(caret("10 END", range(3, 2, 0)), "   ^^^");
+        assert_eq!(caret("10", range(40, 0, 41)), "  ^");
+    }
+
+    #[test]
+    fn json_has_one_based_positions_and_names() {
+        let value = json_report(&[report("10 GOTO 99\n")]);
+        let first = &value[0];
+        assert_eq!(first["file"], "PROG.BAS");
+        assert_eq!(first["start"], json!({ "line": 1, "column": 9 }));
+        assert_eq!(first["code"], "B001");
+        assert_eq!(first["name"], "undefined-line");
+        assert_eq!(first["severity"], "error");
+    }
+
+    #[test]
+    fn sarif_results_point_at_their_rules() {
+        let value = sarif_report(&[report(
+            "10 OPEN \"A\" FOR INPUT AS #1\n20 PRINT #1, 1\n30 END\n",
+        )]);
+        let run = &value["runs"][0];
+        assert_eq!(
+            run["tool"]["driver"]["rules"].as_array().unwrap().len(),
+            CHECKS.len()
+        );
+        let result = run["results"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|r| r["ruleId"] == "B019")
+            .unwrap();
+        let index = result["ruleIndex"].as_u64().unwrap() as usize;
+        assert_eq!(CHECKS[index].id, "B019");
+        assert_eq!(result["level"], "warning");
+        assert_eq!(
+            result["relatedLocations"][0]["message"]["text"],
+            "Opened here"
+        );
+    }
+
+    #[test]
+    fn check_options_are_validated() {
+        let args = |list: &[&str]| list.iter().map(|a| a.to_string()).collect::<Vec<_>>();
+        let allowed = &["--format", "--dialect", "--config"];
+        let options = parse_options(&args(&["--format", "sarif", "A.BAS"]), allowed).unwrap();
+        assert!(options.format == Format::Sarif);
+        assert_eq!(options.paths, [PathBuf::from("A.BAS")]);
+        assert!(parse_options(&args(&["--format", "xml"]), allowed).is_err());
+        assert!(parse_options(&args(&["--dialect", "qbasic"]), allowed).is_err());
+        assert!(parse_options(&args(&["--step", "5"]), allowed).is_err());
+    }
+
+    /// A scratch file holding `bytes`, unique to this test
+    fn scratch(name: &str, bytes: &[u8]) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("basica-cli-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    fn renum(path: &Path, extra: &[&str]) -> i32 {
+        let mut args = vec!["renum".to_string()];
+        args.extend(extra.iter().map(|a| a.to_string()));
+        args.push(path.display().to_string());
+        run(&args).unwrap()
+    }
+
+    #[test]
+    fn line_edits_keep_line_endings() {
+        let source = "5 PRINT\r\n7 GOTO 5\r\n8 END";
+        let edits = renumber::renumber_edits(source, 10, 10).unwrap();
+        assert_eq!(
+            apply_line_edits(source, edits),
+            "10 PRINT\r\n20 GOTO 10\r\n30 END"
+        );
+    }
+
+    #[test]
+    fn renum_rewrites_files_in_place() {
+        let path = scratch("PLAIN.BAS", b"5 GOTO 7\n7 END\n");
+        assert_eq!(renum(&path, &["--check"]), 1);
+        assert_eq!(std::fs::read(&path).unwrap(), b"5 GOTO 7\n7 END\n");
+        assert_eq!(renum(&path, &["--start", "100"]), 0);
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "100 GOTO 110\n110 END\n"
+        );
+        assert_eq!(renum(&path, &["--start", "100", "--check"]), 0);
+    }
+
+    #[test]
+    fn renum_keeps_tokenized_files_tokenized() {
+        let bytes = tokenized::tokenize("1 GOTO 2\n2 END\n").unwrap();
+        let path = scratch("TOKEN.BAS", &bytes);
+        assert_eq!(renum(&path, &[]), 0);
+        let bytes = std::fs::read(&path).unwrap();
+        assert!(tokenized::is_tokenized(&bytes));
+        assert_eq!(
+            tokenized::detokenize(&bytes).unwrap(),
+            "10 GOTO 20\n20 END\n"
+        );
+    }
+
+    #[test]
+    fn renum_failures_leave_files_alone() {
+        let path = scratch("FULL.BAS", b"1 PRINT\n2 PRINT\n");
+        assert_eq!(renum(&path, &["--start", "65529"]), 2);
+        assert_eq!(std::fs::read(&path).unwrap(), b"1 PRINT\n2 PRINT\n");
+
+        let path = scratch("CP437.BAS", b"1 PRINT \"\x82\"\n");
+        assert_eq!(renum(&path, &[]), 2);
+        assert_eq!(std::fs::read(&path).unwrap(), b"1 PRINT \"\x82\"\n");
+    }
+
+    #[test]
+    fn renum_options_are_in_range() {
+        let path = scratch("RANGE.BAS", b"1 END\n");
+        assert_eq!(renum(&path, &["--step", "0"]), 2);
+        assert_eq!(renum(&path, &["--start", "65530"]), 2);
+    }
+
+    #[test]
+    fn fmt_uppercases_keywords() {
+        let path = scratch("FMT.BAS", b"10 print \"hi\"\r\n");
+        let args = |extra: &str| {
+            vec![
+                "fmt".to_string(),
+                extra.to_string(),
+                path.display().to_string(),
+            ]
+        };
+        assert_eq!(run(&args("--check")).unwrap(), 1);
+        let mut args = args("--check");
+        args.remove(1);
+        assert_eq!(run(&args).unwrap(), 0);
+        assert_eq!(std::fs::read(&path).unwrap(), b"10 PRINT \"hi\"\r\n");
+    }
+}
@@ -0,0 +1,214 @@
+use std::path::PathBuf;
+use std::sync::OnceLock;
+use tower_lsp::lsp_types::*;
+
+/// A class of diagnostic, with a stable id and a name for settings and
+/// suppressions
+pub struct Check {
+    pub id: &'static str,
+    pub name: &'static str,
+}
+
+pub const CHECKS: &[Check] = &[
+    Check {
+        id: "B001",
+        name: "undefined-line",
+    },
+    Check {
+        id: "B002",
+        name: "undefined-variable",
+    },
+    Check {
+        id: "B003",
+        name: "unused-variable",
+    },
+    Check {
+        id: "B004",
+        name: "unreachable-code",
+    },
+    Check {
+        id: "B005",
+        name: "syntax-error",
+    },
+    Check {
+        id: "B006",
+        name: "string-syntax",
+    },
+    Check {
+        id: "B007",
+        name: "hardware-range",
+    },
+    Check {
+        id: "B008",
+        name: "file-not-open",
+    },
+    Check {
+        id: "B009",
+        name: "handler-fallthrough",
+    },
+    Check {
+        id: "B010",
+        name: "dialect",
+    },
+    Check {
+        id: "B011",
+        name: "unused-suppression",
+    },
+    Check {
+        id: "B012",
+        name: "runtime-error",
+    },
+    Check {
+        id: "B013",
+        name: "not-executed",
+    },
+    Check {
+        id: "B014",
+        name: "golden-output",
+    },
+    Check {
+        id: "B015",
+        name: "color-range",
+    },
+    Check {
+        id: "B016",
+        name: "locate-range",
+    },
+    Check {
+        id: "B017",
+        name: "text-mode-graphics",
+    },
+    Check {
+        id: "B018",
+        name: "file-already-open",
+    },
+    Check {
+        id: "B019",
+        name: "file-mode",
+    },
+    Check {
+        id: "B020",
+        name: "file-left-open",
+    },
+    Check {
+        id: "B021",
+        name: "handler-without-resume",
+    },
+    Check {
+        id: "B022",
+        name: "outside-handler",
+    },
+    Check {
+        id: "B023",
+        name: "erl-line",
+    },
+];
+
+/// Published docs, for when the server runs without the extension
+pub const ONLINE_DOCS: &str = "https://github.com/larssg/basica-vscode/blob/main/docs/diagnostics/";
+
+/// Look up a check by id (`B001`) or name (`undefined-line`)
+pub fn find(code: &str) -> Option<&'static Check> {
+    CHECKS
+        .iter()
+        .find(|c| c.id.eq_ignore_ascii_case(code) || c.name.eq_ignore_ascii_case(code))
+}
+
+impl Check {
+    /// Tag a diagnostic with this check's code and a link to its docs
+    pub fn tag(&self, diagnostic: &mut Diagnostic) {
+        diagnostic.code = Some(NumberOrString::String(self.id.to_string()));
+        diagnostic.code_description = self.docs().map(|href| CodeDescription { href });
+    }
+
+    /// The markdown page describing this check
+    pub fn docs(&self) -> Option<Url> {
+        let file = format!("{}.md", self.id);
+        match bundled_docs() {
+            Some(dir) => Url::from_file_path(dir.join(file)).ok(),
+            None => Url::parse(ONLINE_DOCS).ok()?.join(&file).ok(),
+        }
+    }
+}
+
+/// The check a diagnostic was tagged with
+pub fn check_of(diagnostic: &Diagnostic) -> Option<&'static Check> {
+    match &diagnostic.code {
+        Some(NumberOrString::String(code)) => find(code),
+        _ => None,
+    }
+}
+
+/// The docs shipped with the extension, which bundles the server as
+/// `server/basica-lsp` next to `docs/diagnostics`
+fn bundled_docs() -> Option<&'static PathBuf> {
+    static DOCS: OnceLock<Option<PathBuf>> = OnceLock::new();
+    DOCS.get_or_init(|| {
+        let exe = std::env::current_exe().ok()?;
+        let dir = exe.parent()?.parent()?.join("docs").join("diagnostics");
+        dir.is_dir().then_some(dir)
+    })
+    .as_ref()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+    use std::path::Path;
+
+    #[test]
+    fn ids_and_names_are_unique() {
+        let ids: HashSet<&str> = CHECKS.iter().map(|c| c.id).collect();
+        let names: HashSet<&str> = CHECKS.iter().map(|c| c.name).collect();
+        assert_eq!(ids.len(), CHECKS.len());
+        assert_eq!(names.len(), CHECKS.len());
+    }
+
+    #[test]
+    fn checks_are_found_by_id_or_name() {
+        assert_eq!(find("b019").map(|c| c.name), Some("file-mode"));
+        assert_eq!(find("Outside-Handler").map(|c| c.id), Some("B022"));
+        assert!(find("file-io").is_none());
+    }
+
+    #[test]
+    fn every_check_has_a_docs_page() {
+        let docs = Path::new(env!("CARGO_MANIFEST_DIR")).join("../docs/diagnostics");
+        for check in CHECKS {
+            let page = std::fs::read_to_string(docs.join(format!("{}.md", check.id)))
+                .unwrap_or_else(|_| panic!("missing docs page for {}", check.id));
+            let title = format!("# {} {}", check.id, check.name);
+            assert!(page.starts_with(&title), "{} has the wrong title", check.id);
+        }
+    }
+
+    #[test]
+    fn each_warning_class_has_its_own_code() {
+        let uri = Url::parse("file:///test.bas").unwrap();
+        let source = "10 SCREEN 0: COLOR 40: LOCATE 30: PSET (1, 1)\n\
+            20 OPEN \"A\" FOR INPUT AS #1: OPEN \"B\" FOR INPUT AS #1\n\
+            30 PRINT #1, 1: PRINT #2, 1: PRINT ERR\n\
+            40 END\n";
+        let mut diagnostics = crate::hardware::check(source);
+        diagnostics.extend(crate::files::check(source, &uri));
+        diagnostics.extend(crate::error_handlers::check(source, &uri));
+        let names: Vec<&str> = diagnostics
+            .iter()
+            .filter_map(|d| check_of(d).map(|c| c.name))
+            .collect();
+        assert_eq!(
+            names,
+            [
+                "color-range",
+                "locate-range",
+                "text-mode-graphics",
+                "file-already-open",
+                "file-mode",
+                "file-not-open",
+                "file-left-open",
+                "outside-handler",
+            ]
+        );
+    }
+}
@@ -1,12 +1,14 @@
 use std::collections::HashSet;
 use tower_lsp::lsp_types::*;
 
+use crate::dialect::Dialect;
+
 /// Get completion items at the cursor position
-pub fn get_completions(source: &str, _position: Position) -> Vec<CompletionItem> {
+pub fn get_completions(source: &str, _position: Position, dialect: Dialect) -> Vec<CompletionItem> {
     let mut items = Vec::new();
 
     // Add keywords
-    for (keyword, detail) in KEYWORDS {
+    for (keyword, detail) in KEYWORDS.iter().filter(|(k, _)| dialect.supports(k)) {
         items.push(CompletionItem {
             label: keyword.to_string(),
             kind: Some(CompletionItemKind::KEYWORD),
@@ -16,7 +18,7 @@ pub fn get_completions(source: &str, _position: Position) -> Vec<CompletionItem>
     }
 
     // Add built-in functions
-    for (func, detail) in FUNCTIONS {
+    for (func, detail) in FUNCTIONS.iter().filter(|(f, _)| dialect.supports(f)) {
         items.push(CompletionItem {
             label: func.to_string(),
             kind: Some(CompletionItemKind::FUNCTION),
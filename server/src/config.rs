@@ -0,0 +1,212 @@
+use serde::Deserialize;
+use std::collections::HashMap;
+use tower_lsp::lsp_types::DiagnosticSeverity;
+
+use crate::codes::Check;
+use crate::dialect::Dialect;
+
+/// Server settings: the `basica` section of the client's configuration,
+/// sent as `initializationOptions` and pulled again when it changes
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Config {
+    pub auto_number: AutoNumberConfig,
+    /// BASIC dialect that decides the reserved words
+    pub dialect: Dialect,
+    pub diagnostics: DiagnosticsConfig,
+    pub format: FormatConfig,
+    pub run: RunConfig,
+    pub features: FeaturesConfig,
+}
+
+/// Settings for inserting line numbers on Enter (like the AUTO command)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct AutoNumberConfig {
+    pub enabled: bool,
+    /// Increment between lines; 0 means detect it from the program
+    pub step: u32,
+}
+
+impl Default for AutoNumberConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            step: 0,
+        }
+    }
+}
+
+/// Settings for published diagnostics
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct DiagnosticsConfig {
+    /// Severity overrides, keyed by check name (`unused-variable`) or id (`B003`)
+    pub severity: HashMap<String, Severity>,
+}
+
+impl DiagnosticsConfig {
+    /// The override for a check, given by name or id
+    pub fn severity_of(&self, check: &Check) -> Option<Severity> {
+        self.severity
+            .get(check.name)
+            .or_else(|| self.severity.get(check.id))
+            .copied()
+    }
+}
+
+/// A severity override for one check
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Error,
+    Warning,
+    Information,
+    Hint,
+    /// Don't report the check at all
+    Off,
+}
+
+impl Severity {
+    /// The LSP severity, or `None` when the check is turned off
+    pub fn to_lsp(self) -> Option<DiagnosticSeverity> {
+        match self {
+            Severity::Error => Some(DiagnosticSeverity::ERROR),
+            Severity::Warning => Some(DiagnosticSeverity::WARNING),
+            Severity::Information => Some(DiagnosticSeverity::INFORMATION),
+            Severity::Hint => Some(DiagnosticSeverity::HINT),
+            Severity::Off => None,
+        }
+    }
+}
+
+/// Settings for document formatting
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct FormatConfig {
+    pub keyword_case: KeywordCase,
+    pub operator_spacing: OperatorSpacing,
+}
+
+/// How to write reserved words
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeywordCase {
+    /// `PRINT`, as LIST shows them
+    #[default]
+    Upper,
+    Lower,
+    Preserve,
+}
+
+/// Whether to put spaces around binary operators
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OperatorSpacing {
+    /// Keep the spacing as written
+    #[default]
+    Preserve,
+    /// `A=B+1`
+    Compact,
+    /// `A = B + 1`
+    Spaced,
+}
+
+/// Limits on programs run from the editor
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct RunConfig {
+    /// Seconds before a run is stopped
+    pub timeout: u64,
+    /// Statements executed before a run is stopped
+    pub max_steps: u64,
+}
+
+impl Default for RunConfig {
+    fn default() -> Self {
+        Self {
+            timeout: 10,
+            max_steps: 10_000_000,
+        }
+    }
+}
+
+/// Which language features the server answers
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct FeaturesConfig {
+    pub diagnostics: bool,
+    pub hover: bool,
+    pub completion: bool,
+    pub signature_help: bool,
+    pub semantic_tokens: bool,
+    pub folding: bool,
+    pub formatting: bool,
+    pub code_lens: bool,
+}
+
+impl Default for FeaturesConfig {
+    fn default() -> Self {
+        Self {
+            diagnostics: true,
+            hover: true,
+            completion: true,
+            signature_help: true,
+            semantic_tokens: true,
+            folding: true,
+            formatting: true,
+            code_lens: true,
+        }
+    }
+}
+
+impl Config {
+    /// Build a config from the client's JSON settings; missing settings take
+    /// their defaults, as does a `null` section, but a setting of the wrong
+    /// type is an error
+    pub fn from_value(value: serde_json::Value) -> Result<Self, String> {
+        if value.is_null() {
+            return Ok(Config::default());
+        }
+        serde_json::from_value(value).map_err(|e| format!("Invalid basica settings: {}", e))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn missing_settings_take_their_defaults() {
+        let config = Config::from_value(json!({ "dialect": "gw-basic" })).unwrap();
+        assert_eq!(config.dialect, Dialect::GwBasic);
+        assert!(config.auto_number.enabled);
+        assert_eq!(config.run.timeout, 10);
+        assert!(config.features.hover);
+
+        let config = Config::from_value(serde_json::Value::Null).unwrap();
+        assert_eq!(config.dialect, Dialect::Basica);
+        assert_eq!(config.format.keyword_case, KeywordCase::Upper);
+    }
+
+    #[test]
+    fn wrongly_typed_settings_are_errors() {
+        let error = Config::from_value(json!({ "autoNumber": { "step": "ten" } })).unwrap_err();
+        assert!(error.starts_with("Invalid basica settings"));
+        assert!(Config::from_value(json!({ "dialect": "qbasic" })).is_err());
+    }
+
+    #[test]
+    fn severities_are_found_by_name_or_id() {
+        let config = Config::from_value(json!({
+            "diagnostics": { "severity": { "unused-variable": "off", "B001": "hint" } }
+        }))
+        .unwrap();
+        let severity =
+            |name| crate::codes::find(name).and_then(|check| config.diagnostics.severity_of(check));
+        assert_eq!(severity("unused-variable"), Some(Severity::Off));
+        assert_eq!(severity("B001"), Some(Severity::Hint));
+        assert_eq!(Severity::Off.to_lsp(), None);
+    }
+}
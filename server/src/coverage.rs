@@ -0,0 +1,138 @@
+use tower_lsp::lsp_types::*;
+
+use crate::codes;
+use crate::renumber::{leading_line_number, utf16_len};
+use crate::runner::Hits;
+
+/// BASIC line numbers in the order they appear
+pub fn numbered_lines(source: &str) -> Vec<u32> {
+    source
+        .lines()
+        .filter_map(|line| leading_line_number(line).map(|(num, _, _)| num))
+        .collect()
+}
+
+/// An "executed 1,204×" lens above every line that ran
+pub fn code_lenses(source: &str, hits: &Hits) -> Vec<CodeLens> {
+    let mut lenses = Vec::new();
+
+    for (line_idx, line) in source.lines().enumerate() {
+        let Some((num, start, end)) = leading_line_number(line) else {
+            continue;
+        };
+        let Some(&count) = hits.get(&num) else {
+            continue;
+        };
+        lenses.push(CodeLens {
+            range: Range {
+                start: Position {
+                    line: line_idx as u32,
+                    character: start as u32,
+                },
+                end: Position {
+                    line: line_idx as u32,
+                    character: end as u32,
+                },
+            },
+            // A lens without a command id shows as plain text
+            command: Some(Command {
+                title: format!("executed {}×", group_digits(count)),
+                command: String::new(),
+                arguments: None,
+            }),
+            data: None,
+        });
+    }
+
+    lenses
+}
+
+/// An information diagnostic on every line the trace never reached
+pub fn check(source: &str, hits: &Hits) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    for (line_idx, line) in source.lines().enumerate() {
+        let Some((num, start, _)) = leading_line_number(line) else {
+            continue;
+        };
+        if hits.contains_key(&num) {
+            continue;
+        }
+        let mut diagnostic = Diagnostic {
+            range: Range {
+                start: Position {
+                    line: line_idx as u32,
+                    character: start as u32,
+                },
+                end: Position {
+                    line: line_idx as u32,
+                    character: utf16_len(line.trim_end()),
+                },
+            },
+            severity: Some(DiagnosticSeverity::INFORMATION),
+            source: Some("basica".to_string()),
+            message: format!("Line {} never ran in the last trace", num),
+            ..Default::default()
+        };
+        if let Some(check) = codes::find("not-executed") {
+            check.tag(&mut diagnostic);
+        }
+        diagnostics.push(diagnostic);
+    }
+
+    diagnostics
+}
+
+/// `1204` as `1,204`
+fn group_digits(count: u64) -> String {
+    let digits = count.to_string();
+    let mut out = String::new();
+    for (idx, c) in digits.chars().enumerate() {
+        if idx > 0 && (digits.len() - idx).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lenses_count_each_line_that_ran() {
+        let hits = Hits::from([(10, 1204), (30, 1)]);
+        let titles: Vec<(u32, String)> = code_lenses("10 A\n20 B\n30 C\n", &hits)
+            .into_iter()
+            .map(|lens| (lens.range.start.line, lens.command.unwrap().title))
+            .collect();
+        assert_eq!(
+            titles,
+            [
+                (0, "executed 1,204×".to_string()),
+                (2, "executed 1×".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn lines_that_never_ran_are_marked() {
+        let hits = Hits::from([(10, 1)]);
+        let diagnostics = check("10 A\n20 PRINT \"é\"  \n", &hits);
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(
+            diagnostics[0].message,
+            "Line 20 never ran in the last trace"
+        );
+        assert_eq!(diagnostics[0].range.end.character, 12);
+        assert_eq!(numbered_lines("10 A\nREM\n20 B"), [10, 20]);
+    }
+
+    #[test]
+    fn digits_are_grouped_in_threes() {
+        assert_eq!(group_digits(0), "0");
+        assert_eq!(group_digits(999), "999");
+        assert_eq!(group_digits(1_234_567), "1,234,567");
+    }
+}
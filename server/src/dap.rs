@@ -0,0 +1,613 @@
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, TryRecvError};
+use std::sync::{Arc, Mutex};
+
+use crate::renumber::leading_line_number;
+use crate::runner::{Debuggee, Outcome};
+use crate::tokenized;
+
+/// BASIC programs run on a single thread
+const THREAD_ID: i64 = 1;
+
+/// Statements to run between looks for new requests
+const BATCH: usize = 1000;
+
+/// `variablesReference` of the scalar variables scope
+const VARIABLES: i64 = 1;
+
+/// `variablesReference` of the arrays scope; each array gets
+/// `FIRST_ARRAY` plus its index
+const ARRAYS: i64 = 2;
+const FIRST_ARRAY: i64 = 3;
+
+/// Serve the Debug Adapter Protocol on stdin/stdout until the client
+/// disconnects
+pub fn serve() -> i32 {
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || {
+        let mut reader = BufReader::new(std::io::stdin().lock());
+        while let Some(message) = read_message(&mut reader) {
+            if sender.send(message).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut session = Session::new();
+    loop {
+        // While the program runs, only look for requests between batches
+        let message = if session.resume.is_some() {
+            match receiver.try_recv() {
+                Ok(message) => Some(message),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => return 0,
+            }
+        } else {
+            match receiver.recv() {
+                Ok(message) => Some(message),
+                Err(_) => return 0,
+            }
+        };
+
+        match message {
+            Some(message) => {
+                if !session.handle(&message) {
+                    return 0;
+                }
+            }
+            None => session.advance(),
+        }
+    }
+}
+
+/// Read one `Content-Length` framed message
+fn read_message(reader: &mut impl BufRead) -> Option<Value> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).ok()? == 0 {
+            return None;
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = value.trim().parse().ok();
+        }
+    }
+    let mut body = vec![0; length?];
+    reader.read_exact(&mut body).ok()?;
+    serde_json::from_slice(&body).ok()
+}
+
+/// Writes numbered messages to stdout; shared with the program's output
+#[derive(Clone)]
+struct Connection {
+    seq: Arc<Mutex<i64>>,
+}
+
+impl Connection {
+    fn send(&self, mut message: Value) {
+        // Hold the counter while writing so messages go out in order
+        let mut seq = self.seq.lock().unwrap();
+        *seq += 1;
+        message["seq"] = json!(*seq);
+        let body = message.to_string();
+        let mut stdout = std::io::stdout().lock();
+        let _ = write!(stdout, "Content-Length: {}\r\n\r\n{}", body.len(), body);
+        let _ = stdout.flush();
+    }
+
+    fn event(&self, event: &str, body: Value) {
+        self.send(json!({ "type": "event", "event": event, "body": body }));
+    }
+}
+
+/// How far to run before stopping again
+#[derive(Clone, Copy)]
+enum Resume {
+    Continue,
+    /// Until another line at the same GOSUB level or an outer one
+    Over {
+        line: Option<u32>,
+        depth: usize,
+    },
+    /// Until another line, or into a GOSUB
+    In {
+        line: Option<u32>,
+        depth: usize,
+    },
+    /// Until a RETURN leaves the current level
+    Out {
+        depth: usize,
+    },
+}
+
+struct Breakpoint {
+    id: i64,
+    condition: Option<String>,
+}
+
+struct Session {
+    connection: Connection,
+    /// 1 unless the client counts lines from 0
+    line_base: u32,
+    path: PathBuf,
+    /// Source line (0-based) of each BASIC line number
+    source_lines: HashMap<u32, u32>,
+    /// Breakpoints by BASIC line number
+    breakpoints: HashMap<u32, Breakpoint>,
+    next_breakpoint: i64,
+    debuggee: Option<Debuggee>,
+    stop_on_entry: bool,
+    no_debug: bool,
+    /// How the program is running, or `None` while it's stopped
+    resume: Option<Resume>,
+    pause: bool,
+    /// The runtime error the program stopped with; resuming ends it
+    error: Option<String>,
+}
+
+impl Session {
+    fn new() -> Self {
+        Self {
+            connection: Connection {
+                seq: Arc::new(Mutex::new(0)),
+            },
+            line_base: 1,
+            path: PathBuf::new(),
+            source_lines: HashMap::new(),
+            breakpoints: HashMap::new(),
+            next_breakpoint: 1,
+            debuggee: None,
+            stop_on_entry: false,
+            no_debug: false,
+            resume: None,
+            pause: false,
+            error: None,
+        }
+    }
+
+    /// Answer a request, returning false once the client disconnects
+    fn handle(&mut self, request: &Value) -> bool {
+        if request["type"] != "request" {
+            return true;
+        }
+        let command = request["command"].as_str().unwrap_or_default();
+        let args = &request["arguments"];
+
+        let result = match command {
+            "initialize" => {
+                if args["linesStartAt1"] == false {
+                    self.line_base = 0;
+                }
+                self.respond(request, Ok(capabilities()));
+                return true;
+            }
+            "launch" => {
+                let result = self.launch(args);
+                let launched = result.is_ok();
+                self.respond(request, result);
+                // Breakpoints come after `initialized`, and need the
+                // program's line numbers to land on
+                if launched {
+                    self.connection.event("initialized", json!({}));
+                }
+                return true;
+            }
+            "setBreakpoints" => Ok(self.set_breakpoints(args)),
+            "setExceptionBreakpoints" => Ok(json!({})),
+            "configurationDone" => {
+                self.respond(request, Ok(json!({})));
+                self.start();
+                return true;
+            }
+            "threads" => Ok(json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })),
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => Ok(json!({ "scopes": [
+                { "name": "Variables", "variablesReference": VARIABLES, "expensive": false },
+                { "name": "Arrays", "variablesReference": ARRAYS, "expensive": false },
+            ] })),
+            "variables" => Ok(self.variables(args)),
+            "evaluate" => self.evaluate(args),
+            "continue" | "next" | "stepIn" | "stepOut" => {
+                self.respond(request, Ok(json!({ "allThreadsContinued": true })));
+                self.resume(command);
+                return true;
+            }
+            "pause" => {
+                self.pause = true;
+                Ok(json!({}))
+            }
+            "terminate" => {
+                self.respond(request, Ok(json!({})));
+                self.end(None);
+                return true;
+            }
+            "disconnect" => {
+                self.respond(request, Ok(json!({})));
+                return false;
+            }
+            _ => Err(format!("{} is not supported", command)),
+        };
+        self.respond(request, result);
+        true
+    }
+
+    fn respond(&self, request: &Value, result: Result<Value, String>) {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.connection.send(response);
+    }
+
+    /// Load the program; it starts once configuration is done
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let program = args["program"]
+            .as_str()
+            .ok_or("No program to debug: set `program` in the launch configuration")?;
+        self.path = PathBuf::from(program);
+        let source = tokenized::read_program(&self.path)?;
+        self.source_lines = source
+            .lines()
+            .enumerate()
+            .filter_map(|(idx, line)| Some((leading_line_number(line)?.0, idx as u32)))
+            .collect();
+        self.stop_on_entry = args["stopOnEntry"] == true;
+        self.no_debug = args["noDebug"] == true;
+
+        let connection = self.connection.clone();
+        let output = Box::new(move |text: &str| {
+            connection.event("output", json!({ "category": "stdout", "output": text }));
+        });
+        self.debuggee = Some(Debuggee::new(&source, output)?);
+        Ok(json!({}))
+    }
+
+    /// Replace the breakpoints, moving each to the numbered line it covers
+    fn set_breakpoints(&mut self, args: &Value) -> Value {
+        self.breakpoints.clear();
+        let mut numbered: Vec<(u32, u32)> = self
+            .source_lines
+            .iter()
+            .map(|(&number, &line)| (line, number))
+            .collect();
+        numbered.sort();
+
+        let mut results = Vec::new();
+        for requested in args["breakpoints"].as_array().into_iter().flatten() {
+            let line = requested["line"].as_u64().unwrap_or(0) as u32;
+            let line = line.saturating_sub(self.line_base);
+            // A line without a number belongs to the next numbered one
+            let Some(&(source_line, number)) = numbered.iter().find(|(l, _)| *l >= line) else {
+                results.push(json!({
+                    "verified": false,
+                    "message": "No BASIC line here",
+                }));
+                continue;
+            };
+
+            let id = self.next_breakpoint;
+            self.next_breakpoint += 1;
+            let condition = requested["condition"]
+                .as_str()
+                .filter(|c| !c.trim().is_empty())
+                .map(str::to_string);
+            self.breakpoints
+                .insert(number, Breakpoint { id, condition });
+            results.push(json!({
+                "id": id,
+                "verified": true,
+                "line": source_line + self.line_base,
+            }));
+        }
+        json!({ "breakpoints": results })
+    }
+
+    fn start(&mut self) {
+        if self.debuggee.is_none() {
+            return;
+        }
+        if self.stop_on_entry {
+            self.stop("entry", None);
+        } else if !self.hit_breakpoint() {
+            self.resume = Some(Resume::Continue);
+        }
+    }
+
+    fn resume(&mut self, command: &str) {
+        if self.error.is_some() {
+            // Nothing runs after a runtime error
+            let error = self.error.take();
+            self.end(error);
+            return;
+        }
+        let Some(debuggee) = &self.debuggee else {
+            return;
+        };
+        let (line, depth) = (debuggee.line(), debuggee.depth());
+        self.resume = Some(match command {
+            "next" => Resume::Over { line, depth },
+            "stepIn" => Resume::In { line, depth },
+            "stepOut" => Resume::Out { depth },
+            _ => Resume::Continue,
+        });
+        self.pause = false;
+    }
+
+    /// Run a batch of statements, stopping where the resume mode, a
+    /// breakpoint or a pause request says to
+    fn advance(&mut self) {
+        for _ in 0..BATCH {
+            let (Some(resume), Some(debuggee)) = (self.resume, self.debuggee.as_mut()) else {
+                return;
+            };
+            if let Some(outcome) = debuggee.step() {
+                self.finish(outcome);
+                return;
+            }
+
+            let (line, depth) = (debuggee.line(), debuggee.depth());
+            // A jump to the line already running enters it again
+            let entered = debuggee.line_started();
+
+            if self.pause {
+                self.pause = false;
+                self.stop("pause", None);
+                return;
+            }
+            if entered && self.hit_breakpoint() {
+                return;
+            }
+            let done = match resume {
+                Resume::Continue => false,
+                Resume::Over {
+                    line: from,
+                    depth: d,
+                } => (entered || line != from) && depth <= d,
+                Resume::In {
+                    line: from,
+                    depth: d,
+                } => entered || line != from || depth != d,
+                Resume::Out { depth: d } => depth < d,
+            };
+            if done {
+                self.stop("step", None);
+                return;
+            }
+        }
+    }
+
+    /// Stop if there's a breakpoint on the current line and its condition
+    /// holds. A condition that can't be evaluated stops too, to show why
+    fn hit_breakpoint(&mut self) -> bool {
+        if self.no_debug {
+            return false;
+        }
+        let Some(debuggee) = self.debuggee.as_mut() else {
+            return false;
+        };
+        let Some(breakpoint) = debuggee.line().and_then(|l| self.breakpoints.get(&l)) else {
+            return false;
+        };
+        let id = breakpoint.id;
+        let text = match &breakpoint.condition {
+            None => None,
+            Some(condition) => match debuggee.is_true(condition) {
+                Ok(true) => None,
+                Ok(false) => return false,
+                Err(message) => Some(format!("Condition {}: {}", condition, message)),
+            },
+        };
+
+        self.resume = None;
+        let mut body = json!({
+            "reason": "breakpoint",
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+            "hitBreakpointIds": [id],
+        });
+        if let Some(text) = text {
+            body["text"] = json!(text);
+        }
+        self.connection.event("stopped", body);
+        true
+    }
+
+    fn stop(&mut self, reason: &str, text: Option<String>) {
+        self.resume = None;
+        let mut body = json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        });
+        if let Some(text) = text {
+            body["description"] = json!("Runtime error");
+            body["text"] = json!(text);
+        }
+        self.connection.event("stopped", body);
+    }
+
+    fn finish(&mut self, outcome: Outcome) {
+        match outcome {
+            Outcome::Error { message, line } => {
+                let text = match line {
+                    Some(line) => format!("{} in {}", message, line),
+                    None => message,
+                };
+                self.connection.event(
+                    "output",
+                    json!({ "category": "stderr", "output": format!("{}\n", text) }),
+                );
+                if self.no_debug {
+                    self.end(Some(text));
+                } else {
+                    // Stop where it failed so the variables can be inspected
+                    self.stop("exception", Some(text.clone()));
+                    self.error = Some(text);
+                }
+            }
+            _ => self.end(None),
+        }
+    }
+
+    fn end(&mut self, error: Option<String>) {
+        self.resume = None;
+        self.debuggee = None;
+        let code = if error.is_some() { 1 } else { 0 };
+        self.connection.event("exited", json!({ "exitCode": code }));
+        self.connection.event("terminated", json!({}));
+    }
+
+    fn stack_trace(&self) -> Value {
+        let frames = self
+            .debuggee
+            .as_ref()
+            .map(|d| d.frames())
+            .unwrap_or_default();
+        let name = self
+            .path
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned());
+        let frames: Vec<Value> = frames
+            .into_iter()
+            .enumerate()
+            .map(|(id, frame)| {
+                let line = self.source_lines.get(&frame.line).copied().unwrap_or(0);
+                json!({
+                    "id": id,
+                    "name": frame.name,
+                    "source": { "name": name, "path": self.path },
+                    "line": line + self.line_base,
+                    "column": self.line_base,
+                })
+            })
+            .collect();
+        json!({ "stackFrames": frames, "totalFrames": frames.len() })
+    }
+
+    fn variables(&self, args: &Value) -> Value {
+        let Some(debuggee) = &self.debuggee else {
+            return json!({ "variables": [] });
+        };
+        let reference = args["variablesReference"].as_i64().unwrap_or(0);
+
+        let variables: Vec<Value> = match reference {
+            VARIABLES => debuggee
+                .variables()
+                .into_iter()
+                .map(|(name, value)| json!({ "name": name, "value": value, "variablesReference": 0 }))
+                .collect(),
+            ARRAYS => debuggee
+                .arrays()
+                .into_iter()
+                .enumerate()
+                .map(|(idx, array)| {
+                    json!({
+                        "name": format!("{}()", array.name),
+                        "value": format!("{} elements", array.elements.len()),
+                        "variablesReference": FIRST_ARRAY + idx as i64,
+                        "indexedVariables": array.elements.len(),
+                    })
+                })
+                .collect(),
+            _ => {
+                let index = (reference - FIRST_ARRAY) as usize;
+                let start = args["start"].as_u64().unwrap_or(0) as usize;
+                let count = args["count"].as_u64().map_or(usize::MAX, |c| c as usize);
+                debuggee
+                    .arrays()
+                    .into_iter()
+                    .nth(index)
+                    .map(|array| {
+                        array
+                            .elements
+                            .into_iter()
+                            .skip(start)
+                            .take(count)
+                            .map(|(subscripts, value)| {
+                                json!({ "name": subscripts, "value": value, "variablesReference": 0 })
+                            })
+                            .collect()
+                    })
+                    .unwrap_or_default()
+            }
+        };
+        json!({ "variables": variables })
+    }
+
+    /// Evaluate an expression against the stopped program, as immediate
+    /// mode would
+    fn evaluate(&mut self, args: &Value) -> Result<Value, String> {
+        let expression = args["expression"].as_str().unwrap_or_default();
+        let debuggee = self.debuggee.as_mut().ok_or("The program isn't running")?;
+        let result = debuggee.evaluate(expression)?;
+        Ok(json!({ "result": result, "variablesReference": 0 }))
+    }
+}
+
+fn capabilities() -> Value {
+    json!({
+        "supportsConfigurationDoneRequest": true,
+        "supportsConditionalBreakpoints": true,
+        "supportsEvaluateForHovers": true,
+        "supportsTerminateRequest": true,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_are_framed_by_length() {
+        let body = r#"{"type":"request","command":"next"}"#;
+        let framed = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+        let mut reader = framed.as_bytes();
+        let message = read_message(&mut reader).unwrap();
+        assert_eq!(message["command"], "next");
+        assert!(read_message(&mut reader).is_none());
+    }
+
+    #[test]
+    fn breakpoints_move_to_the_numbered_line() {
+        let mut session = Session::new();
+        session.source_lines = HashMap::from([(10, 0), (20, 2)]);
+        let result = session.set_breakpoints(&json!({
+            "breakpoints": [
+                { "line": 2, "condition": "A > 1" },
+                { "line": 3, "condition": " " },
+                { "line": 9 },
+            ]
+        }));
+
+        let breakpoints = result["breakpoints"].as_array().unwrap();
+        assert_eq!(breakpoints[0]["line"], 3);
+        assert_eq!(breakpoints[1]["line"], 3);
+        assert_eq!(breakpoints[2]["verified"], false);
+        // The second breakpoint on line 20 replaces the first
+        assert_eq!(session.breakpoints.len(), 1);
+        assert!(session.breakpoints[&20].condition.is_none());
+    }
+
+    #[test]
+    fn lines_can_count_from_zero() {
+        let mut session = Session::new();
+        session.line_base = 0;
+        session.source_lines = HashMap::from([(10, 0)]);
+        let result = session.set_breakpoints(&json!({ "breakpoints": [{ "line": 0 }] }));
+        assert_eq!(result["breakpoints"][0]["line"], 0);
+        assert!(session.breakpoints.contains_key(&10));
+    }
+}
@@ -71,7 +71,7 @@ pub fn find_definition(
 }
 
 /// Find the first assignment of a variable
-fn find_variable_definition(source: &str, var_name: &str) -> Option<(u32, u32)> {
+pub fn find_variable_definition(source: &str, var_name: &str) -> Option<(u32, u32)> {
     for (line_idx, line) in source.lines().enumerate() {
         let upper = line.to_uppercase();
 
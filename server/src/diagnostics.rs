@@ -3,8 +3,18 @@ use basica::parser::Parser;
 use std::collections::{HashMap, HashSet};
 use tower_lsp::lsp_types::*;
 
+use crate::codes;
+use crate::config::{Config, DiagnosticsConfig};
+use crate::dialect::{self, Dialect};
+use crate::error_handlers;
+use crate::files;
+use crate::hardware;
+use crate::minilang;
+use crate::semantic_tokens::{lex_line, LexKind};
+use crate::suppression;
+
 /// Check source code for parse errors and warnings
-pub fn check(source: &str) -> Vec<Diagnostic> {
+pub fn check(source: &str, uri: &Url, config: &Config) -> Vec<Diagnostic> {
     let mut diagnostics = Vec::new();
 
     // First check for parse errors
@@ -15,7 +25,7 @@ pub fn check(source: &str) -> Vec<Diagnostic> {
     match parser.parse() {
         Ok(_) => {
             // No parse errors, check for warnings
-            diagnostics.extend(check_warnings(source));
+            diagnostics.extend(check_warnings(source, uri, config));
         }
         Err(msg) => {
             // Try to extract line number from error message
@@ -40,17 +50,83 @@ pub fn check(source: &str) -> Vec<Diagnostic> {
                 Range::default()
             };
 
-            diagnostics.push(Diagnostic {
+            let error = Diagnostic {
                 range,
                 severity: Some(DiagnosticSeverity::ERROR),
                 source: Some("basica".to_string()),
                 message,
                 ..Default::default()
-            });
+            };
+            diagnostics.extend(with_code("syntax-error", vec![error]));
+        }
+    }
+
+    // The warnings only ran if the program parsed, so only then can a
+    // suppression be known to be unused
+    let parsed = diagnostics
+        .iter()
+        .all(|d| codes::check_of(d).is_none_or(|c| c.name != "syntax-error"));
+    let diagnostics = suppression::apply(source, diagnostics, parsed, &config.diagnostics);
+    configure(diagnostics, &config.diagnostics)
+}
+
+/// Tag a check's diagnostics with its code, which suppressions and
+/// severity settings refer to
+fn with_code(name: &str, mut diagnostics: Vec<Diagnostic>) -> Vec<Diagnostic> {
+    if let Some(check) = codes::find(name) {
+        for diagnostic in &mut diagnostics {
+            check.tag(diagnostic);
         }
     }
+    diagnostics
+}
 
+/// Apply the configured severities, dropping checks that are turned off
+pub fn configure(diagnostics: Vec<Diagnostic>, config: &DiagnosticsConfig) -> Vec<Diagnostic> {
     diagnostics
+        .into_iter()
+        .filter_map(|mut diagnostic| {
+            let Some(severity) = codes::check_of(&diagnostic).and_then(|c| config.severity_of(c))
+            else {
+                return Some(diagnostic);
+            };
+            diagnostic.severity = Some(severity.to_lsp()?);
+            Some(diagnostic)
+        })
+        .collect()
+}
+
+/// A related location in the same document
+pub fn related(
+    uri: &Url,
+    line: u32,
+    start: u32,
+    end: u32,
+    message: &str,
+) -> DiagnosticRelatedInformation {
+    DiagnosticRelatedInformation {
+        location: Location {
+            uri: uri.clone(),
+            range: Range {
+                start: Position {
+                    line,
+                    character: start,
+                },
+                end: Position {
+                    line,
+                    character: end,
+                },
+            },
+        },
+        message: message.to_string(),
+    }
+}
+
+/// Related locations, or `None` when there are none
+pub fn related_list(
+    related: Vec<DiagnosticRelatedInformation>,
+) -> Option<Vec<DiagnosticRelatedInformation>> {
+    (!related.is_empty()).then_some(related)
 }
 
 /// Parse error message to extract line number and clean message
@@ -81,7 +157,7 @@ fn parse_error_message(msg: &str) -> (u32, String) {
 }
 
 /// Find the source file line (0-indexed) containing a BASIC line number
-fn find_source_line_for_basic_line(source: &str, basic_line: u32) -> u32 {
+pub fn find_source_line_for_basic_line(source: &str, basic_line: u32) -> u32 {
     for (idx, line) in source.lines().enumerate() {
         let trimmed = line.trim_start();
         if let Some(first_word) = trimmed.split_whitespace().next() {
@@ -96,8 +172,10 @@ fn find_source_line_for_basic_line(source: &str, basic_line: u32) -> u32 {
 }
 
 /// Check for warnings (undefined vars, unused vars, unreachable code)
-fn check_warnings(source: &str) -> Vec<Diagnostic> {
+fn check_warnings(source: &str, uri: &Url, config: &Config) -> Vec<Diagnostic> {
     let mut diagnostics = Vec::new();
+    let mut undefined = Vec::new();
+    let mut unused = Vec::new();
 
     // Track variable definitions and usages
     let (definitions, usages) = analyze_variables(source);
@@ -106,7 +184,7 @@ fn check_warnings(source: &str) -> Vec<Diagnostic> {
     for (var, locations) in &usages {
         if !definitions.contains_key(var) && !is_builtin_var(var) {
             for &(line_idx, char_start, char_end) in locations {
-                diagnostics.push(Diagnostic {
+                undefined.push(Diagnostic {
                     range: Range {
                         start: Position {
                             line: line_idx,
@@ -131,7 +209,7 @@ fn check_warnings(source: &str) -> Vec<Diagnostic> {
         if !usages.contains_key(var) {
             // Only warn for first definition
             if let Some(&(line_idx, char_start, char_end)) = locations.first() {
-                diagnostics.push(Diagnostic {
+                unused.push(Diagnostic {
                     range: Range {
                         start: Position {
                             line: line_idx,
@@ -146,17 +224,46 @@ fn check_warnings(source: &str) -> Vec<Diagnostic> {
                     source: Some("basica".to_string()),
                     message: format!("Variable '{}' is defined but never used", var),
                     tags: Some(vec![DiagnosticTag::UNNECESSARY]),
+                    related_information: related_list(
+                        locations[1..]
+                            .iter()
+                            .map(|&(line, start, end)| {
+                                related(uri, line, start, end, "Also assigned here")
+                            })
+                            .collect(),
+                    ),
                     ..Default::default()
                 });
             }
         }
     }
 
+    diagnostics.extend(with_code("undefined-variable", undefined));
+    diagnostics.extend(with_code("unused-variable", unused));
+
     // Check for unreachable code
-    diagnostics.extend(check_unreachable_code(source));
+    diagnostics.extend(with_code(
+        "unreachable-code",
+        check_unreachable_code(source, uri),
+    ));
 
     // Check for undefined line numbers in GOTO/GOSUB
-    diagnostics.extend(check_undefined_lines(source));
+    diagnostics.extend(with_code("undefined-line", check_undefined_lines(source)));
+
+    // Check PLAY, DRAW and PRINT USING strings
+    diagnostics.extend(with_code("string-syntax", check_embedded_strings(source)));
+
+    // Check statement arguments against the SCREEN mode and hardware ranges
+    diagnostics.extend(hardware::check(source));
+
+    // Check OPEN/CLOSE and file I/O
+    diagnostics.extend(files::check(source, uri));
+
+    // Check ON ERROR handlers, RESUME, ERR and ERL
+    diagnostics.extend(error_handlers::check(source, uri));
+
+    // Check for words the selected dialect doesn't have
+    diagnostics.extend(with_code("dialect", check_dialect(source, config.dialect)));
 
     diagnostics
 }
@@ -279,6 +386,21 @@ fn find_variable_usages(
     let mut pos = 0;
 
     while pos < bytes.len() {
+        // Skip string literals
+        if bytes[pos] == b'"' {
+            pos += 1;
+            while pos < bytes.len() && bytes[pos] != b'"' {
+                pos += 1;
+            }
+            pos += 1;
+            continue;
+        }
+
+        // Comments (and suppression directives in them) hold no variables
+        if bytes[pos] == b'\'' {
+            break;
+        }
+
         // Skip non-alphabetic
         if !bytes[pos].is_ascii_alphabetic() {
             pos += 1;
@@ -294,6 +416,9 @@ fn find_variable_usages(
         }
 
         let word = &line[start..pos];
+        if word == "REM" {
+            break;
+        }
         if !is_keyword(word) && !is_function(word) && word.len() > 0 {
             // Skip if this position is a definition site
             let is_def_site = definitions.get(word).map_or(false, |locs| {
@@ -497,7 +622,7 @@ fn is_function(word: &str) -> bool {
 }
 
 /// Check for unreachable code after END, STOP, or unconditional GOTO
-fn check_unreachable_code(source: &str) -> Vec<Diagnostic> {
+fn check_unreachable_code(source: &str, uri: &Url) -> Vec<Diagnostic> {
     let mut diagnostics = Vec::new();
     let lines: Vec<&str> = source.lines().collect();
     let mut unreachable_start: Option<u32> = None;
@@ -532,6 +657,13 @@ fn check_unreachable_code(source: &str) -> Vec<Diagnostic> {
                                 source: Some("basica".to_string()),
                                 message: "Unreachable code".to_string(),
                                 tags: Some(vec![DiagnosticTag::UNNECESSARY]),
+                                related_information: Some(vec![related(
+                                    uri,
+                                    start,
+                                    0,
+                                    lines[start as usize].len() as u32,
+                                    "Execution doesn't continue past this line",
+                                )]),
                                 ..Default::default()
                             });
                         }
@@ -628,6 +760,132 @@ fn check_undefined_lines(source: &str) -> Vec<Diagnostic> {
     diagnostics
 }
 
+/// Check for reserved words and functions missing from the selected dialect
+fn check_dialect(source: &str, dialect: Dialect) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    if dialect == Dialect::Basica {
+        return diagnostics;
+    }
+
+    for (line_idx, line) in source.lines().enumerate() {
+        let upper = line.to_ascii_uppercase();
+        for lexeme in lex_line(&upper, Dialect::Basica) {
+            if lexeme.kind != LexKind::Keyword {
+                continue;
+            }
+            let word = &upper[lexeme.start..lexeme.end];
+            // A crunched name like DOG is just a variable where DO isn't reserved
+            let whole_word = !upper[lexeme.end..].starts_with(|c: char| c.is_ascii_alphanumeric());
+            if !whole_word || dialect.supports(word) {
+                continue;
+            }
+
+            diagnostics.push(Diagnostic {
+                range: Range {
+                    start: Position {
+                        line: line_idx as u32,
+                        character: lexeme.start as u32,
+                    },
+                    end: Position {
+                        line: line_idx as u32,
+                        character: lexeme.end as u32,
+                    },
+                },
+                severity: Some(DiagnosticSeverity::ERROR),
+                source: Some("basica".to_string()),
+                message: format!(
+                    "{} is not available in {}",
+                    dialect::construct_name(word),
+                    dialect.name()
+                ),
+                ..Default::default()
+            });
+        }
+    }
+
+    diagnostics
+}
+
+/// Check string literals passed to PLAY, DRAW and PRINT USING.
+///
+/// Errors point at the offending characters inside the literal.
+fn check_embedded_strings(source: &str) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+
+    for (line_idx, line) in source.lines().enumerate() {
+        for (start, end) in string_literals(line) {
+            let before = line[..start].trim_end().to_uppercase();
+            let content = line[start + 1..end].trim_end_matches('"');
+
+            let (statement, errors) = if before.ends_with("PLAY") {
+                ("PLAY", minilang::check_play(content))
+            } else if before.ends_with("DRAW") {
+                ("DRAW", minilang::check_draw(content))
+            } else if before.ends_with("USING") {
+                ("PRINT USING", minilang::check_using(content))
+            } else {
+                continue;
+            };
+
+            for error in errors {
+                diagnostics.push(Diagnostic {
+                    range: Range {
+                        start: Position {
+                            line: line_idx as u32,
+                            character: (start + 1 + error.start) as u32,
+                        },
+                        end: Position {
+                            line: line_idx as u32,
+                            character: (start + 1 + error.end) as u32,
+                        },
+                    },
+                    severity: Some(DiagnosticSeverity::ERROR),
+                    source: Some("basica".to_string()),
+                    message: format!("{}: {}", statement, error.message),
+                    ..Default::default()
+                });
+            }
+        }
+    }
+
+    diagnostics
+}
+
+/// Byte ranges of the string literals on a line, including their quotes
+fn string_literals(line: &str) -> Vec<(usize, usize)> {
+    let bytes = line.as_bytes();
+    let mut literals = Vec::new();
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        match bytes[pos] {
+            b'"' => {
+                let start = pos;
+                pos += 1;
+                while pos < bytes.len() && bytes[pos] != b'"' {
+                    pos += 1;
+                }
+                pos = (pos + 1).min(bytes.len());
+                literals.push((start, pos));
+            }
+            b'\'' => break,
+            b if b.is_ascii_alphabetic() => {
+                let start = pos;
+                while pos < bytes.len() && bytes[pos].is_ascii_alphanumeric() {
+                    pos += 1;
+                }
+                // REM is recognised even when crunched into a longer word
+                if line[start..pos].to_uppercase().starts_with("REM") {
+                    break;
+                }
+            }
+            _ => pos += 1,
+        }
+    }
+
+    literals
+}
+
 /// Find all line numbers that are jump targets
 fn find_jump_targets(source: &str) -> HashSet<u32> {
     let mut targets = HashSet::new();
@@ -658,3 +916,33 @@ fn find_jump_targets(source: &str) -> HashSet<u32> {
 
     targets
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dialect_messages_name_the_chosen_dialect() {
+        let source = "10 DO: LOOP UNTIL X\n20 DOG = UCASE$(A$)\n";
+        let messages = |dialect| -> Vec<String> {
+            check_dialect(source, dialect)
+                .into_iter()
+                .map(|d| d.message)
+                .collect()
+        };
+        assert_eq!(
+            messages(Dialect::IbmBasica),
+            [
+                "DO...LOOP is not available in BASICA 3.3",
+                "DO...LOOP is not available in BASICA 3.3",
+                "DO...LOOP is not available in BASICA 3.3",
+                "UCASE$ is not available in BASICA 3.3",
+            ]
+        );
+        assert_eq!(
+            messages(Dialect::GwBasic)[3],
+            "UCASE$ is not available in GW-BASIC 3.23"
+        );
+        assert!(messages(Dialect::Basica).is_empty());
+    }
+}
@@ -0,0 +1,75 @@
+use serde::Deserialize;
+
+/// The BASIC a program is written for, which decides the reserved words
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+pub enum Dialect {
+    /// The basica interpreter, including its QBasic-style extensions
+    #[default]
+    #[serde(rename = "basica")]
+    Basica,
+    /// Microsoft GW-BASIC 3.23
+    #[serde(rename = "gw-basic")]
+    GwBasic,
+    /// IBM BASICA 3.3
+    #[serde(rename = "ibm-basica")]
+    IbmBasica,
+}
+
+/// Reserved words and functions basica adds on top of GW-BASIC and BASICA
+const EXTENSIONS: &[&str] = &[
+    "DO", "LOOP", "UNTIL", "EXIT", "SELECT", "CASE", "ELSEIF", "ENDIF", "SUB", "SHARED", "STATIC",
+    "BINARY", "UCASE$", "LCASE$", "LTRIM$", "RTRIM$", "SADD",
+];
+
+impl Dialect {
+    /// Name used in messages
+    pub fn name(self) -> &'static str {
+        match self {
+            Dialect::Basica => "basica",
+            Dialect::GwBasic => "GW-BASIC 3.23",
+            Dialect::IbmBasica => "BASICA 3.3",
+        }
+    }
+
+    /// Whether a reserved word or built-in function exists in this dialect
+    pub fn supports(self, word: &str) -> bool {
+        self == Dialect::Basica || !EXTENSIONS.contains(&word)
+    }
+}
+
+/// How to name the construct a reserved word belongs to
+pub fn construct_name(word: &str) -> &str {
+    match word {
+        "DO" | "LOOP" | "UNTIL" => "DO...LOOP",
+        "SELECT" | "CASE" => "SELECT CASE",
+        "ELSEIF" | "ENDIF" => "Block IF",
+        "SUB" | "SHARED" | "STATIC" => "SUB",
+        "BINARY" => "OPEN FOR BINARY",
+        _ => word,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn each_setting_is_its_own_dialect() {
+        let parse = |name: &str| serde_json::from_value::<Dialect>(name.into()).unwrap();
+        assert_eq!(parse("basica"), Dialect::Basica);
+        assert_eq!(parse("gw-basic"), Dialect::GwBasic);
+        assert_eq!(parse("ibm-basica"), Dialect::IbmBasica);
+        assert_eq!(Dialect::IbmBasica.name(), "BASICA 3.3");
+        assert!(serde_json::from_value::<Dialect>("qbasic".into()).is_err());
+    }
+
+    #[test]
+    fn only_basica_has_the_extensions() {
+        assert!(Dialect::Basica.supports("SELECT"));
+        assert!(!Dialect::GwBasic.supports("SELECT"));
+        assert!(!Dialect::IbmBasica.supports("UCASE$"));
+        assert!(Dialect::IbmBasica.supports("WHILE"));
+        assert_eq!(construct_name("LOOP"), "DO...LOOP");
+        assert_eq!(construct_name("SADD"), "SADD");
+    }
+}
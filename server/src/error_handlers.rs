@@ -0,0 +1,314 @@
+use std::collections::HashSet;
+use tower_lsp::lsp_types::*;
+
+use crate::codes;
+use crate::diagnostics::related;
+use crate::flow::{self, Program, Statement, Token};
+use crate::semantic_tokens::LexKind;
+
+/// An `ON ERROR GOTO n` statement
+struct OnError<'a> {
+    statement: &'a Statement,
+    target: u32,
+    /// The `n` token
+    token: &'a Token,
+}
+
+/// Check ON ERROR GOTO handlers, RESUME, and uses of ERR and ERL
+pub fn check(source: &str, uri: &Url) -> Vec<Diagnostic> {
+    let program = Program::build(source);
+    let mut diagnostics = Vec::new();
+
+    // Code that runs without an error having occurred
+    let normal = reachable(&program, &[0], |_| true);
+
+    let on_errors: Vec<OnError> = program.statements.iter().filter_map(on_error).collect();
+    let mut in_handler = HashSet::new();
+    for on_error in &on_errors {
+        // Undefined targets are reported with the other undefined lines
+        let Some(&entry) = program.lines.get(&on_error.target) else {
+            continue;
+        };
+        let handler_start = {
+            let statement = &program.statements[entry];
+            let (start, end) = flow::span(&statement.tokens).unwrap_or((0, 0));
+            related(
+                uri,
+                statement.line,
+                start as u32,
+                end as u32,
+                "Error handler starts here",
+            )
+        };
+
+        if normal.contains(&entry) {
+            let mut diagnostic = diagnostic(
+                "handler-fallthrough",
+                on_error.statement.line,
+                on_error.token,
+                format!(
+                    "Error handler at line {} is also reached without an error; end the program before it",
+                    on_error.target
+                ),
+            );
+            diagnostic.related_information = Some(vec![handler_start.clone()]);
+            diagnostics.push(diagnostic);
+        }
+
+        // The handler runs until a RESUME hands control back
+        let handler = reachable(&program, &[entry], |s| s.keyword() != Some("RESUME"));
+        let ends = handler.iter().any(|&idx| {
+            let statement = &program.statements[idx];
+            matches!(
+                statement.keyword(),
+                Some("RESUME") | Some("END") | Some("STOP") | Some("SYSTEM") | Some("ERROR")
+            ) || on_error_target(statement) == Some(0)
+        });
+        if !ends {
+            let mut diagnostic = diagnostic(
+                "handler-without-resume",
+                on_error.statement.line,
+                on_error.token,
+                format!(
+                    "Error handler at line {} never reaches RESUME",
+                    on_error.target
+                ),
+            );
+            diagnostic.related_information = Some(vec![handler_start]);
+            diagnostics.push(diagnostic);
+        }
+        in_handler.extend(handler);
+    }
+
+    for idx in 0..program.statements.len() {
+        if !normal.contains(&idx) || in_handler.contains(&idx) {
+            continue;
+        }
+        let statement = &program.statements[idx];
+        if statement.keyword() == Some("RESUME") {
+            diagnostics.push(diagnostic(
+                "outside-handler",
+                statement.line,
+                &statement.tokens[0],
+                "RESUME outside an error handler".to_string(),
+            ));
+        }
+        for token in &statement.tokens {
+            if token.text == "ERR" || token.text == "ERL" {
+                diagnostics.push(diagnostic(
+                    "outside-handler",
+                    statement.line,
+                    token,
+                    format!("{} is only meaningful in an error handler", token.text),
+                ));
+            }
+        }
+    }
+
+    // ERL = n, n <> ERL, ...
+    for statement in &program.statements {
+        for token in erl_comparisons(&statement.tokens) {
+            let exists = token
+                .text
+                .parse::<u32>()
+                .is_ok_and(|n| program.lines.contains_key(&n));
+            if !exists {
+                diagnostics.push(diagnostic(
+                    "erl-line",
+                    statement.line,
+                    token,
+                    format!(
+                        "Line {} does not exist, so ERL can never equal it",
+                        token.text
+                    ),
+                ));
+            }
+        }
+    }
+
+    diagnostics
+}
+
+/// Parse `ON ERROR GOTO n`
+fn on_error(statement: &Statement) -> Option<OnError<'_>> {
+    let target = on_error_target(statement)?;
+    (target != 0).then_some(OnError {
+        statement,
+        target,
+        token: &statement.tokens[3],
+    })
+}
+
+fn on_error_target(statement: &Statement) -> Option<u32> {
+    match statement.tokens.as_slice() {
+        [on, error, goto, number]
+            if on.is_keyword("ON")
+                && error.text == "ERROR"
+                && goto.is_keyword("GOTO")
+                && number.kind == LexKind::Number =>
+        {
+            number.text.parse().ok()
+        }
+        _ => None,
+    }
+}
+
+/// Statements reachable from `entries`, only following edges out of statements
+/// for which `expand` holds
+fn reachable(
+    program: &Program,
+    entries: &[usize],
+    expand: impl Fn(&Statement) -> bool,
+) -> HashSet<usize> {
+    let mut seen: HashSet<usize> = entries
+        .iter()
+        .copied()
+        .filter(|&idx| idx < program.statements.len())
+        .collect();
+    let mut worklist: Vec<usize> = seen.iter().copied().collect();
+
+    while let Some(idx) = worklist.pop() {
+        let statement = &program.statements[idx];
+        if !expand(statement) {
+            continue;
+        }
+        for &next in &statement.successors {
+            if seen.insert(next) {
+                worklist.push(next);
+            }
+        }
+    }
+
+    seen
+}
+
+/// Line number tokens compared against ERL
+fn erl_comparisons(tokens: &[Token]) -> Vec<&Token> {
+    let is_comparison =
+        |t: &Token| t.is_operator(b'=') || t.is_operator(b'<') || t.is_operator(b'>');
+    let mut numbers = Vec::new();
+
+    for (i, token) in tokens.iter().enumerate() {
+        if token.text != "ERL" {
+            continue;
+        }
+        // ERL <op> n
+        let ops = tokens[i + 1..]
+            .iter()
+            .take_while(|t| is_comparison(t))
+            .count();
+        if let Some(number) = tokens.get(i + 1 + ops) {
+            if ops > 0 && number.kind == LexKind::Number {
+                numbers.push(number);
+            }
+        }
+        // n <op> ERL
+        let ops = tokens[..i]
+            .iter()
+            .rev()
+            .take_while(|t| is_comparison(t))
+            .count();
+        if ops > 0 && i > ops {
+            let number = &tokens[i - ops - 1];
+            if number.kind == LexKind::Number {
+                numbers.push(number);
+            }
+        }
+    }
+
+    numbers
+}
+
+/// A warning tagged with the code of the check that found it
+fn diagnostic(check: &str, line: u32, token: &Token, message: String) -> Diagnostic {
+    let mut diagnostic = Diagnostic {
+        range: Range {
+            start: Position {
+                line,
+                character: token.start as u32,
+            },
+            end: Position {
+                line,
+                character: token.end as u32,
+            },
+        },
+        severity: Some(DiagnosticSeverity::WARNING),
+        source: Some("basica".to_string()),
+        message,
+        ..Default::default()
+    };
+    if let Some(check) = codes::find(check) {
+        check.tag(&mut diagnostic);
+    }
+    diagnostic
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(source: &str) -> Vec<(u32, String)> {
+        let uri = Url::parse("file:///test.bas").unwrap();
+        check(source, &uri)
+            .into_iter()
+            .map(|d| (d.range.start.line, d.message))
+            .collect()
+    }
+
+    #[test]
+    fn handlers_must_be_fenced_off_and_resume() {
+        let source = "10 ON ERROR GOTO 30\n\
+            20 PRINT 1 / 0\n\
+            30 PRINT ERR\n\
+            40 GOTO 30\n";
+        assert_eq!(
+            messages(source),
+            [
+                (
+                    0,
+                    "Error handler at line 30 is also reached without an error; end the program before it"
+                        .to_string()
+                ),
+                (0, "Error handler at line 30 never reaches RESUME".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn a_well_formed_handler_is_quiet() {
+        let source = "10 ON ERROR GOTO 100\n\
+            20 ERROR 5\n\
+            30 END\n\
+            100 IF ERL = 20 THEN RESUME NEXT\n\
+            110 ON ERROR GOTO 0\n";
+        assert_eq!(messages(source), []);
+    }
+
+    #[test]
+    fn error_state_outside_a_handler() {
+        let source = "10 PRINT ERR, ERL\n20 RESUME\n";
+        assert_eq!(
+            messages(source),
+            [
+                (0, "ERR is only meaningful in an error handler".to_string()),
+                (0, "ERL is only meaningful in an error handler".to_string()),
+                (1, "RESUME outside an error handler".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn erl_compares_with_existing_lines() {
+        let source = "10 ON ERROR GOTO 100\n\
+            20 END\n\
+            100 IF 25 = ERL OR ERL <> 20 THEN RESUME 20\n\
+            110 RESUME NEXT\n";
+        assert_eq!(
+            messages(source),
+            [(
+                2,
+                "Line 25 does not exist, so ERL can never equal it".to_string()
+            )]
+        );
+    }
+}
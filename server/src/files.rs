@@ -0,0 +1,580 @@
+use std::collections::BTreeMap;
+use tower_lsp::lsp_types::*;
+
+use crate::codes;
+use crate::diagnostics::{related, related_list};
+use crate::flow::{self, Program, Statement, Token};
+use crate::semantic_tokens::LexKind;
+
+/// How a file was opened
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Mode {
+    Input,
+    Output,
+    Append,
+    Random,
+}
+
+impl Mode {
+    fn name(self) -> &'static str {
+        match self {
+            Mode::Input => "INPUT",
+            Mode::Output => "OUTPUT",
+            Mode::Append => "APPEND",
+            Mode::Random => "RANDOM",
+        }
+    }
+}
+
+/// An OPEN statement that may have opened a file number
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct OpenFile {
+    mode: Mode,
+    /// File name when it's a string literal
+    name: Option<String>,
+    /// Source line of the OPEN
+    line: u32,
+    /// Where the OPEN names its file number
+    span: (usize, usize),
+    /// BASIC line number of the OPEN
+    number: Option<u32>,
+}
+
+/// What a file number may refer to at some point of the program
+#[derive(Debug, Clone, PartialEq)]
+struct Slot {
+    /// The number may be closed
+    closed: bool,
+    /// OPEN statements the number may have come from
+    opens: Vec<OpenFile>,
+}
+
+impl Default for Slot {
+    fn default() -> Self {
+        Self {
+            closed: true,
+            opens: Vec::new(),
+        }
+    }
+}
+
+impl Slot {
+    fn is_open(&self) -> bool {
+        !self.closed && !self.opens.is_empty()
+    }
+
+    fn is_closed(&self) -> bool {
+        self.closed && self.opens.is_empty()
+    }
+
+    /// The mode the file is certainly open in
+    fn mode(&self) -> Option<Mode> {
+        let mode = self.opens.first()?.mode;
+        (self.is_open() && self.opens.iter().all(|f| f.mode == mode)).then_some(mode)
+    }
+}
+
+/// Open files on entry to a statement
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Files {
+    /// Numbers that may be open; any other number is closed
+    slots: BTreeMap<i64, Slot>,
+    /// A file was opened with a computed number, so any number may be open
+    dynamic: bool,
+}
+
+impl Files {
+    fn slot(&self, number: i64) -> Slot {
+        self.slots.get(&number).cloned().unwrap_or_default()
+    }
+
+    fn join(&self, other: &Files) -> Files {
+        let mut joined = self.clone();
+        joined.dynamic |= other.dynamic;
+        for number in other.slots.keys().chain(self.slots.keys()) {
+            let (a, b) = (self.slot(*number), other.slot(*number));
+            let mut opens = a.opens;
+            opens.extend(b.opens);
+            opens.sort();
+            opens.dedup();
+            joined.slots.insert(
+                *number,
+                Slot {
+                    closed: a.closed || b.closed,
+                    opens,
+                },
+            );
+        }
+        joined
+    }
+}
+
+/// What a statement does with a file number
+#[derive(Clone, Copy, PartialEq)]
+enum Access {
+    Read,
+    Write,
+    /// GET/PUT of a record
+    Record,
+    Field,
+}
+
+/// Check OPEN/CLOSE and file I/O along the control flow
+pub fn check(source: &str, uri: &Url) -> Vec<Diagnostic> {
+    let program = Program::build(source);
+    let states = program.solve(Files::default(), transfer, Files::join);
+    let opened: Vec<i64> = program
+        .statements
+        .iter()
+        .filter_map(|s| parse_open(s).and_then(|(n, _)| n))
+        .collect();
+    let any_dynamic = program
+        .statements
+        .iter()
+        .any(|s| parse_open(s).is_some_and(|(n, _)| n.is_none()));
+
+    let mut diagnostics = Vec::new();
+    for (statement, state) in program.statements.iter().zip(&states) {
+        let Some(state) = state else { continue };
+        let mut report =
+            |check: &str, span: (usize, usize), message: String, opens: &[OpenFile]| {
+                let mut diagnostic = diagnostic(check, statement.line, span, message);
+                diagnostic.related_information = related_list(
+                    opens
+                        .iter()
+                        .map(|open| {
+                            let (start, end) = open.span;
+                            related(uri, open.line, start as u32, end as u32, "Opened here")
+                        })
+                        .collect(),
+                );
+                diagnostics.push(diagnostic);
+            };
+
+        if let Some((number, _)) = parse_open(statement) {
+            let slot = number.map(|n| (n, state.slot(n)));
+            if let Some((number, slot)) = slot.filter(|(_, slot)| slot.is_open()) {
+                report(
+                    "file-already-open",
+                    open_span(statement),
+                    format!(
+                        "File #{} is already open ({})",
+                        number,
+                        describe_opens(&slot.opens)
+                    ),
+                    &slot.opens,
+                );
+            }
+            continue;
+        }
+
+        match statement.keyword() {
+            Some("END") | Some("SYSTEM") => {
+                for (number, slot) in &state.slots {
+                    if slot.is_open() {
+                        report(
+                            "file-left-open",
+                            (statement.tokens[0].start, statement.tokens[0].end),
+                            format!(
+                                "File #{} is still open at END ({})",
+                                number,
+                                describe_opens(&slot.opens)
+                            ),
+                            &slot.opens,
+                        );
+                    }
+                }
+                continue;
+            }
+            Some("CLOSE") => {
+                for argument in statement.arguments() {
+                    let Some((number, span)) = file_number(argument) else {
+                        continue;
+                    };
+                    if state.slot(number).is_closed() && !state.dynamic {
+                        report(
+                            "file-not-open",
+                            span,
+                            not_open_message(number, &opened, any_dynamic),
+                            &[],
+                        );
+                    }
+                }
+                continue;
+            }
+            _ => {}
+        }
+
+        let Some((access, tokens)) = file_access(statement) else {
+            continue;
+        };
+        let Some((number, span)) = file_number(tokens) else {
+            continue;
+        };
+        let slot = state.slot(number);
+
+        if slot.is_closed() {
+            if !state.dynamic {
+                report(
+                    "file-not-open",
+                    span,
+                    not_open_message(number, &opened, any_dynamic),
+                    &[],
+                );
+            }
+            continue;
+        }
+
+        let Some(mode) = slot.mode() else { continue };
+        let problem = match (access, mode) {
+            (Access::Write, Mode::Input) => Some("it can't be written to"),
+            (Access::Read, Mode::Output | Mode::Append) => Some("it can't be read from"),
+            (Access::Record | Access::Field, Mode::Input | Mode::Output | Mode::Append) => {
+                Some("FIELD, GET and PUT need a RANDOM file")
+            }
+            _ => None,
+        };
+        if let Some(problem) = problem {
+            report(
+                "file-mode",
+                span,
+                format!("File #{} is open FOR {}; {}", number, mode.name(), problem),
+                &slot.opens,
+            );
+        }
+    }
+
+    diagnostics
+}
+
+/// Describe the file a `#n` reference at `character` on `line` refers to
+pub fn file_hover(source: &str, line: u32, character: usize) -> Option<String> {
+    let program = Program::build(source);
+    let states = program.solve(Files::default(), transfer, Files::join);
+
+    let (statement, state) = program
+        .statements
+        .iter()
+        .zip(&states)
+        .find(|(s, _)| s.line == line && s.tokens.iter().any(|t| t.start == character))?;
+    let token = statement.tokens.iter().find(|t| t.start == character)?;
+    let number = flow::constant(std::slice::from_ref(token))?;
+
+    // Describe an OPEN by what it opens
+    let state = match (state, statement.keyword()) {
+        (Some(state), Some("OPEN")) => Some(transfer(statement, state)),
+        (state, _) => state.clone(),
+    };
+
+    let mut value = format!("**File #{}**\n\n", number);
+    match state {
+        Some(state) => {
+            let slot = state.slot(number);
+            if slot.opens.is_empty() {
+                value.push_str("Not open here");
+            } else {
+                for open in &slot.opens {
+                    value.push_str(&format!("- {}\n", describe_open(open)));
+                }
+                if slot.closed {
+                    value.push_str("\nMay also be closed here");
+                }
+            }
+        }
+        None => {
+            // Unreached code: list every OPEN of this number
+            let opens: Vec<OpenFile> = program
+                .statements
+                .iter()
+                .filter_map(|s| match parse_open(s) {
+                    Some((Some(n), open)) if n == number => Some(open),
+                    _ => None,
+                })
+                .collect();
+            if opens.is_empty() {
+                value.push_str("Never opened");
+            }
+            for open in &opens {
+                value.push_str(&format!("- {}\n", describe_open(open)));
+            }
+        }
+    }
+
+    Some(value.trim_end().to_string())
+}
+
+/// Open files after a statement runs
+fn transfer(statement: &Statement, state: &Files) -> Files {
+    let mut state = state.clone();
+
+    if let Some((number, open)) = parse_open(statement) {
+        match number {
+            Some(number) => {
+                state.slots.insert(
+                    number,
+                    Slot {
+                        closed: false,
+                        opens: vec![open],
+                    },
+                );
+            }
+            None => state.dynamic = true,
+        }
+        return state;
+    }
+
+    match statement.keyword() {
+        Some("CLOSE") => {
+            let arguments = statement.arguments();
+            if arguments.is_empty() {
+                return Files::default();
+            }
+            for argument in arguments {
+                match file_number(argument) {
+                    Some((number, _)) => {
+                        state.slots.remove(&number);
+                    }
+                    // CLOSE n% may close any of them
+                    None => {
+                        for slot in state.slots.values_mut() {
+                            slot.closed = true;
+                        }
+                    }
+                }
+            }
+        }
+        Some("RESET") | Some("END") | Some("SYSTEM") => return Files::default(),
+        _ => {}
+    }
+
+    state
+}
+
+/// Parse an OPEN statement into its file number (`None` if computed) and file.
+///
+/// Handles `OPEN name FOR mode AS #n` and `OPEN "mode", #n, name`.
+fn parse_open(statement: &Statement) -> Option<(Option<i64>, OpenFile)> {
+    if statement.keyword() != Some("OPEN") {
+        return None;
+    }
+    let tokens = &statement.tokens[1..];
+    let string = |tokens: &[Token]| match tokens {
+        [t] if t.kind == LexKind::String => Some(t.text.trim_matches('"').to_string()),
+        _ => None,
+    };
+
+    let (mode, number, name) = match tokens.iter().position(|t| t.is_keyword("AS")) {
+        Some(as_pos) => {
+            let for_pos = tokens[..as_pos].iter().position(|t| t.is_keyword("FOR"));
+            let mode = match for_pos
+                .and_then(|p| tokens.get(p + 1))
+                .map(|t| t.text.as_str())
+            {
+                Some("INPUT") => Mode::Input,
+                Some("OUTPUT") => Mode::Output,
+                Some("APPEND") => Mode::Append,
+                _ => Mode::Random,
+            };
+            let number_end = tokens[as_pos..]
+                .iter()
+                .position(|t| t.text == "LEN")
+                .map_or(tokens.len(), |p| as_pos + p);
+            let number = file_number(&tokens[as_pos + 1..number_end]).map(|(n, _)| n);
+            let name = string(&tokens[..for_pos.unwrap_or(as_pos)]);
+            (mode, number, name)
+        }
+        None => {
+            let arguments = statement.arguments();
+            let mode = match string(arguments.first()?)?.chars().next()? {
+                'I' => Mode::Input,
+                'O' => Mode::Output,
+                'A' => Mode::Append,
+                _ => Mode::Random,
+            };
+            let number = file_number(arguments.get(1)?).map(|(n, _)| n);
+            let name = arguments.get(2).and_then(|a| string(a));
+            (mode, number, name)
+        }
+    };
+
+    Some((
+        number,
+        OpenFile {
+            mode,
+            name,
+            line: statement.line,
+            span: open_span(statement),
+            number: statement.number,
+        },
+    ))
+}
+
+/// Range to report a duplicate OPEN on: the `#n` after AS, or the keyword
+fn open_span(statement: &Statement) -> (usize, usize) {
+    let tokens = &statement.tokens;
+    let start = tokens
+        .iter()
+        .position(|t| t.is_keyword("AS"))
+        .map_or(0, |p| p + 1)
+        .min(tokens.len() - 1);
+    let end = tokens[start..]
+        .iter()
+        .position(|t| t.kind == LexKind::Number)
+        .map_or(start, |p| start + p);
+    (tokens[start].start, tokens[end].end)
+}
+
+/// The file number argument of a file I/O statement
+fn file_access(statement: &Statement) -> Option<(Access, &[Token])> {
+    let tokens = &statement.tokens;
+    let hash = |at: usize| tokens.get(at).is_some_and(|t| t.is_operator(b'#'));
+
+    let (access, from) = match statement.keyword()? {
+        "PRINT" | "WRITE" if hash(1) => (Access::Write, 1),
+        "INPUT" if hash(1) => (Access::Read, 1),
+        "LINE" if tokens.get(1).is_some_and(|t| t.is_keyword("INPUT")) && hash(2) => {
+            (Access::Read, 2)
+        }
+        // GET (x1,y1)-(x2,y2) is the graphics statement
+        "GET" | "PUT" if !tokens.get(1).is_some_and(|t| t.is_operator(b'(')) => (Access::Record, 1),
+        "FIELD" => (Access::Field, 1),
+        _ => return None,
+    };
+
+    let end = tokens[from..]
+        .iter()
+        .position(|t| t.is_operator(b',') || t.is_operator(b';'))
+        .map_or(tokens.len(), |p| from + p);
+    Some((access, &tokens[from..end]))
+}
+
+/// A constant file number like `#1` or `1`, with its range
+fn file_number(tokens: &[Token]) -> Option<(i64, (usize, usize))> {
+    let number = match tokens {
+        [hash, rest @ ..] if hash.is_operator(b'#') => rest,
+        _ => tokens,
+    };
+    Some((flow::constant(number)?, flow::span(tokens)?))
+}
+
+fn not_open_message(number: i64, opened: &[i64], any_dynamic: bool) -> String {
+    if opened.contains(&number) || any_dynamic {
+        format!("File #{} is not open here", number)
+    } else {
+        format!("File #{} is never opened", number)
+    }
+}
+
+fn describe_open(open: &OpenFile) -> String {
+    let name = open
+        .name
+        .as_ref()
+        .map_or_else(|| "File".to_string(), |n| format!("`{}`", n));
+    match open.number {
+        Some(line) => format!("{} opened FOR {} on line {}", name, open.mode.name(), line),
+        None => format!("{} opened FOR {}", name, open.mode.name()),
+    }
+}
+
+fn describe_opens(opens: &[OpenFile]) -> String {
+    opens
+        .iter()
+        .map(describe_open)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// A warning tagged with the code of the check that found it
+fn diagnostic(check: &str, line: u32, (start, end): (usize, usize), message: String) -> Diagnostic {
+    let mut diagnostic = Diagnostic {
+        range: Range {
+            start: Position {
+                line,
+                character: start as u32,
+            },
+            end: Position {
+                line,
+                character: end as u32,
+            },
+        },
+        severity: Some(DiagnosticSeverity::WARNING),
+        source: Some("basica".to_string()),
+        message,
+        ..Default::default()
+    };
+    if let Some(check) = codes::find(check) {
+        check.tag(&mut diagnostic);
+    }
+    diagnostic
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(source: &str) -> Vec<(u32, String)> {
+        let uri = Url::parse("file:///test.bas").unwrap();
+        check(source, &uri)
+            .into_iter()
+            .map(|d| (d.range.start.line, d.message))
+            .collect()
+    }
+
+    #[test]
+    fn open_files_follow_gosub_and_goto() {
+        let source = "10 OPEN \"IN.TXT\" FOR INPUT AS #1\n\
+            20 GOSUB 100\n\
+            30 PRINT #1, \"X\"\n\
+            40 CLOSE #1\n\
+            50 GOTO 70\n\
+            60 OPEN \"OUT.TXT\" FOR OUTPUT AS #1\n\
+            70 PRINT #1, \"Y\"\n\
+            80 END\n\
+            100 INPUT #1, A$: RETURN\n";
+        let messages = messages(source);
+        assert_eq!(messages.len(), 2, "{:?}", messages);
+        assert_eq!(messages[0].0, 2);
+        assert!(messages[0]
+            .1
+            .starts_with("File #1 is open FOR INPUT; it can't be written to"));
+        assert_eq!(messages[1].0, 6);
+    }
+
+    #[test]
+    fn closed_and_reopened_numbers() {
+        let source = "10 PRINT #2, 1\n\
+            20 OPEN \"A\" FOR OUTPUT AS #1\n\
+            30 OPEN \"B\" FOR APPEND AS #1\n\
+            40 CLOSE #1: CLOSE #1\n\
+            50 OPEN \"C\" FOR INPUT AS 3\n\
+            60 GET #3, 1\n\
+            70 END\n";
+        let messages: Vec<String> = messages(source).into_iter().map(|(_, m)| m).collect();
+        assert_eq!(
+            messages,
+            [
+                "File #2 is never opened",
+                "File #1 is already open (`A` opened FOR OUTPUT on line 20)",
+                "File #1 is not open here",
+                "File #3 is open FOR INPUT; FIELD, GET and PUT need a RANDOM file",
+                "File #3 is still open at END (`C` opened FOR INPUT on line 50)",
+            ]
+        );
+    }
+
+    #[test]
+    fn computed_numbers_silence_closed_checks() {
+        let source = "10 OPEN \"A\" FOR RANDOM AS #N\n20 GET #1, 1: CLOSE\n";
+        assert_eq!(messages(source), []);
+    }
+
+    #[test]
+    fn hover_lists_the_opens_reaching_a_number() {
+        let source = "10 IF X THEN OPEN \"A\" FOR INPUT AS #1\n20 INPUT #1, A$\n";
+        let value = file_hover(source, 1, 10).unwrap();
+        assert_eq!(
+            value,
+            "**File #1**\n\n- `A` opened FOR INPUT on line 10\n\nMay also be closed here"
+        );
+    }
+}
@@ -0,0 +1,516 @@
+use std::collections::HashMap;
+
+use crate::dialect::Dialect;
+use crate::semantic_tokens::{lex_line, LexKind};
+
+/// A token of a statement, with its byte range in the source line
+#[derive(Debug, Clone)]
+pub struct Token {
+    pub start: usize,
+    pub end: usize,
+    pub kind: LexKind,
+    /// Uppercased token text
+    pub text: String,
+}
+
+impl Token {
+    pub fn is_keyword(&self, word: &str) -> bool {
+        self.kind == LexKind::Keyword && self.text == word
+    }
+
+    pub fn is_operator(&self, op: u8) -> bool {
+        self.kind == LexKind::Operator(op)
+    }
+}
+
+/// A single statement of the program
+#[derive(Debug)]
+pub struct Statement {
+    /// Source line index (0-based)
+    pub line: u32,
+    /// BASIC line number of the line holding the statement
+    pub number: Option<u32>,
+    /// Tokens of the statement; for IF this is the condition up to THEN
+    pub tokens: Vec<Token>,
+    /// Statements that can run next
+    pub successors: Vec<usize>,
+}
+
+impl Statement {
+    /// The keyword the statement starts with
+    pub fn keyword(&self) -> Option<&str> {
+        self.tokens
+            .first()
+            .filter(|t| t.kind == LexKind::Keyword)
+            .map(|t| t.text.as_str())
+    }
+
+    /// Arguments after the leading keyword, split at top-level commas.
+    /// An omitted argument is an empty slice.
+    pub fn arguments(&self) -> Vec<&[Token]> {
+        let tokens = match self.keyword() {
+            Some(_) => &self.tokens[1..],
+            None => &self.tokens[..],
+        };
+        if tokens.is_empty() {
+            return Vec::new();
+        }
+
+        let mut arguments = Vec::new();
+        let mut depth = 0i32;
+        let mut start = 0;
+        for (i, token) in tokens.iter().enumerate() {
+            match token.kind {
+                LexKind::Operator(b'(') => depth += 1,
+                LexKind::Operator(b')') => depth -= 1,
+                LexKind::Operator(b',') if depth == 0 => {
+                    arguments.push(&tokens[start..i]);
+                    start = i + 1;
+                }
+                _ => {}
+            }
+        }
+        arguments.push(&tokens[start..]);
+        arguments
+    }
+}
+
+/// Value of an argument that is a literal integer like `4`, `-1` or `&H1F`
+pub fn constant(tokens: &[Token]) -> Option<i64> {
+    let (negative, number) = match tokens {
+        [number] => (false, number),
+        [sign, number] if sign.is_operator(b'-') => (true, number),
+        _ => return None,
+    };
+    if number.kind != LexKind::Number {
+        return None;
+    }
+
+    let text = number.text.trim_end_matches(['%', '!', '#']);
+    let value = if let Some(hex) = text.strip_prefix("&H") {
+        i64::from_str_radix(hex, 16).ok()?
+    } else if let Some(octal) = text.strip_prefix("&O").or_else(|| text.strip_prefix('&')) {
+        i64::from_str_radix(octal, 8).ok()?
+    } else {
+        // BASICA rounds fractional arguments
+        text.replace('D', "E").parse::<f64>().ok()?.round() as i64
+    };
+    Some(if negative { -value } else { value })
+}
+
+/// Byte range covered by a run of tokens
+pub fn span(tokens: &[Token]) -> Option<(usize, usize)> {
+    Some((tokens.first()?.start, tokens.last()?.end))
+}
+
+/// A program split into statements, linked by control flow.
+///
+/// Lines run in source order. GOTO, GOSUB, ON...GOTO/GOSUB, IF...THEN...ELSE,
+/// FOR...NEXT and WHILE...WEND are followed; RETURN continues after every
+/// GOSUB, since the analysis doesn't track call sites. Error and event
+/// handlers are only entered through their own jumps.
+pub struct Program {
+    pub statements: Vec<Statement>,
+    /// First statement of each BASIC line number
+    pub lines: HashMap<u32, usize>,
+}
+
+/// Where control goes after a statement, before indices are known
+#[derive(Clone, Copy)]
+enum Edge {
+    Statement(usize),
+    /// The first statement of the following line
+    LineEnd,
+    Line(u32),
+    /// Back after every GOSUB
+    Return,
+}
+
+/// A statement and the constructs nested inside a line
+enum Item {
+    Simple(Vec<Token>),
+    If {
+        condition: Vec<Token>,
+        then: Vec<Item>,
+        otherwise: Vec<Item>,
+    },
+}
+
+/// ON <event> GOSUB sets up a trap rather than jumping
+const EVENT_TRAPS: &[&str] = &["ERROR", "KEY", "TIMER", "COM", "PEN", "STRIG", "PLAY"];
+
+#[derive(Default)]
+struct Builder {
+    statements: Vec<Statement>,
+    edges: Vec<Vec<Edge>>,
+    /// Index into `line_starts` of the line each statement is on
+    ordinals: Vec<usize>,
+    /// First statement of each line, in source order
+    line_starts: Vec<usize>,
+    /// Where each GOSUB continues once the subroutine returns, with its line ordinal
+    continuations: Vec<(usize, Edge)>,
+    /// BASIC line number of the line being built
+    number: Option<u32>,
+    for_stack: Vec<usize>,
+    while_stack: Vec<usize>,
+}
+
+impl Program {
+    pub fn build(source: &str) -> Self {
+        let mut builder = Builder::default();
+        let mut lines = HashMap::new();
+
+        for (line_idx, line) in source.lines().enumerate() {
+            let upper = line.to_ascii_uppercase();
+            let mut number = None;
+            let mut tokens = Vec::new();
+            for lexeme in lex_line(&upper, Dialect::Basica) {
+                match lexeme.kind {
+                    LexKind::LineNumber => number = upper[lexeme.start..lexeme.end].parse().ok(),
+                    LexKind::Comment => {}
+                    kind => tokens.push(Token {
+                        start: lexeme.start,
+                        end: lexeme.end,
+                        kind,
+                        text: upper[lexeme.start..lexeme.end].to_string(),
+                    }),
+                }
+            }
+            if number.is_none() && tokens.is_empty() {
+                continue;
+            }
+
+            let first = builder.statements.len();
+            builder.line_starts.push(first);
+            builder.number = number;
+            builder.emit_block(parse_block(&tokens), line_idx as u32);
+            // Keep a statement for lines holding only a comment, so jumps land somewhere
+            if builder.statements.len() == first {
+                builder.push(Vec::new(), line_idx as u32, vec![Edge::LineEnd]);
+            }
+            if let Some(number) = number {
+                lines.entry(number).or_insert(first);
+            }
+        }
+
+        let mut program = Program {
+            statements: builder.statements,
+            lines,
+        };
+        let resolve = |edge: Edge, ordinal: usize, lines: &HashMap<u32, usize>| match edge {
+            Edge::Statement(idx) => vec![idx],
+            Edge::LineEnd => builder
+                .line_starts
+                .get(ordinal + 1)
+                .copied()
+                .into_iter()
+                .collect(),
+            Edge::Line(number) => lines.get(&number).copied().into_iter().collect(),
+            Edge::Return => Vec::new(),
+        };
+
+        let mut returns = Vec::new();
+        for &(ordinal, edge) in &builder.continuations {
+            returns.extend(resolve(edge, ordinal, &program.lines));
+        }
+
+        for (idx, edges) in builder.edges.iter().enumerate() {
+            let mut successors = Vec::new();
+            for &edge in edges {
+                match edge {
+                    Edge::Return => successors.extend(&returns),
+                    edge => successors.extend(resolve(edge, builder.ordinals[idx], &program.lines)),
+                }
+            }
+            successors.sort_unstable();
+            successors.dedup();
+            program.statements[idx].successors = successors;
+        }
+
+        program
+    }
+
+    /// Propagate a state through the program from its first statement.
+    ///
+    /// `transfer` gives the state after a statement and `join` merges states
+    /// meeting at a statement. Returns the state on entry to each statement,
+    /// or `None` for statements that are never reached.
+    pub fn solve<S: Clone + PartialEq>(
+        &self,
+        entry: S,
+        transfer: impl Fn(&Statement, &S) -> S,
+        join: impl Fn(&S, &S) -> S,
+    ) -> Vec<Option<S>> {
+        let mut states: Vec<Option<S>> = vec![None; self.statements.len()];
+        if states.is_empty() {
+            return states;
+        }
+        states[0] = Some(entry);
+        let mut worklist = vec![0];
+
+        while let Some(idx) = worklist.pop() {
+            let Some(state) = &states[idx] else { continue };
+            let out = transfer(&self.statements[idx], state);
+            for &next in &self.statements[idx].successors {
+                let merged = match &states[next] {
+                    Some(old) => join(old, &out),
+                    None => out.clone(),
+                };
+                if states[next].as_ref() != Some(&merged) {
+                    states[next] = Some(merged);
+                    worklist.push(next);
+                }
+            }
+        }
+
+        states
+    }
+}
+
+impl Builder {
+    fn push(&mut self, tokens: Vec<Token>, line: u32, edges: Vec<Edge>) -> usize {
+        self.statements.push(Statement {
+            line,
+            number: self.number,
+            tokens,
+            successors: Vec::new(),
+        });
+        self.edges.push(edges);
+        self.ordinals.push(self.line_starts.len() - 1);
+        self.statements.len() - 1
+    }
+
+    /// Emit the statements of a block, returning the first one
+    fn emit_block(&mut self, items: Vec<Item>, line: u32) -> Option<usize> {
+        let entry = (!items.is_empty()).then_some(self.statements.len());
+        let count = items.len();
+
+        for (i, item) in items.into_iter().enumerate() {
+            let next = if i + 1 < count {
+                Edge::Statement(self.statements.len() + 1)
+            } else {
+                Edge::LineEnd
+            };
+            match item {
+                Item::Simple(tokens) => self.emit_statement(tokens, line, next),
+                Item::If {
+                    condition,
+                    then,
+                    otherwise,
+                } => {
+                    let idx = self.push(condition, line, Vec::new());
+                    let then_entry = self.emit_block(then, line);
+                    let else_entry = self.emit_block(otherwise, line);
+                    self.edges[idx] = vec![
+                        then_entry.map_or(Edge::LineEnd, Edge::Statement),
+                        else_entry.map_or(Edge::LineEnd, Edge::Statement),
+                    ];
+                }
+            }
+        }
+
+        entry
+    }
+
+    fn emit_statement(&mut self, tokens: Vec<Token>, line: u32, next: Edge) {
+        let targets = |from: usize| -> Vec<Edge> {
+            tokens[from..]
+                .iter()
+                .take_while(|t| t.kind == LexKind::Number || t.is_operator(b','))
+                .filter_map(|t| t.text.parse().ok())
+                .map(Edge::Line)
+                .collect()
+        };
+        let keyword = tokens
+            .first()
+            .filter(|t| t.kind == LexKind::Keyword)
+            .map(|t| t.text.clone());
+
+        let mut call = false;
+        let mut edges = match keyword.as_deref() {
+            // `THEN 100` / `ELSE 100`
+            None if tokens.first().is_some_and(|t| t.kind == LexKind::Number) => targets(0),
+            Some("GOTO") => targets(1),
+            Some("GOSUB") => {
+                call = true;
+                let mut edges = targets(1);
+                edges.push(next);
+                edges
+            }
+            Some("ON")
+                if !tokens
+                    .get(1)
+                    .is_some_and(|t| EVENT_TRAPS.contains(&t.text.as_str())) =>
+            {
+                let jump = tokens
+                    .iter()
+                    .position(|t| t.is_keyword("GOTO") || t.is_keyword("GOSUB"));
+                let mut edges = jump.map_or_else(Vec::new, |pos| targets(pos + 1));
+                call = jump.is_some_and(|pos| tokens[pos].text == "GOSUB");
+                edges.push(next);
+                edges
+            }
+            Some("RETURN") if tokens.len() > 1 => targets(1),
+            Some("RETURN") => vec![Edge::Return],
+            Some("RESUME") => targets(1)
+                .into_iter()
+                .filter(|edge| !matches!(edge, Edge::Line(0)))
+                .collect(),
+            Some("END") | Some("STOP") | Some("SYSTEM") | Some("RUN") => Vec::new(),
+            _ => vec![next],
+        };
+
+        let idx = self.statements.len();
+        match keyword.as_deref() {
+            Some("FOR") => self.for_stack.push(idx),
+            Some("NEXT") => {
+                // NEXT I, J closes two loops
+                let closes = tokens.iter().filter(|t| t.is_operator(b',')).count() + 1;
+                for _ in 0..closes {
+                    if let Some(start) = self.for_stack.pop() {
+                        edges.push(Edge::Statement(start));
+                    }
+                }
+            }
+            Some("WHILE") => self.while_stack.push(idx),
+            Some("WEND") => {
+                if let Some(start) = self.while_stack.pop() {
+                    edges.push(Edge::Statement(start));
+                    // Leaving the loop continues after the WEND
+                    self.edges[start].push(Edge::Statement(idx));
+                }
+            }
+            _ => {}
+        }
+
+        if call {
+            self.continuations.push((self.line_starts.len() - 1, next));
+        }
+        self.push(tokens, line, edges);
+    }
+}
+
+/// Split the tokens of a line into statements and IF constructs
+fn parse_block(tokens: &[Token]) -> Vec<Item> {
+    let mut items = Vec::new();
+    let mut pos = 0;
+
+    while pos < tokens.len() {
+        if tokens[pos].is_operator(b':') {
+            pos += 1;
+            continue;
+        }
+
+        if tokens[pos].is_keyword("IF") {
+            // IF cond THEN ... / IF cond GOTO n
+            let cond_end = (pos..tokens.len())
+                .find(|&i| tokens[i].is_keyword("THEN") || tokens[i].is_keyword("GOTO"))
+                .unwrap_or(tokens.len());
+            let then_start = if cond_end < tokens.len() && tokens[cond_end].text == "THEN" {
+                cond_end + 1
+            } else {
+                cond_end
+            };
+
+            // The ELSE belonging to this IF, skipping those of nested IFs
+            let mut depth = 0;
+            let mut else_pos = None;
+            for (i, token) in tokens.iter().enumerate().skip(then_start) {
+                if token.is_keyword("IF") {
+                    depth += 1;
+                } else if token.is_keyword("ELSE") {
+                    if depth == 0 {
+                        else_pos = Some(i);
+                        break;
+                    }
+                    depth -= 1;
+                }
+            }
+
+            let then_end = else_pos.unwrap_or(tokens.len());
+            items.push(Item::If {
+                condition: tokens[pos..cond_end].to_vec(),
+                then: parse_block(&tokens[then_start..then_end]),
+                otherwise: else_pos.map_or_else(Vec::new, |e| parse_block(&tokens[e + 1..])),
+            });
+            // IF takes the rest of the line
+            break;
+        }
+
+        let end = (pos..tokens.len())
+            .find(|&i| tokens[i].is_operator(b':'))
+            .unwrap_or(tokens.len());
+        items.push(Item::Simple(tokens[pos..end].to_vec()));
+        pos = end;
+    }
+
+    items
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Line numbers of the lines control can reach
+    fn reached(source: &str) -> Vec<u32> {
+        let program = Program::build(source);
+        let states = program.solve((), |_, _| (), |_, _| ());
+        let mut lines: Vec<u32> = program
+            .statements
+            .iter()
+            .zip(&states)
+            .filter(|(_, state)| state.is_some())
+            .filter_map(|(statement, _)| statement.number)
+            .collect();
+        lines.dedup();
+        lines
+    }
+
+    fn argument(text: &str) -> Option<i64> {
+        let program = Program::build(&format!("10 PRINT {}", text));
+        constant(program.statements[0].arguments()[0])
+    }
+
+    #[test]
+    fn constants_are_literal_integers() {
+        assert_eq!(argument("4"), Some(4));
+        assert_eq!(argument("-1"), Some(-1));
+        assert_eq!(argument("&H1F"), Some(31));
+        assert_eq!(argument("&O17"), Some(15));
+        assert_eq!(argument("2.5"), Some(3));
+        assert_eq!(argument("N"), None);
+        assert_eq!(argument("1 + 1"), None);
+    }
+
+    #[test]
+    fn arguments_split_at_top_level_commas() {
+        let program = Program::build("10 LOCATE , A(1, 2), 3\n");
+        let lengths: Vec<usize> = program.statements[0]
+            .arguments()
+            .iter()
+            .map(|a| a.len())
+            .collect();
+        assert_eq!(lengths, [0, 6, 1]);
+    }
+
+    #[test]
+    fn jumps_and_branches_are_followed() {
+        let source = "10 GOTO 30\n\
+            20 END\n\
+            30 IF X THEN 50 ELSE 60\n\
+            40 PRINT\n\
+            50 GOSUB 70: END\n\
+            60 ON X GOTO 80\n\
+            70 RETURN\n\
+            80 END\n\
+            90 PRINT\n";
+        assert_eq!(reached(source), [10, 30, 50, 60, 70, 80]);
+    }
+
+    #[test]
+    fn unreached_statements_have_no_state() {
+        let program = Program::build("10 A = 1: GOTO 30\n20 A = 2\n30 PRINT A\n");
+        let states = program.solve(0, |_, n| n + 1, |a, b| *a.max(b));
+        assert_eq!(states, [Some(0), Some(1), None, Some(2)]);
+    }
+}
@@ -0,0 +1,145 @@
+use tower_lsp::lsp_types::*;
+
+use crate::config::{FormatConfig, KeywordCase, OperatorSpacing};
+use crate::dialect::Dialect;
+use crate::semantic_tokens::{lex_line, LexKind, Lexeme};
+
+/// Operators that can sit between two operands
+const BINARY_OPERATORS: &[u8] = b"+-*/^=<>";
+
+/// Format a program, returning one edit per changed line
+pub fn format_edits(source: &str, style: &FormatConfig, dialect: Dialect) -> Vec<TextEdit> {
+    let mut edits = Vec::new();
+
+    for (line_idx, line) in source.lines().enumerate() {
+        let new_text = format_line(line, style, dialect);
+        if new_text != line {
+            edits.push(TextEdit {
+                range: Range {
+                    start: Position {
+                        line: line_idx as u32,
+                        character: 0,
+                    },
+                    end: Position {
+                        line: line_idx as u32,
+                        character: line.len() as u32,
+                    },
+                },
+                new_text,
+            });
+        }
+    }
+
+    edits
+}
+
+/// Format a single source line.
+///
+/// Keywords are recased and the whitespace between tokens is collapsed to a
+/// single space (or set by the operator spacing). Strings, comments and
+/// DATA items are copied verbatim, and crunched code like `FORI=1TO9` stays
+/// crunched.
+pub fn format_line(line: &str, style: &FormatConfig, dialect: Dialect) -> String {
+    let upper = line.to_ascii_uppercase();
+    let lexemes = lex_line(&upper, dialect);
+    let Some(first) = lexemes.first() else {
+        return line.trim_end().to_string();
+    };
+
+    let mut out = String::with_capacity(line.len());
+    // Unnumbered lines keep their indentation
+    if first.kind != LexKind::LineNumber {
+        out.push_str(&line[..first.start]);
+    }
+
+    let mut prev: Option<(&Lexeme, bool)> = None;
+    let mut i = 0;
+    while i < lexemes.len() {
+        let lexeme = &lexemes[i];
+        let binary = is_binary(&lexemes, i);
+
+        if let Some((prev_lexeme, prev_binary)) = prev {
+            let gap = &line[prev_lexeme.end..lexeme.start];
+            if !gap.trim().is_empty() {
+                // Something the lexer skipped; leave it alone
+                out.push_str(gap);
+            } else if prev_lexeme.kind == LexKind::LineNumber {
+                out.push(' ');
+            } else if (binary || prev_binary) && style.operator_spacing != OperatorSpacing::Preserve
+            {
+                // `<>` and `<=` stay together
+                if style.operator_spacing == OperatorSpacing::Spaced && !(binary && prev_binary) {
+                    out.push(' ');
+                }
+            } else if !gap.is_empty() {
+                out.push(' ');
+            }
+        }
+
+        let text = &line[lexeme.start..lexeme.end];
+        match lexeme.kind {
+            LexKind::Keyword => out.push_str(&recase(text, style.keyword_case)),
+            LexKind::Comment if upper[lexeme.start..].starts_with("REM") => {
+                out.push_str(&recase(&text[..3], style.keyword_case));
+                out.push_str(text[3..].trim_end());
+            }
+            LexKind::Comment => out.push_str(text.trim_end()),
+            _ => out.push_str(text),
+        }
+        prev = Some((lexeme, binary));
+        i += 1;
+
+        // DATA items are taken literally, spaces and all
+        if lexeme.kind == LexKind::Keyword && &upper[lexeme.start..lexeme.end] == "DATA" {
+            let next = lexemes[i..]
+                .iter()
+                .position(|l| l.kind == LexKind::Operator(b':'))
+                .map_or(lexemes.len(), |p| i + p);
+            let end = lexemes.get(next).map_or(line.len(), |l| l.start);
+            let items = &line[lexeme.end..end];
+            out.push_str(if next == lexemes.len() {
+                items.trim_end()
+            } else {
+                items
+            });
+            if let Some(colon) = lexemes.get(next) {
+                out.push_str(&line[colon.start..colon.end]);
+                prev = Some((colon, false));
+            }
+            i = next + 1;
+        }
+    }
+
+    out
+}
+
+/// Whether the operator at `idx` joins two operands; a sign after another
+/// operator, a keyword or the start of a statement doesn't
+fn is_binary(lexemes: &[Lexeme], idx: usize) -> bool {
+    let LexKind::Operator(op) = lexemes[idx].kind else {
+        return false;
+    };
+    if !BINARY_OPERATORS.contains(&op) {
+        return false;
+    }
+    match idx.checked_sub(1).map(|p| lexemes[p].kind) {
+        Some(LexKind::Identifier) | Some(LexKind::Number) | Some(LexKind::String) => true,
+        Some(LexKind::Operator(b')')) => true,
+        // `<>`, `>=` and friends
+        Some(LexKind::Operator(prev)) => {
+            matches!(
+                (prev, op),
+                (b'<', b'>' | b'=') | (b'>', b'<' | b'=') | (b'=', b'<' | b'>')
+            )
+        }
+        _ => false,
+    }
+}
+
+fn recase(word: &str, case: KeywordCase) -> String {
+    match case {
+        KeywordCase::Upper => word.to_ascii_uppercase(),
+        KeywordCase::Lower => word.to_ascii_lowercase(),
+        KeywordCase::Preserve => word.to_string(),
+    }
+}
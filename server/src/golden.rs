@@ -0,0 +1,343 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicBool;
+use std::sync::{Arc, Mutex};
+use tower_lsp::lsp_types::*;
+
+use crate::codes;
+use crate::config::RunConfig;
+use crate::diagnostics::related;
+use crate::renumber::utf16_len;
+use crate::runner;
+use crate::tokenized;
+
+/// A program with the output it should print: `PROG.BAS` next to
+/// `PROG.out`, and optionally `PROG.in` to answer INPUT
+pub struct Fixture {
+    pub program: PathBuf,
+    pub input: Option<PathBuf>,
+    pub expected: PathBuf,
+}
+
+/// How a fixture's run compared with its expected output
+pub struct TestResult {
+    pub fixture: Fixture,
+    pub passed: bool,
+    /// Why the test failed, if it did
+    pub message: Option<String>,
+    pub mismatch: Option<Mismatch>,
+}
+
+/// The first output line that differs, numbered from 1. A side is `None`
+/// when that output ended early
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Mismatch {
+    pub line: usize,
+    pub expected: Option<String>,
+    pub actual: Option<String>,
+}
+
+/// Directories never searched for fixtures, besides hidden ones like `.git`
+const SKIPPED_DIRECTORIES: &[&str] = &["node_modules", "target", "vendor"];
+
+/// How many directories deep fixtures are searched for
+const MAX_DEPTH: usize = 16;
+
+/// Find fixtures under a directory, or the fixture for a single program
+pub fn discover(path: &Path) -> Vec<Fixture> {
+    let mut fixtures = Vec::new();
+    if path.is_dir() {
+        collect(path, 0, &mut fixtures);
+    } else if let Some(fixture) = fixture_for(path) {
+        fixtures.push(fixture);
+    }
+    fixtures
+}
+
+/// Add the fixtures in a directory and below it. Symlinked directories
+/// aren't followed, so a link back up the tree can't loop
+fn collect(dir: &Path, depth: usize, fixtures: &mut Vec<Fixture>) {
+    let Ok(entries) = std::fs::read_dir(dir) else {
+        return;
+    };
+    let mut entries: Vec<PathBuf> = entries.filter_map(|e| e.ok().map(|e| e.path())).collect();
+    entries.sort();
+    for entry in entries {
+        let Ok(metadata) = std::fs::symlink_metadata(&entry) else {
+            continue;
+        };
+        if metadata.is_dir() {
+            let name = entry.file_name().and_then(|n| n.to_str()).unwrap_or("");
+            let skipped = name.starts_with('.') || SKIPPED_DIRECTORIES.contains(&name);
+            if !skipped && depth < MAX_DEPTH {
+                collect(&entry, depth + 1, fixtures);
+            }
+        } else if let Some(fixture) = fixture_for(&entry) {
+            fixtures.push(fixture);
+        }
+    }
+}
+
+/// The fixture for a program, if it has an expected output file
+pub fn fixture_for(program: &Path) -> Option<Fixture> {
+    let is_program = program
+        .extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("bas"));
+    if !is_program {
+        return None;
+    }
+    Some(Fixture {
+        program: program.to_path_buf(),
+        input: sibling(program, "in"),
+        expected: sibling(program, "out")?,
+    })
+}
+
+/// `PROG.<extension>` next to a program, in either case
+fn sibling(program: &Path, extension: &str) -> Option<PathBuf> {
+    [extension.to_string(), extension.to_ascii_uppercase()]
+        .into_iter()
+        .map(|ext| program.with_extension(ext))
+        .find(|path| path.is_file())
+}
+
+/// Run a fixture headlessly and compare what it prints, including the
+/// message it stops with, against the expected output
+pub fn run(fixture: Fixture, limits: &RunConfig) -> TestResult {
+    match try_run(&fixture, limits) {
+        Ok(mismatch) => TestResult {
+            passed: mismatch.is_none(),
+            message: mismatch.as_ref().map(|m| describe(m, &fixture.expected)),
+            mismatch,
+            fixture,
+        },
+        Err(message) => TestResult {
+            fixture,
+            passed: false,
+            message: Some(message),
+            mismatch: None,
+        },
+    }
+}
+
+fn try_run(fixture: &Fixture, limits: &RunConfig) -> Result<Option<Mismatch>, String> {
+    let source = tokenized::read_program(&fixture.program)?;
+    let input = match &fixture.input {
+        Some(path) => read_text(path)?.lines().map(str::to_string).collect(),
+        None => Vec::new(),
+    };
+    let expected = read_text(&fixture.expected)?;
+
+    let printed = Arc::new(Mutex::new(String::new()));
+    let output = {
+        let printed = printed.clone();
+        Box::new(move |text: &str| printed.lock().unwrap().push_str(text))
+    };
+    let (outcome, _) = runner::run(&source, input, limits, output, &AtomicBool::new(false));
+
+    let mut actual = printed.lock().unwrap().clone();
+    if let Some(message) = outcome.message(limits) {
+        if !actual.is_empty() && !actual.ends_with('\n') {
+            actual.push('\n');
+        }
+        actual.push_str(&message);
+    }
+    Ok(compare(&expected, &actual))
+}
+
+fn read_text(path: &Path) -> Result<String, String> {
+    std::fs::read(path)
+        .map(|bytes| String::from_utf8_lossy(&bytes).into_owned())
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Compare outputs line by line, ignoring line endings, trailing spaces
+/// (PRINT leaves one after numbers), trailing blank lines and a DOS
+/// end-of-file mark
+fn compare(expected: &str, actual: &str) -> Option<Mismatch> {
+    let expected = normalize(expected);
+    let actual = normalize(actual);
+
+    (0..expected.len().max(actual.len()))
+        .find(|&idx| expected.get(idx) != actual.get(idx))
+        .map(|idx| Mismatch {
+            line: idx + 1,
+            expected: expected.get(idx).cloned(),
+            actual: actual.get(idx).cloned(),
+        })
+}
+
+fn normalize(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = text
+        .trim_end_matches('\u{1a}')
+        .lines()
+        .map(|line| line.trim_end().to_string())
+        .collect();
+    while lines.last().is_some_and(|line| line.is_empty()) {
+        lines.pop();
+    }
+    lines
+}
+
+fn describe(mismatch: &Mismatch, expected_path: &Path) -> String {
+    let name = expected_path
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    match (&mismatch.expected, &mismatch.actual) {
+        (Some(expected), Some(actual)) => format!(
+            "Output line {} differs from {}: expected \"{}\", got \"{}\"",
+            mismatch.line, name, expected, actual
+        ),
+        (Some(expected), None) => format!(
+            "Output ended before line {} of {}: \"{}\"",
+            mismatch.line, name, expected
+        ),
+        (None, Some(actual)) => format!(
+            "Output has more lines than {}, starting with line {}: \"{}\"",
+            name, mismatch.line, actual
+        ),
+        (None, None) => format!("Output differs from {}", name),
+    }
+}
+
+/// A failed test as a diagnostic on the program's first line, pointing at
+/// the expected output line
+pub fn diagnostic(result: &TestResult, source: &str) -> Option<Diagnostic> {
+    let message = result.message.clone().filter(|_| !result.passed)?;
+    let first_line = source.lines().next().map_or(0, |l| utf16_len(l.trim_end()));
+
+    let expected_line = result
+        .mismatch
+        .as_ref()
+        .and_then(|m| Some((m.line as u32 - 1, utf16_len(m.expected.as_ref()?))));
+    let related_information = match (Url::from_file_path(&result.fixture.expected), expected_line) {
+        (Ok(uri), Some((line, len))) => Some(vec![related(&uri, line, 0, len, "Expected output")]),
+        _ => None,
+    };
+
+    let mut diagnostic = Diagnostic {
+        range: Range {
+            start: Position {
+                line: 0,
+                character: 0,
+            },
+            end: Position {
+                line: 0,
+                character: first_line,
+            },
+        },
+        severity: Some(DiagnosticSeverity::ERROR),
+        source: Some("basica".to_string()),
+        message,
+        related_information,
+        ..Default::default()
+    };
+    if let Some(check) = codes::find("golden-output") {
+        check.tag(&mut diagnostic);
+    }
+    Some(diagnostic)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A scratch directory holding `files`, unique to this test
+    fn scratch(name: &str, files: &[&str]) -> PathBuf {
+        let dir = std::env::temp_dir()
+            .join(format!("basica-golden-{}", std::process::id()))
+            .join(name);
+        for file in files {
+            let path = dir.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, "").unwrap();
+        }
+        dir
+    }
+
+    fn names(fixtures: &[Fixture], root: &Path) -> Vec<String> {
+        fixtures
+            .iter()
+            .map(|f| {
+                f.program
+                    .strip_prefix(root)
+                    .unwrap()
+                    .to_string_lossy()
+                    .replace('\\', "/")
+            })
+            .collect()
+    }
+
+    #[test]
+    fn fixtures_need_an_expected_output() {
+        let root = scratch(
+            "discover",
+            &[
+                "A.BAS",
+                "A.OUT",
+                "b.bas",
+                "b.out",
+                "b.in",
+                "lonely.bas",
+                "sub/c.bas",
+                "sub/c.out",
+                ".git/d.bas",
+                ".git/d.out",
+                "node_modules/e.bas",
+                "node_modules/e.out",
+            ],
+        );
+        let fixtures = discover(&root);
+        assert_eq!(names(&fixtures, &root), ["A.BAS", "b.bas", "sub/c.bas"]);
+        assert!(fixtures[0].input.is_none());
+        assert_eq!(fixtures[1].input, Some(root.join("b.in")));
+
+        let single = discover(&root.join("b.bas"));
+        assert_eq!(names(&single, &root), ["b.bas"]);
+        assert!(discover(&root.join("lonely.bas")).is_empty());
+    }
+
+    #[test]
+    fn outputs_compare_ignoring_trailing_space() {
+        assert!(compare("HELLO \r\n 3 \r\n\r\n\u{1a}", "HELLO\n 3\n").is_none());
+
+        let mismatch = compare("A\nB\n", "A\nC\n").unwrap();
+        assert_eq!(mismatch.line, 2);
+        assert_eq!(
+            describe(&mismatch, Path::new("T.out")),
+            "Output line 2 differs from T.out: expected \"B\", got \"C\""
+        );
+
+        let mismatch = compare("A\nB\n", "A\n").unwrap();
+        assert_eq!(
+            describe(&mismatch, Path::new("T.out")),
+            "Output ended before line 2 of T.out: \"B\""
+        );
+    }
+
+    #[test]
+    fn failures_point_at_the_expected_line() {
+        let root = scratch("diagnostic", &["T.out"]);
+        let result = TestResult {
+            fixture: Fixture {
+                program: root.join("T.bas"),
+                input: None,
+                expected: root.join("T.out"),
+            },
+            passed: false,
+            message: Some("Output line 2 differs".to_string()),
+            mismatch: Some(Mismatch {
+                line: 2,
+                expected: Some("é!".to_string()),
+                actual: None,
+            }),
+        };
+
+        let diagnostic = diagnostic(&result, "10 PRINT \"é\"\n").unwrap();
+        assert_eq!(diagnostic.range.end.character, 12);
+        let related = &diagnostic.related_information.unwrap()[0];
+        assert_eq!(related.location.range.start.line, 1);
+        assert_eq!(related.location.range.end.character, 2);
+    }
+}
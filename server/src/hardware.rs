@@ -0,0 +1,371 @@
+use tower_lsp::lsp_types::*;
+
+use crate::codes;
+use crate::flow::{self, Program, Statement, Token};
+
+/// Screen modes basica implements (the CGA modes of IBM BASICA)
+const SUPPORTED_SCREEN_MODES: &[i64] = &[0, 1, 2];
+
+/// Statements that only work in a graphics mode
+const GRAPHICS_STATEMENTS: &[&str] = &["PSET", "PRESET", "LINE", "CIRCLE", "PAINT", "DRAW"];
+
+/// Frequencies SOUND accepts, in Hz
+const SOUND_FREQUENCIES: (i64, i64) = (37, 32767);
+
+/// Text rows on every screen mode
+const SCREEN_ROWS: i64 = 25;
+
+/// Display settings on entry to a statement; `None` when paths disagree
+/// or the value isn't a constant
+#[derive(Clone, PartialEq)]
+struct Display {
+    screen: Option<i64>,
+    width: Option<i64>,
+}
+
+/// Check constant statement arguments against the hardware's ranges and
+/// the SCREEN mode and WIDTH in effect at that point of the program
+pub fn check(source: &str) -> Vec<Diagnostic> {
+    let program = Program::build(source);
+    let states = program.solve(
+        Display {
+            screen: Some(0),
+            width: Some(80),
+        },
+        transfer,
+        |a, b| Display {
+            screen: a.screen.filter(|s| b.screen == Some(*s)),
+            width: a.width.filter(|w| b.width == Some(*w)),
+        },
+    );
+
+    let mut diagnostics = Vec::new();
+    for (statement, state) in program.statements.iter().zip(&states) {
+        check_statement(statement, state.as_ref(), &mut diagnostics);
+    }
+    diagnostics
+}
+
+/// Display settings after a statement runs
+fn transfer(statement: &Statement, state: &Display) -> Display {
+    let mut state = state.clone();
+    let arguments = statement.arguments();
+
+    match statement.keyword() {
+        Some("SCREEN") => {
+            // SCREEN ,1 keeps the mode
+            if let Some(mode) = arguments.first().filter(|a| !a.is_empty()) {
+                state.screen = flow::constant(mode).filter(|m| SUPPORTED_SCREEN_MODES.contains(m));
+                match state.screen {
+                    Some(1) => state.width = Some(40),
+                    Some(2) => state.width = Some(80),
+                    Some(_) => {}
+                    None => state.width = None,
+                }
+            }
+        }
+        Some("WIDTH") if is_screen_width(&arguments) => {
+            state.width = arguments.first().and_then(|a| flow::constant(a));
+            // Changing the width switches between the two graphics modes
+            state.screen = match (state.screen, state.width) {
+                (Some(1), Some(80)) => Some(2),
+                (Some(2), Some(40)) => Some(1),
+                (screen, Some(_)) => screen,
+                (Some(0), None) => Some(0),
+                _ => None,
+            };
+        }
+        _ => {}
+    }
+
+    state
+}
+
+/// WIDTH n sets the screen; WIDTH #f, WIDTH "dev:" and WIDTH LPRINT don't
+fn is_screen_width(arguments: &[&[Token]]) -> bool {
+    arguments.first().is_some_and(|a| {
+        a.first().is_some_and(|t| {
+            !t.is_operator(b'#') && !t.is_keyword("LPRINT") && !t.text.starts_with('"')
+        })
+    })
+}
+
+fn check_statement(
+    statement: &Statement,
+    state: Option<&Display>,
+    diagnostics: &mut Vec<Diagnostic>,
+) {
+    let arguments = statement.arguments();
+    let keyword = match statement.keyword() {
+        Some(keyword) => keyword,
+        None => return,
+    };
+    let mut report = |check: &str, tokens: &[Token], severity, message: String| {
+        if let Some(span) = flow::span(tokens) {
+            diagnostics.push(diagnostic(check, statement.line, span, severity, message));
+        }
+    };
+
+    match keyword {
+        "SCREEN" => {
+            if let Some(mode) = arguments.first() {
+                if let Some(value) = flow::constant(mode) {
+                    if !SUPPORTED_SCREEN_MODES.contains(&value) {
+                        report(
+                            "hardware-range",
+                            mode,
+                            DiagnosticSeverity::ERROR,
+                            format!("SCREEN {} is not supported (use 0, 1 or 2)", value),
+                        );
+                    }
+                }
+            }
+        }
+        "SOUND" => {
+            if let Some(frequency) = arguments.first() {
+                let (min, max) = SOUND_FREQUENCIES;
+                if let Some(value) = flow::constant(frequency).filter(|f| *f < min || *f > max) {
+                    report(
+                        "hardware-range",
+                        frequency,
+                        DiagnosticSeverity::ERROR,
+                        format!(
+                            "SOUND frequency {} is out of range ({} to {})",
+                            value, min, max
+                        ),
+                    );
+                }
+            }
+        }
+        "WIDTH" if is_screen_width(&arguments) => {
+            if let Some(value) = flow::constant(arguments[0]).filter(|w| *w != 40 && *w != 80) {
+                report(
+                    "hardware-range",
+                    arguments[0],
+                    DiagnosticSeverity::ERROR,
+                    format!("WIDTH {} is not supported (use 40 or 80)", value),
+                );
+            }
+        }
+        _ => {}
+    }
+
+    // The rest depends on the display settings at this point
+    let Some(state) = state else { return };
+
+    match keyword {
+        "COLOR" => {
+            let Some(screen) = state.screen else { return };
+            let limits: &[(&str, i64)] = match screen {
+                0 => &[("foreground", 31), ("background", 7), ("border", 15)],
+                1 => &[("background", 15), ("palette", 255)],
+                _ => {
+                    report(
+                        "color-range",
+                        &statement.tokens[..1],
+                        DiagnosticSeverity::WARNING,
+                        format!("COLOR is not allowed in SCREEN {}", screen),
+                    );
+                    return;
+                }
+            };
+            for (i, argument) in arguments.iter().enumerate() {
+                let Some(value) = flow::constant(argument) else {
+                    continue;
+                };
+                match limits.get(i) {
+                    Some(&(name, max)) if value < 0 || value > max => report(
+                        "color-range",
+                        argument,
+                        DiagnosticSeverity::WARNING,
+                        format!(
+                            "COLOR {} {} is out of range for SCREEN {} (0 to {})",
+                            name, value, screen, max
+                        ),
+                    ),
+                    Some(_) => {}
+                    None => report(
+                        "color-range",
+                        argument,
+                        DiagnosticSeverity::WARNING,
+                        format!(
+                            "COLOR takes at most {} arguments in SCREEN {}",
+                            limits.len(),
+                            screen
+                        ),
+                    ),
+                }
+            }
+        }
+        "LOCATE" => {
+            if let Some(row) = arguments.first() {
+                if let Some(value) = flow::constant(row).filter(|r| *r < 1 || *r > SCREEN_ROWS) {
+                    report(
+                        "locate-range",
+                        row,
+                        DiagnosticSeverity::WARNING,
+                        format!(
+                            "LOCATE row {} is out of range (1 to {})",
+                            value, SCREEN_ROWS
+                        ),
+                    );
+                }
+            }
+            if let (Some(column), Some(width)) = (arguments.get(1), state.width) {
+                if let Some(value) = flow::constant(column).filter(|c| *c < 1 || *c > width) {
+                    report(
+                        "locate-range",
+                        column,
+                        DiagnosticSeverity::WARNING,
+                        format!(
+                            "LOCATE column {} is beyond WIDTH {} (1 to {})",
+                            value, width, width
+                        ),
+                    );
+                }
+            }
+        }
+        _ if state.screen == Some(0) && GRAPHICS_STATEMENTS.contains(&keyword) => {
+            // LINE INPUT reads a line rather than drawing one
+            if keyword == "LINE"
+                && statement
+                    .tokens
+                    .get(1)
+                    .is_some_and(|t| t.is_keyword("INPUT"))
+            {
+                return;
+            }
+            report(
+                "text-mode-graphics",
+                &statement.tokens[..1],
+                DiagnosticSeverity::WARNING,
+                format!("{} needs a graphics mode; SCREEN 0 is text only", keyword),
+            );
+        }
+        _ => {}
+    }
+}
+
+/// A diagnostic tagged with the code of the check that found it
+fn diagnostic(
+    check: &str,
+    line: u32,
+    (start, end): (usize, usize),
+    severity: DiagnosticSeverity,
+    message: String,
+) -> Diagnostic {
+    let mut diagnostic = Diagnostic {
+        range: Range {
+            start: Position {
+                line,
+                character: start as u32,
+            },
+            end: Position {
+                line,
+                character: end as u32,
+            },
+        },
+        severity: Some(severity),
+        source: Some("basica".to_string()),
+        message,
+        ..Default::default()
+    };
+    if let Some(check) = codes::find(check) {
+        check.tag(&mut diagnostic);
+    }
+    diagnostic
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(source: &str) -> Vec<(u32, String)> {
+        check(source)
+            .into_iter()
+            .map(|d| (d.range.start.line, d.message))
+            .collect()
+    }
+
+    #[test]
+    fn screen_mode_follows_gosub_and_goto() {
+        let source = "10 SCREEN 2\n\
+            20 GOSUB 100\n\
+            30 SCREEN 0: GOTO 50\n\
+            40 PSET (1,1)\n\
+            50 PSET (1,1)\n\
+            60 END\n\
+            100 COLOR 1: RETURN\n";
+        assert_eq!(
+            messages(source),
+            [
+                (
+                    4,
+                    "PSET needs a graphics mode; SCREEN 0 is text only".to_string()
+                ),
+                (6, "COLOR is not allowed in SCREEN 2".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn modes_that_disagree_are_not_checked() {
+        // COLOR 1 is fine in SCREEN 1 but not in SCREEN 2
+        let source = "10 SCREEN 1: GOSUB 100\n\
+            20 SCREEN 2: GOSUB 100\n\
+            30 END\n\
+            100 COLOR 1: LOCATE 1, 60: RETURN\n";
+        assert_eq!(messages(source), []);
+    }
+
+    #[test]
+    fn constants_outside_the_hardware_are_errors() {
+        let errors: Vec<String> = check("10 SCREEN 7: SOUND 20, 1: WIDTH 60: SOUND 440, 1\n")
+            .into_iter()
+            .inspect(|d| assert_eq!(d.severity, Some(DiagnosticSeverity::ERROR)))
+            .map(|d| d.message)
+            .collect();
+        assert_eq!(
+            errors,
+            [
+                "SCREEN 7 is not supported (use 0, 1 or 2)",
+                "SOUND frequency 20 is out of range (37 to 32767)",
+                "WIDTH 60 is not supported (use 40 or 80)",
+            ]
+        );
+    }
+
+    #[test]
+    fn color_arguments_depend_on_the_mode() {
+        let source = "10 COLOR 31, 8, 1, 2\n20 SCREEN 1: COLOR 3, 1\n30 COLOR 16\n";
+        assert_eq!(
+            messages(source),
+            [
+                (
+                    0,
+                    "COLOR background 8 is out of range for SCREEN 0 (0 to 7)".to_string()
+                ),
+                (0, "COLOR takes at most 3 arguments in SCREEN 0".to_string()),
+                (
+                    2,
+                    "COLOR background 16 is out of range for SCREEN 1 (0 to 15)".to_string()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn locate_follows_width() {
+        let source = "10 LOCATE 26, 80\n20 WIDTH 40: LOCATE 1, 41\n30 LINE INPUT A$\n";
+        assert_eq!(
+            messages(source),
+            [
+                (0, "LOCATE row 26 is out of range (1 to 25)".to_string()),
+                (
+                    1,
+                    "LOCATE column 41 is beyond WIDTH 40 (1 to 40)".to_string()
+                ),
+            ]
+        );
+    }
+}
@@ -1,24 +1,89 @@
+use basica::lexer::is_keyword;
 use tower_lsp::lsp_types::*;
 
-/// Get hover documentation for keyword/function at cursor position
-pub fn get_hover(source: &str, position: Position) -> Option<Hover> {
+use crate::definition;
+use crate::dialect::{self, Dialect};
+use crate::files;
+use crate::minilang::{self, UsingField, UsingValue};
+use crate::references;
+use crate::renumber::{leading_line_number, line_references};
+
+/// Number of lines shown after a jump target in a line-number hover
+const PREVIEW_LINES: usize = 4;
+
+/// Maximum number of notes listed in a PLAY string hover
+const MAX_PLAY_NOTES: usize = 24;
+
+/// Sample values shown in a PRINT USING hover
+const USING_SAMPLES: &[f64] = &[0.5, 12.345, -42.0, 1234.5678, 9876543.21];
+
+/// Get hover information for the symbol at cursor position.
+///
+/// Keywords and functions show their documentation, line-number references
+/// preview the target line, `FNx` calls show the DEF FN, and variables show
+/// their type, dimensions, definition and reference count.
+pub fn get_hover(source: &str, position: Position, dialect: Dialect) -> Option<Hover> {
     let lines: Vec<&str> = source.lines().collect();
     let line = lines.get(position.line as usize)?;
-    let word = get_word_at_position(line, position.character as usize)?;
 
-    let doc = get_documentation(&word.to_uppercase())?;
+    // PLAY, DRAW and PRINT USING strings get a decoded preview
+    if let Some((start, end)) = string_at_position(line, position.character as usize) {
+        let value = string_hover(line, start, end)?;
+        return Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value,
+            }),
+            range: None,
+        });
+    }
+
+    if let Some(start) = file_number_at(line, position.character as usize) {
+        let value = files::file_hover(source, position.line, start)?;
+        return Some(Hover {
+            contents: HoverContents::Markup(MarkupContent {
+                kind: MarkupKind::Markdown,
+                value,
+            }),
+            range: None,
+        });
+    }
+
+    let (start, word) = get_word_at_position(line, position.character as usize)?;
+    let upper = word.to_uppercase();
+
+    let value = if let Ok(target) = word.parse::<u32>() {
+        line_reference_hover(&lines, line, start, target)?
+    } else if let Some(name) = user_function_name(line, start, &upper) {
+        user_function_hover(source, &name)?
+    } else if let Some(doc) = get_documentation(&upper) {
+        if dialect.supports(&upper) {
+            doc.to_string()
+        } else {
+            format!(
+                "{}\n\n*{} is not available in {}*",
+                doc,
+                dialect::construct_name(&upper),
+                dialect.name()
+            )
+        }
+    } else if !is_keyword(&upper) {
+        variable_hover(source, &upper)?
+    } else {
+        return None;
+    };
 
     Some(Hover {
         contents: HoverContents::Markup(MarkupContent {
             kind: MarkupKind::Markdown,
-            value: doc.to_string(),
+            value,
         }),
         range: None,
     })
 }
 
 /// Get word at cursor position (including $ suffix for string functions)
-fn get_word_at_position(line: &str, char_pos: usize) -> Option<&str> {
+fn get_word_at_position(line: &str, char_pos: usize) -> Option<(usize, &str)> {
     let bytes = line.as_bytes();
     let char_pos = char_pos.min(bytes.len());
 
@@ -45,12 +110,465 @@ fn get_word_at_position(line: &str, char_pos: usize) -> Option<&str> {
     }
 
     if start < end {
-        Some(&line[start..end])
+        Some((start, &line[start..end]))
+    } else {
+        None
+    }
+}
+
+/// Find the start of the digits of a `#n` file number under the cursor
+fn file_number_at(line: &str, char_pos: usize) -> Option<usize> {
+    let bytes = line.as_bytes();
+    let mut pos = char_pos.min(bytes.len());
+    if bytes.get(pos) == Some(&b'#') {
+        pos += 1;
+        while bytes.get(pos) == Some(&b' ') {
+            pos += 1;
+        }
+    }
+    if !bytes.get(pos).is_some_and(u8::is_ascii_digit) {
+        return None;
+    }
+
+    let start = bytes[..pos]
+        .iter()
+        .rposition(|b| !b.is_ascii_digit())
+        .map_or(0, |p| p + 1);
+    line[..start].trim_end().ends_with('#').then_some(start)
+}
+
+/// Find the string literal (including quotes) containing the cursor
+fn string_at_position(line: &str, char_pos: usize) -> Option<(usize, usize)> {
+    let bytes = line.as_bytes();
+    let mut pos = 0;
+
+    while pos < bytes.len() {
+        match bytes[pos] {
+            b'"' => {
+                let start = pos;
+                pos += 1;
+                while pos < bytes.len() && bytes[pos] != b'"' {
+                    pos += 1;
+                }
+                let end = (pos + 1).min(bytes.len());
+                if char_pos >= start && char_pos < end {
+                    return Some((start, end));
+                }
+                pos = end;
+            }
+            // Nothing after a comment is code
+            b'\'' => return None,
+            _ => pos += 1,
+        }
+    }
+
+    None
+}
+
+/// Decode the string literal at `start..end` if it follows PLAY, DRAW or USING
+fn string_hover(line: &str, start: usize, end: usize) -> Option<String> {
+    let before = line[..start].trim_end().to_uppercase();
+    let content = line[start + 1..end].trim_end_matches('"');
+
+    if before.ends_with("PLAY") {
+        Some(play_hover(content))
+    } else if before.ends_with("DRAW") {
+        Some(draw_hover(content))
+    } else if before.ends_with("USING") {
+        Some(using_hover(content))
     } else {
         None
     }
 }
 
+/// List the notes of a PLAY string
+fn play_hover(content: &str) -> String {
+    let notes = minilang::parse_play(content);
+    let mut value = first_paragraph("PLAY");
+
+    if notes.is_empty() {
+        value.push_str("\n\nNo notes");
+        return value;
+    }
+
+    value.push_str("\n\n| Note | Octave | Length | Hz | Seconds |\n|---|---|---|---|---|");
+    for note in notes.iter().take(MAX_PLAY_NOTES) {
+        let length = format!("1/{}{}", note.length, ".".repeat(note.dots as usize));
+        match &note.name {
+            Some(name) => value.push_str(&format!(
+                "\n| {} | {} | {} | {:.0} | {:.2} |",
+                name, note.octave, length, note.frequency, note.seconds
+            )),
+            None => value.push_str(&format!(
+                "\n| rest | | {} | | {:.2} |",
+                length, note.seconds
+            )),
+        }
+    }
+    if notes.len() > MAX_PLAY_NOTES {
+        value.push_str(&format!("\n\n...and {} more", notes.len() - MAX_PLAY_NOTES));
+    }
+
+    let total: f64 = notes.iter().map(|n| n.seconds).sum();
+    value.push_str(&format!(
+        "\n\n{} notes, {:.1} seconds",
+        notes.iter().filter(|n| n.name.is_some()).count(),
+        total
+    ));
+    value
+}
+
+/// Show the path of a DRAW string as an image
+fn draw_hover(content: &str) -> String {
+    let path = minilang::trace_draw(content);
+    let mut value = first_paragraph("DRAW");
+
+    if path.segments.is_empty() {
+        value.push_str("\n\nNothing drawn");
+        return value;
+    }
+
+    let svg = minilang::draw_svg(&path);
+    value.push_str(&format!(
+        "\n\n![DRAW preview](data:image/svg+xml;base64,{})\n\n{} segments",
+        base64_encode(svg.as_bytes()),
+        path.segments.len()
+    ));
+    value
+}
+
+/// Show example output for a PRINT USING format string
+fn using_hover(content: &str) -> String {
+    let fields = minilang::parse_using(content);
+    let mut value = get_documentation("USING").unwrap_or_default().to_string();
+
+    let fields: Vec<&UsingField> = fields
+        .iter()
+        .filter(|f| !matches!(f, UsingField::Literal(_)))
+        .collect();
+    if fields.is_empty() {
+        value.push_str("\n\nNo format fields");
+        return value;
+    }
+
+    let numeric = fields.iter().any(|f| matches!(f, UsingField::Numeric(_)));
+    let samples: &[f64] = if numeric { USING_SAMPLES } else { &[0.0] };
+
+    value.push_str("\n\nExample output:\n```");
+    for &sample in samples {
+        let values: Vec<UsingValue> = fields
+            .iter()
+            .map(|f| match f {
+                UsingField::Numeric(_) => UsingValue::Number(sample),
+                _ => UsingValue::Str("BASICA".to_string()),
+            })
+            .collect();
+        let formatted = minilang::format_using(content, &values);
+        if numeric {
+            value.push_str(&format!("\n{:>12} -> {}", sample, formatted));
+        } else {
+            value.push_str(&format!("\n\"BASICA\" -> {}", formatted));
+        }
+    }
+    value.push_str("\n```");
+    value
+}
+
+/// Get the syntax line of a keyword's documentation
+fn first_paragraph(keyword: &str) -> String {
+    get_documentation(keyword)
+        .and_then(|doc| doc.split("\n\n").next())
+        .unwrap_or(keyword)
+        .to_string()
+}
+
+fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - i * 6)) as usize & 0x3f] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+
+    out
+}
+
+/// Preview the line a GOTO/GOSUB/THEN/RESTORE reference jumps to
+fn line_reference_hover(lines: &[&str], line: &str, start: usize, target: u32) -> Option<String> {
+    if !is_line_reference(line, start) {
+        return None;
+    }
+
+    let target_idx = lines
+        .iter()
+        .position(|l| leading_line_number(l).is_some_and(|(num, _, _)| num == target));
+    let target_idx = match target_idx {
+        Some(idx) => idx,
+        None => return Some(format!("**Line {}** is not defined", target)),
+    };
+
+    let end = (target_idx + PREVIEW_LINES).min(lines.len());
+    let preview = lines[target_idx..end].join("\n");
+    Some(format!(
+        "**Line {}**\n\n```basica\n{}\n```",
+        target, preview
+    ))
+}
+
+/// Whether the number at `start` is a line reference
+fn is_line_reference(line: &str, start: usize) -> bool {
+    line_references(line).iter().any(|&(from, _)| from == start)
+}
+
+/// Get the name of a user function if the word is part of an `FNx` call
+fn user_function_name(line: &str, start: usize, word: &str) -> Option<String> {
+    if let Some(name) = word.strip_prefix("FN") {
+        if !name.is_empty() {
+            return Some(name.to_string());
+        }
+    }
+
+    // `FN X(...)` with a space after FN
+    let before = line[..start].trim_end().to_uppercase();
+    if before.ends_with("FN") && !word.is_empty() && word != "FN" {
+        return Some(word.to_string());
+    }
+
+    None
+}
+
+/// Show the DEF FN definition for a user function
+fn user_function_hover(source: &str, name: &str) -> Option<String> {
+    for line in source.lines() {
+        let upper = line.to_uppercase();
+        let def_pos = match upper.find("DEF") {
+            Some(pos) => pos,
+            None => continue,
+        };
+        let after_def = upper[def_pos + 3..].trim_start();
+        let after_fn = match after_def.strip_prefix("FN") {
+            Some(rest) => rest.trim_start(),
+            None => continue,
+        };
+        let rest = match after_fn.strip_prefix(name) {
+            Some(rest) => rest,
+            None => continue,
+        };
+
+        // Make sure we matched the whole name
+        if rest
+            .chars()
+            .next()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '$')
+        {
+            continue;
+        }
+
+        let rest = rest.trim_start();
+        let (params, body) = match rest.strip_prefix('(') {
+            Some(inner) => {
+                let close = inner.find(')')?;
+                (&inner[..close], inner[close + 1..].trim_start())
+            }
+            None => ("", rest),
+        };
+        let body = body.strip_prefix('=').unwrap_or(body).trim();
+        let params: Vec<&str> = params
+            .split(',')
+            .map(|p| p.trim())
+            .filter(|p| !p.is_empty())
+            .collect();
+
+        let mut value = format!("```basica\nDEF FN{}", name);
+        if !params.is_empty() {
+            value.push_str(&format!("({})", params.join(", ")));
+        }
+        value.push_str(&format!(" = {}\n```", body));
+
+        if params.is_empty() {
+            value.push_str("\n\nNo parameters");
+        } else {
+            value.push_str(&format!("\n\n**Parameters:** {}", params.join(", ")));
+        }
+        if let Some((num, _, _)) = leading_line_number(line) {
+            value.push_str(&format!("\n\nDefined at line {}", num));
+        }
+        return Some(value);
+    }
+
+    Some(format!(
+        "**FN{}**\n\nUser function is not defined (missing DEF FN{})",
+        name, name
+    ))
+}
+
+/// Show type, dimensions, first definition and reference count for a variable
+fn variable_hover(source: &str, name: &str) -> Option<String> {
+    let refs = references::find_variable_ranges(source, name);
+    if refs.is_empty() {
+        return None;
+    }
+
+    let mut value = format!("**{}** ({})", name, variable_type(source, name));
+
+    if let Some(bounds) = find_dim_bounds(source, name) {
+        value.push_str(&format!("\n\n**Dimensions:** ({})", bounds));
+    }
+
+    match definition::find_variable_definition(source, name) {
+        Some((def_line, _)) => {
+            let text = source.lines().nth(def_line as usize).unwrap_or("").trim();
+            value.push_str(&format!("\n\nFirst defined:\n```basica\n{}\n```", text));
+        }
+        None => value.push_str("\n\nNever assigned"),
+    }
+
+    let count = refs.len();
+    value.push_str(&format!(
+        "\n\n{} reference{}",
+        count,
+        if count == 1 { "" } else { "s" }
+    ));
+
+    Some(value)
+}
+
+/// Infer a variable's type from its suffix or DEFINT/DEFSNG/DEFDBL/DEFSTR
+fn variable_type(source: &str, name: &str) -> &'static str {
+    match name.chars().last() {
+        Some('$') => return "String",
+        Some('%') => return "Integer",
+        Some('!') => return "Single-precision",
+        Some('#') => return "Double-precision",
+        _ => {}
+    }
+
+    let first = name.chars().next().unwrap_or('A');
+    let mut result = "Single-precision";
+
+    for line in source.lines() {
+        let upper = line.to_uppercase();
+        for (keyword, type_name) in [
+            ("DEFINT", "Integer"),
+            ("DEFSNG", "Single-precision"),
+            ("DEFDBL", "Double-precision"),
+            ("DEFSTR", "String"),
+        ] {
+            if let Some(pos) = upper.find(keyword) {
+                let ranges = upper[pos + keyword.len()..].split(':').next().unwrap_or("");
+                if letter_ranges_contain(ranges, first) {
+                    result = type_name;
+                }
+            }
+        }
+    }
+
+    result
+}
+
+/// Check whether a DEFtype letter list like `A-C, X` includes a letter
+fn letter_ranges_contain(ranges: &str, letter: char) -> bool {
+    ranges.split(',').any(|part| {
+        let mut bounds = part.split('-').map(|b| b.trim().chars().next());
+        match (bounds.next().flatten(), bounds.next().flatten()) {
+            (Some(from), Some(to)) => (from..=to).contains(&letter),
+            (Some(only), None) => only == letter,
+            _ => false,
+        }
+    })
+}
+
+/// Find the bounds of an array from its DIM statement (or the implicit 10)
+fn find_dim_bounds(source: &str, name: &str) -> Option<String> {
+    let base = option_base(source);
+    let mut used_as_array = false;
+
+    for line in source.lines() {
+        let upper = line.to_uppercase();
+
+        if let Some(dim_pos) = upper.find("DIM ") {
+            let list = upper[dim_pos + 4..].split(':').next().unwrap_or("");
+            for (entry_name, dims) in split_dim_list(list) {
+                if entry_name == name {
+                    let bounds: Vec<String> = dims
+                        .iter()
+                        .map(|d| format!("{} TO {}", base, d.trim()))
+                        .collect();
+                    return Some(bounds.join(", "));
+                }
+            }
+        }
+
+        let pattern = format!("{}(", name);
+        used_as_array |= upper
+            .match_indices(&pattern)
+            .any(|(pos, _)| pos == 0 || !upper.as_bytes()[pos - 1].is_ascii_alphanumeric());
+    }
+
+    // Arrays used without DIM get 11 elements per dimension
+    if used_as_array {
+        Some(format!("{} TO 10, implicit", base))
+    } else {
+        None
+    }
+}
+
+/// Split a DIM list like `A(10), B$(5, 5)` into names and dimension sizes
+fn split_dim_list(list: &str) -> Vec<(String, Vec<String>)> {
+    let mut entries = Vec::new();
+    let mut depth = 0;
+    let mut current = String::new();
+
+    for c in list.chars().chain(std::iter::once(',')) {
+        match c {
+            '(' => {
+                depth += 1;
+                current.push(c);
+            }
+            ')' => {
+                depth -= 1;
+                current.push(c);
+            }
+            ',' if depth == 0 => {
+                if let Some(open) = current.find('(') {
+                    let name = current[..open].trim().to_string();
+                    let inner = current[open + 1..].trim_end().trim_end_matches(')');
+                    let dims = inner.split(',').map(|d| d.to_string()).collect();
+                    entries.push((name, dims));
+                }
+                current.clear();
+            }
+            _ => current.push(c),
+        }
+    }
+
+    entries
+}
+
+/// Get the OPTION BASE setting (0 by default)
+fn option_base(source: &str) -> u32 {
+    source
+        .lines()
+        .find_map(|line| {
+            let upper = line.to_uppercase();
+            let pos = upper.find("OPTION BASE")?;
+            upper[pos + 11..].trim_start().get(..1)?.parse().ok()
+        })
+        .unwrap_or(0)
+}
+
 /// Get documentation for a keyword or function
 fn get_documentation(keyword: &str) -> Option<&'static str> {
     // Strip $ suffix for lookup
@@ -154,6 +672,7 @@ fn get_documentation(keyword: &str) -> Option<&'static str> {
         "PUT" => Some("**PUT** (x, y), array\n\nDraw array contents at position (sprite draw)."),
         "DRAW" => Some("**DRAW** command$\n\nTurtle graphics. Commands: U/D/L/R (move), M (move to), C (color), etc."),
         "PLAY" => Some("**PLAY** music$\n\nPlay music notation. Notes: A-G, O (octave), L (length), T (tempo)."),
+        "USING" => Some("**PRINT USING** format$; expr [; expr ...]\n\nFormatted output. Numbers: `#` digit, `.` decimal point, `,` thousands, `+`/`-` sign, `**` asterisk fill, `$$` dollar sign, `^^^^` exponent. Strings: `!` first character, `\\  \\` fixed width, `&` whole string."),
         "SOUND" => Some("**SOUND** frequency, duration\n\nPlay tone at frequency (Hz) for duration."),
         "BEEP" => Some("**BEEP**\n\nPlay system beep sound."),
         "WIDTH" => Some("**WIDTH** columns\n\nSet screen width (40 or 80 columns typically)."),
@@ -189,3 +708,61 @@ fn get_documentation(keyword: &str) -> Option<&'static str> {
         _ => None,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hover_text(source: &str, line: u32, character: u32) -> Option<String> {
+        let hover = get_hover(source, Position { line, character }, Dialect::Basica)?;
+        match hover.contents {
+            HoverContents::Markup(markup) => Some(markup.value),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn line_references_preview_the_target() {
+        let source = "10 GOSUB 30\n20 END\n30 PRINT \"HI\"\n40 RETURN\n";
+        let value = hover_text(source, 0, 10).unwrap();
+        assert!(value.starts_with("**Line 30**"));
+        assert!(value.contains("30 PRINT \"HI\"\n40 RETURN"));
+        assert_eq!(
+            hover_text("10 GOTO 99\n", 0, 8).unwrap(),
+            "**Line 99** is not defined"
+        );
+    }
+
+    #[test]
+    fn only_jump_targets_preview() {
+        let source = "10 IF ERL = 20 THEN RESUME 20 ELSE PRINT 20\n20 END\n";
+        assert!(hover_text(source, 0, 12)
+            .unwrap()
+            .starts_with("**Line 20**"));
+        assert!(hover_text(source, 0, 27)
+            .unwrap()
+            .starts_with("**Line 20**"));
+        assert!(hover_text(source, 0, 41).is_none());
+    }
+
+    #[test]
+    fn fn_calls_show_their_definition() {
+        let source = "10 DEF FNSQ(X) = X * X\n20 PRINT FNSQ(3)\n30 PRINT FNNO(1)\n";
+        let value = hover_text(source, 1, 10).unwrap();
+        assert!(value.contains("DEF FNSQ(X) = X * X"));
+        assert!(value.contains("**Parameters:** X"));
+        assert!(value.contains("Defined at line 10"));
+        assert!(hover_text(source, 2, 10)
+            .unwrap()
+            .contains("missing DEF FNNO"));
+    }
+
+    #[test]
+    fn variables_show_type_dimensions_and_uses() {
+        let source = "10 DEFINT A-C\n20 DIM B(5, 2)\n30 B(1, 1) = 4: PRINT B(1, 1)\n";
+        let value = hover_text(source, 2, 3).unwrap();
+        assert!(value.starts_with("**B** (Integer)"));
+        assert!(value.contains("**Dimensions:** (0 TO 5, 0 TO 2)"));
+        assert!(value.contains("3 references"));
+    }
+}
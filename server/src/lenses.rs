@@ -0,0 +1,312 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use tower_lsp::lsp_types::*;
+
+use crate::references::{find_line_references, find_variable_ranges, referenced_line_numbers};
+use crate::renumber::{byte_offset, leading_line_number, utf16_len};
+
+/// Client-side command that opens the references peek; arguments are
+/// `[uri, position, locations]`
+pub const SHOW_REFERENCES_COMMAND: &str = "basica.showReferences";
+
+/// What an unresolved lens counts references to
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+enum Target {
+    Line(u32),
+    /// A `DEF FN` function, by its name without `FN`
+    Function(String),
+}
+
+/// Carried from `textDocument/codeLens` to `codeLens/resolve`
+#[derive(Debug, Serialize, Deserialize)]
+struct LensData {
+    uri: Url,
+    target: Target,
+}
+
+/// Lenses above every jump target, unused subroutine and `DEF FN`. Only
+/// the unused subroutines are counted here; the rest are counted when the
+/// client resolves them, for the lines it shows
+pub fn code_lenses(source: &str, uri: &Url) -> Vec<CodeLens> {
+    let referenced = referenced_line_numbers(source);
+    let unused = unused_subroutines(source, &referenced);
+    let mut lenses = Vec::new();
+
+    for (line_idx, line) in source.lines().enumerate() {
+        if let Some((num, start, end)) = leading_line_number(line) {
+            let range = line_range(line_idx, line, start, end);
+            if referenced.contains(&num) {
+                lenses.push(unresolved(range, uri, Target::Line(num)));
+            } else if unused.contains(&line_idx) {
+                lenses.push(CodeLens {
+                    range,
+                    // Opens an empty references peek, like a counted lens would
+                    command: Some(Command {
+                        title: "unused subroutine".to_string(),
+                        command: SHOW_REFERENCES_COMMAND.to_string(),
+                        arguments: Some(vec![
+                            serde_json::json!(uri),
+                            serde_json::json!(range.start),
+                            serde_json::json!([]),
+                        ]),
+                    }),
+                    data: None,
+                });
+            }
+        }
+
+        for (name, start, end) in function_definitions(line) {
+            let range = line_range(line_idx, line, start, end);
+            lenses.push(unresolved(range, uri, Target::Function(name)));
+        }
+    }
+
+    lenses
+}
+
+/// Count the references of a lens from `code_lenses`
+pub fn resolve(mut lens: CodeLens, source: &str) -> CodeLens {
+    let Some(data) = lens
+        .data
+        .take()
+        .and_then(|data| serde_json::from_value::<LensData>(data).ok())
+    else {
+        return lens;
+    };
+    let at = lens.range.start;
+
+    let (title, locations) = match &data.target {
+        Target::Line(num) => {
+            // The line's own number is listed as its definition
+            let locations: Vec<Location> = find_line_references(source, *num, &data.uri)
+                .into_iter()
+                .filter(|l| l.range.start != at)
+                .collect();
+            (line_title(source, &locations), locations)
+        }
+        Target::Function(name) => {
+            let locations: Vec<Location> = find_variable_ranges(source, &format!("FN{}", name))
+                .into_iter()
+                .filter(|range| range.start != at)
+                .map(|range| Location::new(data.uri.clone(), range))
+                .collect();
+            (plural(locations.len(), "reference"), locations)
+        }
+    };
+
+    lens.command = Some(Command {
+        title,
+        command: SHOW_REFERENCES_COMMAND.to_string(),
+        arguments: Some(vec![
+            serde_json::json!(data.uri),
+            serde_json::json!(at),
+            serde_json::json!(locations),
+        ]),
+    });
+    lens
+}
+
+/// The document a lens from `code_lenses` belongs to
+pub fn lens_uri(lens: &CodeLens) -> Option<Url> {
+    let data = serde_json::from_value::<LensData>(lens.data.clone()?).ok()?;
+    Some(data.uri)
+}
+
+fn unresolved(range: Range, uri: &Url, target: Target) -> CodeLens {
+    let data = LensData {
+        uri: uri.clone(),
+        target,
+    };
+    CodeLens {
+        range,
+        command: None,
+        data: serde_json::to_value(data).ok(),
+    }
+}
+
+/// "3 GOSUB callers", "referenced by 5 GOTOs", or both, by the keyword
+/// before each reference
+fn line_title(source: &str, locations: &[Location]) -> String {
+    let lines: Vec<&str> = source.lines().collect();
+    let (mut callers, mut jumps, mut restores) = (0, 0, 0);
+
+    for location in locations {
+        let Some(line) = lines.get(location.range.start.line as usize) else {
+            continue;
+        };
+        let before = line[..byte_offset(line, location.range.start.character)].to_ascii_uppercase();
+        let keyword = ["GOSUB", "GOTO", "THEN", "RESTORE"]
+            .into_iter()
+            .filter_map(|keyword| before.rfind(keyword).map(|pos| (pos, keyword)))
+            .max();
+        match keyword {
+            Some((_, "GOSUB")) => callers += 1,
+            Some((_, "RESTORE")) => restores += 1,
+            _ => jumps += 1,
+        }
+    }
+
+    let mut parts = Vec::new();
+    if callers > 0 {
+        parts.push(plural(callers, "GOSUB caller"));
+    }
+    if jumps > 0 {
+        parts.push(format!("referenced by {}", plural(jumps, "GOTO")));
+    }
+    if restores > 0 {
+        parts.push(plural(restores, "RESTORE"));
+    }
+    if parts.is_empty() {
+        return plural(0, "reference");
+    }
+    parts.join(", ")
+}
+
+/// Source lines starting a block of code after a RETURN that nothing jumps
+/// into: subroutines no one calls. REM lines heading the block belong to it
+fn unused_subroutines(source: &str, referenced: &HashSet<u32>) -> Vec<usize> {
+    let mut unused = Vec::new();
+    let mut after_return = false;
+    // First line of the block being looked at, while nothing refers to it
+    let mut block_start: Option<usize> = None;
+
+    for (line_idx, line) in source.lines().enumerate() {
+        let Some((num, _, end)) = leading_line_number(line) else {
+            continue;
+        };
+        let code = line[end..].trim().to_ascii_uppercase();
+
+        if after_return || block_start.is_some() {
+            if referenced.contains(&num) {
+                block_start = None;
+            } else if code.starts_with("REM") || code.starts_with('\'') {
+                block_start.get_or_insert(line_idx);
+            } else {
+                unused.push(block_start.take().unwrap_or(line_idx));
+            }
+        }
+
+        let last_statement = code.rsplit(':').next().unwrap_or("").trim();
+        after_return = last_statement == "RETURN";
+        if after_return {
+            block_start = None;
+        }
+    }
+
+    unused
+}
+
+/// `DEF FN` names in a source line, with the byte range of `FNname`
+fn function_definitions(line: &str) -> Vec<(String, usize, usize)> {
+    let upper = line.to_ascii_uppercase();
+    let mut functions = Vec::new();
+
+    for (pos, _) in upper.match_indices("DEF FN") {
+        if pos > 0 && upper.as_bytes()[pos - 1].is_ascii_alphanumeric() {
+            continue;
+        }
+        let start = pos + "DEF ".len();
+        let name_start = start + "FN".len();
+        let name_len = upper[name_start..]
+            .find(|c: char| !(c.is_ascii_alphanumeric() || c == '.'))
+            .unwrap_or(upper.len() - name_start);
+        let mut end = name_start + name_len;
+        if upper[end..].starts_with(['$', '%', '!', '#']) {
+            end += 1;
+        }
+        if end > name_start {
+            functions.push((upper[name_start..end].to_string(), start, end));
+        }
+    }
+
+    functions
+}
+
+/// A byte range of a source line as an LSP range
+fn line_range(line_idx: usize, line: &str, start: usize, end: usize) -> Range {
+    Range {
+        start: Position {
+            line: line_idx as u32,
+            character: utf16_len(&line[..start]),
+        },
+        end: Position {
+            line: line_idx as u32,
+            character: utf16_len(&line[..end]),
+        },
+    }
+}
+
+/// `1 reference`, `2 references`
+fn plural(count: usize, noun: &str) -> String {
+    match count {
+        1 => format!("1 {}", noun),
+        _ => format!("{} {}s", count, noun),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn titles(source: &str) -> Vec<(u32, String)> {
+        let uri = Url::parse("file:///test.bas").unwrap();
+        code_lenses(source, &uri)
+            .into_iter()
+            .map(|lens| resolve(lens, source))
+            .map(|lens| (lens.range.start.line, lens.command.unwrap().title))
+            .collect()
+    }
+
+    #[test]
+    fn lenses_count_callers_and_jumps() {
+        let source = "10 GOSUB 100: GOSUB 100\n\
+            20 IF A THEN 10\n\
+            30 END\n\
+            100 RETURN\n";
+        assert_eq!(
+            titles(source),
+            [
+                (0, "referenced by 1 GOTO".to_string()),
+                (3, "2 GOSUB callers".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn subroutines_nothing_calls_are_unused() {
+        let source = "10 GOSUB 100: END\n\
+            100 RETURN\n\
+            110 REM Never called\n\
+            120 PRINT: RETURN\n";
+        assert_eq!(
+            titles(source),
+            [
+                (1, "1 GOSUB caller".to_string()),
+                (2, "unused subroutine".to_string()),
+            ]
+        );
+        let uri = Url::parse("file:///test.bas").unwrap();
+        let unused = code_lenses(source, &uri).pop().unwrap().command.unwrap();
+        assert_eq!(unused.command, SHOW_REFERENCES_COMMAND);
+    }
+
+    #[test]
+    fn else_and_resume_targets_are_used() {
+        let source = "10 ON ERROR GOTO 100\n\
+            20 IF A THEN END ELSE 110\n\
+            30 RETURN\n\
+            100 RESUME 120\n\
+            110 RETURN\n\
+            120 END\n";
+        assert!(!titles(source)
+            .iter()
+            .any(|(_, title)| title == "unused subroutine"));
+    }
+
+    #[test]
+    fn functions_count_their_calls() {
+        let source = "10 DEF FNSQ(X) = X * X\n20 PRINT FNSQ(2) + fnsq(3)\n";
+        assert_eq!(titles(source), [(0, "2 references".to_string())]);
+    }
+}
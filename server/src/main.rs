@@ -1,24 +1,54 @@
+mod audio;
+mod auto_number;
 mod backend;
+mod cli;
+mod codes;
 mod completion;
+mod config;
+mod coverage;
+mod dap;
 mod definition;
 mod diagnostics;
+mod dialect;
+mod error_handlers;
+mod files;
+mod flow;
 mod folding;
+mod formatting;
+mod golden;
+mod hardware;
 mod hover;
+mod lenses;
+mod minilang;
 mod references;
 mod rename;
+mod renumber;
+mod runner;
+mod screen;
+mod selection;
 mod semantic_tokens;
 mod signature;
+mod suppression;
 mod symbols;
+mod tokenized;
 
 use backend::BasicaBackend;
 use tower_lsp::{LspService, Server};
 
 #[tokio::main]
 async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Some(code) = cli::run(&args) {
+        std::process::exit(code);
+    }
+
     let stdin = tokio::io::stdin();
     let stdout = tokio::io::stdout();
 
-    let (service, socket) = LspService::build(BasicaBackend::new).finish();
+    let (service, socket) = LspService::build(BasicaBackend::new)
+        .custom_method("basica/detokenize", BasicaBackend::detokenize)
+        .custom_method("basica/evaluate", BasicaBackend::evaluate)
+        .finish();
 
     Server::new(stdin, stdout, socket).serve(service).await;
 }
@@ -0,0 +1,1162 @@
+/// A note or rest decoded from a PLAY string
+#[derive(Debug, Clone)]
+pub struct PlayNote {
+    /// Note name like `C`, `F#` or `B-`; `None` for a rest
+    pub name: Option<String>,
+    pub octave: u32,
+    /// Note length as a fraction of a whole note (4 = quarter note)
+    pub length: u32,
+    pub dots: u32,
+    /// Frequency in Hz (0 for rests)
+    pub frequency: f64,
+    /// Duration in seconds at the current tempo
+    pub seconds: f64,
+    /// Part of the duration the note sounds: 7/8 in music normal, all of it
+    /// in legato and 3/4 in staccato
+    pub sounded: f64,
+}
+
+/// Semitone offsets of the notes A-G from C
+const NOTE_SEMITONES: [(char, i32); 7] = [
+    ('C', 0),
+    ('D', 2),
+    ('E', 4),
+    ('F', 5),
+    ('G', 7),
+    ('A', 9),
+    ('B', 11),
+];
+
+/// Decode a PLAY string into notes and rests.
+///
+/// Follows the BASICA defaults: octave 4, length 4, tempo 120, and music
+/// normal (notes sound for 7/8 of their length, then rest).
+pub fn parse_play(source: &str) -> Vec<PlayNote> {
+    let mut notes = Vec::new();
+    let mut scanner = Scanner::new(source);
+    let mut octave = 4u32;
+    let mut length = 4u32;
+    let mut tempo = 120u32;
+    let mut sounded = 7.0 / 8.0;
+
+    while let Some((_, c)) = scanner.next_command() {
+        match c {
+            'A'..='G' => {
+                let mut semitone = note_semitone(c);
+                let mut name = c.to_string();
+                match scanner.peek() {
+                    Some('#') | Some('+') => {
+                        scanner.bump();
+                        semitone += 1;
+                        name.push('#');
+                    }
+                    Some('-') => {
+                        scanner.bump();
+                        semitone -= 1;
+                        name.push('-');
+                    }
+                    _ => {}
+                }
+                let note_length = scanner.number().map_or(length, |n| n as u32);
+                let dots = scanner.dots();
+                notes.push(PlayNote {
+                    name: Some(name),
+                    octave,
+                    length: note_length,
+                    dots,
+                    frequency: note_frequency(octave as i32 * 12 + semitone),
+                    seconds: note_seconds(note_length, dots, tempo),
+                    sounded,
+                });
+            }
+            'N' => {
+                let n = scanner.number().unwrap_or(0).max(0) as i32;
+                let dots = scanner.dots();
+                if n == 0 {
+                    notes.push(rest(length, dots, tempo));
+                } else {
+                    let index = n - 1;
+                    let name = NOTE_SEMITONES
+                        .iter()
+                        .find(|(_, s)| *s == index % 12)
+                        .map(|(c, _)| c.to_string())
+                        .unwrap_or_else(|| {
+                            // Black keys are named as sharps of the note below
+                            let below = NOTE_SEMITONES
+                                .iter()
+                                .rev()
+                                .find(|(_, s)| *s < index % 12)
+                                .map_or('C', |(c, _)| *c);
+                            format!("{}#", below)
+                        });
+                    notes.push(PlayNote {
+                        name: Some(name),
+                        octave: (index / 12) as u32,
+                        length,
+                        dots,
+                        frequency: note_frequency(index),
+                        seconds: note_seconds(length, dots, tempo),
+                        sounded,
+                    });
+                }
+            }
+            'P' => {
+                let pause = scanner.number().map_or(length, |n| n as u32);
+                let dots = scanner.dots();
+                notes.push(rest(pause, dots, tempo));
+            }
+            'O' => octave = scanner.number().map_or(octave, |n| n.clamp(0, 6) as u32),
+            'L' => length = scanner.number().map_or(length, |n| n.clamp(1, 64) as u32),
+            'T' => tempo = scanner.number().map_or(tempo, |n| n.clamp(32, 255) as u32),
+            '>' => octave = (octave + 1).min(6),
+            '<' => octave = octave.saturating_sub(1),
+            'M' => {
+                // MF and MB only choose foreground or background playing
+                match scanner.peek() {
+                    Some('N') => sounded = 7.0 / 8.0,
+                    Some('L') => sounded = 1.0,
+                    Some('S') => sounded = 3.0 / 4.0,
+                    _ => {}
+                }
+                scanner.bump();
+            }
+            'X' => scanner.skip_variable(),
+            _ => {}
+        }
+    }
+
+    notes
+}
+
+fn note_semitone(c: char) -> i32 {
+    NOTE_SEMITONES
+        .iter()
+        .find(|(n, _)| *n == c)
+        .map_or(0, |(_, s)| *s)
+}
+
+/// Frequency of a note, counted in semitones from C in octave 0.
+/// Octave 3 holds middle C, so A in octave 3 is 440 Hz.
+fn note_frequency(semitones: i32) -> f64 {
+    440.0 * 2f64.powf((semitones - (3 * 12 + 9)) as f64 / 12.0)
+}
+
+/// Duration of a note in seconds; tempo is in quarter notes per minute
+fn note_seconds(length: u32, dots: u32, tempo: u32) -> f64 {
+    let base = 4.0 / length.max(1) as f64 * 60.0 / tempo.max(1) as f64;
+    base * 1.5f64.powi(dots as i32)
+}
+
+fn rest(length: u32, dots: u32, tempo: u32) -> PlayNote {
+    PlayNote {
+        name: None,
+        octave: 0,
+        length,
+        dots,
+        frequency: 0.0,
+        seconds: note_seconds(length, dots, tempo),
+        sounded: 0.0,
+    }
+}
+
+/// A problem found in a PLAY, DRAW or PRINT USING string
+#[derive(Debug, Clone)]
+pub struct SyntaxError {
+    /// Byte range of the offending text within the string
+    pub start: usize,
+    pub end: usize,
+    pub message: String,
+}
+
+impl SyntaxError {
+    fn at_char(offset: usize, c: char, message: String) -> Self {
+        Self {
+            start: offset,
+            end: offset + c.len_utf8(),
+            message,
+        }
+    }
+}
+
+/// Find commands in a PLAY string that BASICA rejects with "Illegal function call"
+pub fn check_play(source: &str) -> Vec<SyntaxError> {
+    let mut errors = Vec::new();
+    let mut scanner = Scanner::new(source);
+
+    while let Some((offset, c)) = scanner.next_command() {
+        let command = (offset, c);
+        match c {
+            'A'..='G' => {
+                if matches!(scanner.peek(), Some('#') | Some('+') | Some('-')) {
+                    scanner.bump();
+                }
+                let length = scanner.argument(false);
+                check_range(&mut errors, command, length, "Note length", 1, 64, false);
+                scanner.dots();
+            }
+            'N' => {
+                let note = scanner.argument(false);
+                check_range(&mut errors, command, note, "Note number", 0, 84, true);
+                scanner.dots();
+            }
+            'P' => {
+                let pause = scanner.argument(false);
+                check_range(&mut errors, command, pause, "Pause length", 1, 64, true);
+                scanner.dots();
+            }
+            'O' => {
+                let octave = scanner.argument(false);
+                check_range(&mut errors, command, octave, "Octave", 0, 6, true);
+            }
+            'L' => {
+                let length = scanner.argument(false);
+                check_range(&mut errors, command, length, "Length", 1, 64, true);
+            }
+            'T' => {
+                let tempo = scanner.argument(false);
+                check_range(&mut errors, command, tempo, "Tempo", 32, 255, true);
+            }
+            'M' => match scanner.next_command() {
+                Some((_, 'F' | 'B' | 'L' | 'N' | 'S')) => {}
+                Some((offset, mode)) => errors.push(SyntaxError::at_char(
+                    offset,
+                    mode,
+                    format!("Unknown music mode 'M{}'", mode),
+                )),
+                None => errors.push(SyntaxError::at_char(
+                    offset,
+                    c,
+                    "Missing music mode after 'M'".to_string(),
+                )),
+            },
+            'X' => scanner.skip_variable(),
+            '<' | '>' => {}
+            _ => errors.push(SyntaxError::at_char(
+                offset,
+                c,
+                format!("Unknown PLAY command '{}'", c),
+            )),
+        }
+    }
+
+    errors
+}
+
+/// Find commands in a DRAW string that BASICA rejects with "Illegal function call"
+pub fn check_draw(source: &str) -> Vec<SyntaxError> {
+    let mut errors = Vec::new();
+    let mut scanner = Scanner::new(source);
+
+    while let Some((offset, c)) = scanner.next_command() {
+        let command = (offset, c);
+        match c {
+            'U' | 'D' | 'L' | 'R' | 'E' | 'F' | 'G' | 'H' => {
+                let distance = scanner.argument(false);
+                check_range(&mut errors, command, distance, "Distance", 0, 32767, false);
+            }
+            'M' => {
+                let x = scanner.argument(true);
+                check_range(&mut errors, command, x, "X coordinate", -32768, 32767, true);
+                if !scanner.expect(',') {
+                    let at = scanner.offset();
+                    errors.push(match scanner.peek() {
+                        Some(found) => SyntaxError::at_char(
+                            at,
+                            found,
+                            "Expected ',' between the M coordinates".to_string(),
+                        ),
+                        None => SyntaxError::at_char(
+                            offset,
+                            c,
+                            "Missing Y coordinate after 'M'".to_string(),
+                        ),
+                    });
+                    continue;
+                }
+                let y = scanner.argument(true);
+                check_range(&mut errors, command, y, "Y coordinate", -32768, 32767, true);
+            }
+            'B' | 'N' => {}
+            'A' => {
+                let angle = scanner.argument(false);
+                check_range(&mut errors, command, angle, "Angle", 0, 3, true);
+            }
+            'T' => {
+                if !scanner.expect('A') {
+                    errors.push(SyntaxError::at_char(
+                        offset,
+                        c,
+                        "Expected 'TA' (turn angle)".to_string(),
+                    ));
+                    continue;
+                }
+                let angle = scanner.argument(true);
+                check_range(&mut errors, command, angle, "Turn angle", -360, 360, true);
+            }
+            'S' => {
+                let scale = scanner.argument(false);
+                check_range(&mut errors, command, scale, "Scale", 0, 255, true);
+            }
+            'C' => {
+                let color = scanner.argument(false);
+                check_range(&mut errors, command, color, "Colour", 0, 255, true);
+            }
+            'P' => {
+                let paint = scanner.argument(false);
+                check_range(&mut errors, command, paint, "Paint colour", 0, 255, true);
+                if !scanner.expect(',') {
+                    errors.push(SyntaxError::at_char(
+                        offset,
+                        c,
+                        "Missing border colour after 'P'".to_string(),
+                    ));
+                    continue;
+                }
+                let border = scanner.argument(false);
+                check_range(&mut errors, command, border, "Border colour", 0, 255, true);
+            }
+            'X' => scanner.skip_variable(),
+            _ => errors.push(SyntaxError::at_char(
+                offset,
+                c,
+                format!("Unknown DRAW command '{}'", c),
+            )),
+        }
+    }
+
+    errors
+}
+
+/// Report an argument outside `min..=max`, or a missing one if `required`
+fn check_range(
+    errors: &mut Vec<SyntaxError>,
+    (offset, command): (usize, char),
+    argument: Argument,
+    what: &str,
+    min: i64,
+    max: i64,
+    required: bool,
+) {
+    match argument {
+        Argument::Value(n, start, end) if n < min || n > max => errors.push(SyntaxError {
+            start,
+            end,
+            message: format!("{} {} is out of range ({} to {})", what, n, min, max),
+        }),
+        Argument::Missing if required => errors.push(SyntaxError::at_char(
+            offset,
+            command,
+            format!("Missing {} after '{}'", what.to_lowercase(), command),
+        )),
+        _ => {}
+    }
+}
+
+/// A line segment produced by a DRAW string
+#[derive(Debug, Clone)]
+pub struct DrawSegment {
+    pub from: (f64, f64),
+    pub to: (f64, f64),
+    pub color: u32,
+}
+
+/// Result of tracing a DRAW string
+#[derive(Debug, Clone, Default)]
+pub struct DrawPath {
+    pub segments: Vec<DrawSegment>,
+    /// All points visited, including blind moves, for sizing the preview
+    pub points: Vec<(f64, f64)>,
+}
+
+/// Trace a DRAW string starting at the origin.
+///
+/// Handles U/D/L/R/E/F/G/H moves, absolute and relative M, the B (blind) and
+/// N (return) prefixes, A and TA rotation, S scaling and C colour.
+pub fn trace_draw(source: &str) -> DrawPath {
+    let mut path = DrawPath::default();
+    let mut scanner = Scanner::new(source);
+    let mut pos = (0.0f64, 0.0f64);
+    let mut color = 3u32;
+    let mut scale = 4.0f64;
+    let mut angle = 0.0f64;
+    let mut blind = false;
+    let mut no_update = false;
+    path.points.push(pos);
+
+    while let Some((_, c)) = scanner.next_command() {
+        let step = match c {
+            'U' => Some((0.0, -1.0)),
+            'D' => Some((0.0, 1.0)),
+            'L' => Some((-1.0, 0.0)),
+            'R' => Some((1.0, 0.0)),
+            'E' => Some((1.0, -1.0)),
+            'F' => Some((1.0, 1.0)),
+            'G' => Some((-1.0, 1.0)),
+            'H' => Some((-1.0, -1.0)),
+            _ => None,
+        };
+
+        let target = if let Some((dx, dy)) = step {
+            let n = scanner.number().unwrap_or(1) as f64 * scale / 4.0;
+            let (dx, dy) = rotate(dx * n, dy * n, angle);
+            Some((pos.0 + dx, pos.1 + dy))
+        } else {
+            match c {
+                'M' => {
+                    let relative = matches!(scanner.peek(), Some('+') | Some('-'));
+                    let x = scanner.signed_number().unwrap_or(0) as f64;
+                    scanner.expect(',');
+                    let y = scanner.signed_number().unwrap_or(0) as f64;
+                    if relative {
+                        let (dx, dy) = rotate(x * scale / 4.0, y * scale / 4.0, angle);
+                        Some((pos.0 + dx, pos.1 + dy))
+                    } else {
+                        Some((x, y))
+                    }
+                }
+                'B' => {
+                    blind = true;
+                    None
+                }
+                'N' => {
+                    no_update = true;
+                    None
+                }
+                'A' => {
+                    angle = scanner.number().unwrap_or(0).rem_euclid(4) as f64 * 90.0;
+                    None
+                }
+                'T' => {
+                    scanner.expect('A');
+                    angle = scanner.signed_number().unwrap_or(0) as f64;
+                    None
+                }
+                'S' => {
+                    scale = scanner.number().unwrap_or(4).max(1) as f64;
+                    None
+                }
+                'C' => {
+                    color = scanner.number().unwrap_or(3).max(0) as u32;
+                    None
+                }
+                'P' => {
+                    scanner.number();
+                    scanner.expect(',');
+                    scanner.number();
+                    None
+                }
+                'X' => {
+                    scanner.skip_variable();
+                    None
+                }
+                _ => None,
+            }
+        };
+
+        if let Some(to) = target {
+            if !blind {
+                path.segments.push(DrawSegment {
+                    from: pos,
+                    to,
+                    color,
+                });
+            }
+            path.points.push(to);
+            if !no_update {
+                pos = to;
+            }
+            blind = false;
+            no_update = false;
+        }
+    }
+
+    path
+}
+
+/// Rotate a vector counter-clockwise by `degrees` (screen y points down)
+fn rotate(dx: f64, dy: f64, degrees: f64) -> (f64, f64) {
+    if degrees == 0.0 {
+        return (dx, dy);
+    }
+    let (sin, cos) = (-degrees).to_radians().sin_cos();
+    (dx * cos - dy * sin, dx * sin + dy * cos)
+}
+
+/// CGA/EGA 16-colour palette as SVG colours
+const PALETTE: [&str; 16] = [
+    "#000000", "#0000aa", "#00aa00", "#00aaaa", "#aa0000", "#aa00aa", "#aa5500", "#aaaaaa",
+    "#555555", "#5555ff", "#55ff55", "#55ffff", "#ff5555", "#ff55ff", "#ffff55", "#ffffff",
+];
+
+/// Render a traced DRAW path as an SVG image
+pub fn draw_svg(path: &DrawPath) -> String {
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (0.0f64, 0.0f64, 0.0f64, 0.0f64);
+    for &(x, y) in &path.points {
+        min_x = min_x.min(x);
+        min_y = min_y.min(y);
+        max_x = max_x.max(x);
+        max_y = max_y.max(y);
+    }
+
+    let margin = 2.0;
+    let width = (max_x - min_x).max(1.0) + margin * 2.0;
+    let height = (max_y - min_y).max(1.0) + margin * 2.0;
+    // Scale small drawings up so the preview stays readable
+    let zoom = (200.0 / width.max(height)).clamp(1.0, 8.0);
+
+    let mut svg = format!(
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{:.0}\" height=\"{:.0}\" viewBox=\"{:.1} {:.1} {:.1} {:.1}\">",
+        width * zoom,
+        height * zoom,
+        min_x - margin,
+        min_y - margin,
+        width,
+        height
+    );
+    svg.push_str(&format!(
+        "<rect x=\"{:.1}\" y=\"{:.1}\" width=\"{:.1}\" height=\"{:.1}\" fill=\"#000000\"/>",
+        min_x - margin,
+        min_y - margin,
+        width,
+        height
+    ));
+    for segment in &path.segments {
+        // Colour 0 is the background; draw it dimmed so the path stays visible
+        let color = match segment.color {
+            0 => "#333333",
+            c => PALETTE[(c % 16) as usize],
+        };
+        svg.push_str(&format!(
+            "<line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"{}\" stroke-width=\"{:.2}\" stroke-linecap=\"square\"/>",
+            segment.from.0,
+            segment.from.1,
+            segment.to.0,
+            segment.to.1,
+            color,
+            1.0 / zoom.sqrt()
+        ));
+    }
+    svg.push_str("</svg>");
+    svg
+}
+
+/// A value to format with PRINT USING
+#[derive(Debug, Clone)]
+pub enum UsingValue {
+    Number(f64),
+    Str(String),
+}
+
+/// A field or literal in a PRINT USING format string
+#[derive(Debug, Clone, PartialEq)]
+pub enum UsingField {
+    Literal(String),
+    /// `!`: first character of a string
+    FirstChar,
+    /// `\  \`: fixed-width string field
+    FixedString(usize),
+    /// `&`: whole string
+    WholeString,
+    Numeric(NumericField),
+}
+
+/// Layout of a numeric PRINT USING field
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct NumericField {
+    /// Digit positions before the decimal point (including `$`/`*` fill)
+    pub int_digits: usize,
+    /// Digit positions after the decimal point; `None` without a point
+    pub frac_digits: Option<usize>,
+    pub commas: bool,
+    pub leading_plus: bool,
+    pub trailing_sign: Option<char>,
+    pub asterisk_fill: bool,
+    pub dollar: bool,
+    pub exponent: bool,
+}
+
+/// Split a PRINT USING format string into fields and literal text
+pub fn parse_using(format: &str) -> Vec<UsingField> {
+    let chars: Vec<char> = format.chars().collect();
+    let mut fields = Vec::new();
+    let mut literal = String::new();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let c = chars[i];
+        let next = chars.get(i + 1).copied();
+
+        let field = match c {
+            '_' if next.is_some() => {
+                literal.push(chars[i + 1]);
+                i += 2;
+                continue;
+            }
+            '!' => {
+                i += 1;
+                Some(UsingField::FirstChar)
+            }
+            '&' => {
+                i += 1;
+                Some(UsingField::WholeString)
+            }
+            '\\' => {
+                let close = chars[i + 1..].iter().position(|&c| c == '\\');
+                match close {
+                    Some(n) if chars[i + 1..i + 1 + n].iter().all(|&c| c == ' ') => {
+                        i += n + 2;
+                        Some(UsingField::FixedString(n + 2))
+                    }
+                    _ => None,
+                }
+            }
+            _ => {
+                let (field, len) = parse_numeric_field(&chars[i..]);
+                if len > 0 {
+                    i += len;
+                    Some(UsingField::Numeric(field))
+                } else {
+                    None
+                }
+            }
+        };
+
+        match field {
+            Some(field) => {
+                if !literal.is_empty() {
+                    fields.push(UsingField::Literal(std::mem::take(&mut literal)));
+                }
+                fields.push(field);
+            }
+            None => {
+                literal.push(c);
+                i += 1;
+            }
+        }
+    }
+
+    if !literal.is_empty() {
+        fields.push(UsingField::Literal(literal));
+    }
+    fields
+}
+
+/// Parse a numeric field at the start of `chars`, returning it and its length
+fn parse_numeric_field(chars: &[char]) -> (NumericField, usize) {
+    let mut field = NumericField::default();
+    let mut i = 0;
+    let starts_with = |s: &str, at: usize| {
+        s.chars()
+            .enumerate()
+            .all(|(k, c)| chars.get(at + k) == Some(&c))
+    };
+
+    if chars.first() == Some(&'+') {
+        field.leading_plus = true;
+        i += 1;
+    }
+    if starts_with("**$", i) {
+        field.asterisk_fill = true;
+        field.dollar = true;
+        field.int_digits += 3;
+        i += 3;
+    } else if starts_with("**", i) {
+        field.asterisk_fill = true;
+        field.int_digits += 2;
+        i += 2;
+    } else if starts_with("$$", i) {
+        field.dollar = true;
+        field.int_digits += 2;
+        i += 2;
+    }
+
+    while let Some(&c) = chars.get(i) {
+        match c {
+            '#' => field.int_digits += 1,
+            ',' if field.int_digits > 0 => {
+                field.commas = true;
+                field.int_digits += 1;
+            }
+            _ => break,
+        }
+        i += 1;
+    }
+
+    if chars.get(i) == Some(&'.') && (field.int_digits > 0 || chars.get(i + 1) == Some(&'#')) {
+        i += 1;
+        let frac = chars[i..].iter().take_while(|&&c| c == '#').count();
+        field.frac_digits = Some(frac);
+        i += frac;
+    }
+
+    // A lone `+`, `$` or `.` isn't a field
+    let digits = field.int_digits + field.frac_digits.unwrap_or(0);
+    if digits == 0 || (field.int_digits == 0 && field.frac_digits.is_none()) {
+        return (NumericField::default(), 0);
+    }
+
+    if starts_with("^^^^", i) {
+        field.exponent = true;
+        i += 4;
+    }
+    if !field.leading_plus {
+        if let Some(&c) = chars.get(i) {
+            if c == '+' || c == '-' {
+                field.trailing_sign = Some(c);
+                i += 1;
+            }
+        }
+    }
+
+    (field, i)
+}
+
+/// Find problems in a PRINT USING format string.
+///
+/// A format without any field can't print a value, so BASICA stops with
+/// "Illegal function call".
+pub fn check_using(format: &str) -> Vec<SyntaxError> {
+    let has_fields = parse_using(format)
+        .iter()
+        .any(|f| !matches!(f, UsingField::Literal(_)));
+    if has_fields || format.is_empty() {
+        return Vec::new();
+    }
+    vec![SyntaxError {
+        start: 0,
+        end: format.len(),
+        message: "Format string has no fields (use #, !, & or \\ \\)".to_string(),
+    }]
+}
+
+/// Format values with a PRINT USING format string.
+///
+/// The format is reused from the start if there are more values than fields,
+/// as BASICA does.
+pub fn format_using(format: &str, values: &[UsingValue]) -> String {
+    let fields = parse_using(format);
+    let mut out = String::new();
+    let has_fields = fields.iter().any(|f| !matches!(f, UsingField::Literal(_)));
+    if !has_fields {
+        return format.to_string();
+    }
+
+    let mut values = values.iter().peekable();
+    while values.peek().is_some() {
+        for field in &fields {
+            match field {
+                UsingField::Literal(text) => out.push_str(text),
+                _ => match values.next() {
+                    Some(value) => out.push_str(&format_field(field, value)),
+                    None => break,
+                },
+            }
+        }
+    }
+
+    out
+}
+
+fn format_field(field: &UsingField, value: &UsingValue) -> String {
+    match (field, value) {
+        (UsingField::FirstChar, UsingValue::Str(s)) => s.chars().next().unwrap_or(' ').to_string(),
+        (UsingField::FixedString(width), UsingValue::Str(s)) => {
+            format!("{:<width$.width$}", s, width = *width)
+        }
+        (UsingField::WholeString, UsingValue::Str(s)) => s.clone(),
+        (UsingField::Numeric(field), UsingValue::Number(n)) => format_number(field, *n),
+        // Type mismatch in BASICA; show the raw value
+        (_, UsingValue::Number(n)) => n.to_string(),
+        (_, UsingValue::Str(s)) => s.clone(),
+    }
+}
+
+fn format_number(field: &NumericField, n: f64) -> String {
+    let frac = field.frac_digits.unwrap_or(0);
+    let negative = n < 0.0;
+    let abs = n.abs();
+
+    if field.exponent {
+        let digits = field.int_digits.max(1);
+        let exp = if abs == 0.0 {
+            0
+        } else {
+            abs.log10().floor() as i32 - (digits as i32 - 1)
+        };
+        let mantissa = abs / 10f64.powi(exp);
+        let sign = if negative {
+            "-"
+        } else if field.leading_plus {
+            "+"
+        } else {
+            " "
+        };
+        return format!(
+            "{}{:.*}E{}{:02}",
+            sign,
+            frac,
+            mantissa,
+            if exp < 0 { '-' } else { '+' },
+            exp.abs()
+        );
+    }
+
+    let rounded = format!("{:.*}", frac, abs);
+    let (int_part, frac_part) = match rounded.split_once('.') {
+        Some((i, f)) => (i.to_string(), Some(f.to_string())),
+        None => (rounded.clone(), None),
+    };
+    // `.##` has no room for the leading zero
+    let mut int_part = if int_part == "0" && field.int_digits == 0 {
+        String::new()
+    } else {
+        int_part
+    };
+    if field.commas {
+        int_part = group_thousands(&int_part);
+    }
+
+    let mut body = int_part;
+    if field.dollar {
+        body.insert(0, '$');
+    }
+    if field.trailing_sign.is_none() {
+        if field.leading_plus {
+            body.insert(0, if negative { '-' } else { '+' });
+        } else if negative {
+            body.insert(0, '-');
+        }
+    }
+
+    // Pad to the field width; `$` and `**` positions count as digits
+    let width = field.int_digits + usize::from(field.leading_plus);
+    let overflow = body.len() > width;
+    let fill = if field.asterisk_fill { '*' } else { ' ' };
+    let mut out = String::new();
+    if overflow {
+        out.push('%');
+    } else {
+        out.extend(std::iter::repeat_n(fill, width - body.len()));
+    }
+    out.push_str(&body);
+
+    if let Some(frac_part) = frac_part {
+        out.push('.');
+        out.push_str(&frac_part);
+    } else if field.frac_digits == Some(0) {
+        out.push('.');
+    }
+
+    match field.trailing_sign {
+        Some('+') => out.push(if negative { '-' } else { '+' }),
+        Some(_) => out.push(if negative { '-' } else { ' ' }),
+        None => {}
+    }
+
+    out
+}
+
+fn group_thousands(digits: &str) -> String {
+    let mut out: Vec<char> = Vec::new();
+    for (i, c) in digits.chars().rev().enumerate() {
+        if i > 0 && i % 3 == 0 {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    out.into_iter().rev().collect()
+}
+
+/// Character scanner shared by the PLAY and DRAW parsers.
+///
+/// Both languages ignore spaces and accept `;` as a separator.
+struct Scanner {
+    chars: Vec<(usize, char)>,
+    pos: usize,
+    len: usize,
+}
+
+/// A numeric argument to a PLAY or DRAW command
+enum Argument {
+    /// A literal value with its byte range
+    Value(i64, usize, usize),
+    /// `=var;`, whose value is only known at runtime
+    Variable,
+    Missing,
+}
+
+impl Scanner {
+    fn new(source: &str) -> Self {
+        Self {
+            chars: source
+                .char_indices()
+                .map(|(i, c)| (i, c.to_ascii_uppercase()))
+                .collect(),
+            pos: 0,
+            len: source.len(),
+        }
+    }
+
+    /// Byte offset of the next unread character
+    fn offset(&self) -> usize {
+        self.chars.get(self.pos).map_or(self.len, |&(i, _)| i)
+    }
+
+    fn skip_blanks(&mut self) {
+        while let Some(&(_, c)) = self.chars.get(self.pos) {
+            if c == ' ' || c == ';' {
+                self.pos += 1;
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Next command character with its byte offset
+    fn next_command(&mut self) -> Option<(usize, char)> {
+        self.skip_blanks();
+        let item = self.chars.get(self.pos).copied();
+        if item.is_some() {
+            self.pos += 1;
+        }
+        item
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_blanks();
+        self.chars.get(self.pos).map(|&(_, c)| c)
+    }
+
+    fn bump(&mut self) {
+        self.skip_blanks();
+        if self.pos < self.chars.len() {
+            self.pos += 1;
+        }
+    }
+
+    fn expect(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Read an unsigned number, or `=var;` (whose value is unknown here)
+    fn number(&mut self) -> Option<i64> {
+        self.skip_blanks();
+        if self.peek() == Some('=') {
+            self.skip_variable_ref();
+            return None;
+        }
+        let start = self.pos;
+        while self
+            .chars
+            .get(self.pos)
+            .is_some_and(|(_, c)| c.is_ascii_digit())
+        {
+            self.pos += 1;
+        }
+        if start == self.pos {
+            return None;
+        }
+        self.chars[start..self.pos]
+            .iter()
+            .map(|&(_, c)| c)
+            .collect::<String>()
+            .parse()
+            .map_or(Some(i64::MAX), Some)
+    }
+
+    fn signed_number(&mut self) -> Option<i64> {
+        let sign = match self.peek() {
+            Some('-') => {
+                self.pos += 1;
+                -1
+            }
+            Some('+') => {
+                self.pos += 1;
+                1
+            }
+            _ => 1,
+        };
+        self.number().map(|n| n * sign)
+    }
+
+    /// Read a numeric argument, keeping its range for error reporting
+    fn argument(&mut self, signed: bool) -> Argument {
+        if self.peek() == Some('=') {
+            self.skip_variable_ref();
+            return Argument::Variable;
+        }
+        let start = self.offset();
+        let value = if signed {
+            self.signed_number()
+        } else {
+            self.number()
+        };
+        match value {
+            Some(n) => Argument::Value(n, start, self.offset()),
+            None => Argument::Missing,
+        }
+    }
+
+    fn dots(&mut self) -> u32 {
+        let mut dots = 0;
+        while self.chars.get(self.pos).is_some_and(|&(_, c)| c == '.') {
+            self.pos += 1;
+            dots += 1;
+        }
+        dots
+    }
+
+    /// Skip `=name;` in a numeric position
+    fn skip_variable_ref(&mut self) {
+        self.pos += 1;
+        self.skip_variable();
+    }
+
+    /// Skip a variable name up to and including its terminating `;`
+    fn skip_variable(&mut self) {
+        while let Some(&(_, c)) = self.chars.get(self.pos) {
+            self.pos += 1;
+            if c == ';' {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn play_notes_follow_octave_length_and_tempo() {
+        let notes = parse_play("O3 A L8 B- T60 C#. P4 MS N1");
+        let names: Vec<Option<&str>> = notes.iter().map(|n| n.name.as_deref()).collect();
+        assert_eq!(names, [Some("A"), Some("B-"), Some("C#"), None, Some("C")]);
+        assert!((notes[0].frequency - 440.0).abs() < 1e-9);
+        assert!((notes[0].seconds - 0.5).abs() < 1e-9);
+        assert_eq!(notes[1].length, 8);
+        // A dotted eighth at 60 beats a minute
+        assert!((notes[2].seconds - 0.75).abs() < 1e-9);
+        assert_eq!(notes[4].octave, 0);
+        assert_eq!(notes[4].sounded, 0.75);
+    }
+
+    #[test]
+    fn play_variables_are_skipped() {
+        let notes = parse_play("L=X; C XTUNE$; D");
+        assert_eq!(notes.len(), 2);
+        assert_eq!(notes[0].length, 4);
+    }
+
+    #[test]
+    fn draw_moves_from_the_origin() {
+        let path = trace_draw("R10 D5 BM+0,5 NL2 S8 U1");
+        let ends: Vec<(f64, f64)> = path.segments.iter().map(|s| s.to).collect();
+        assert_eq!(ends, [(10.0, 0.0), (10.0, 5.0), (8.0, 10.0), (10.0, 8.0)]);
+        assert!(draw_svg(&path).starts_with("<svg"));
+    }
+
+    #[test]
+    fn draw_turns_and_colours() {
+        let path = trace_draw("C2 A1 R4 TA180 R4");
+        assert_eq!(path.segments[0].color, 2);
+        let (x, y) = path.segments[0].to;
+        assert!(x.abs() < 1e-9 && (y + 4.0).abs() < 1e-9);
+        let (x, y) = path.segments[1].to;
+        assert!((x + 4.0).abs() < 1e-9 && (y + 4.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn using_formats_split_into_fields() {
+        assert_eq!(
+            parse_using("_#!\\  \\&"),
+            [
+                UsingField::Literal("#".to_string()),
+                UsingField::FirstChar,
+                UsingField::FixedString(4),
+                UsingField::WholeString,
+            ]
+        );
+    }
+
+    #[test]
+    fn using_numbers_match_basica() {
+        let format = |f: &str, n: f64| format_using(f, &[UsingValue::Number(n)]);
+        assert_eq!(format("##.##", 4.126), " 4.13");
+        assert_eq!(format("**$#,###.##", 1234.5), "**$1,234.50");
+        assert_eq!(format("+###", -5.0), "  -5");
+        assert_eq!(format("###-", -5.0), "  5-");
+        assert_eq!(format("##", 123.0), "%123");
+        assert_eq!(format("##.##^^^^", 1234.5), " 12.35E+02");
+    }
+
+    #[test]
+    fn using_repeats_for_extra_values() {
+        let values = [UsingValue::Str("ABC".to_string()), UsingValue::Number(7.0)];
+        assert_eq!(format_using("[!]", &values[..1]), "[A]");
+        assert_eq!(format_using("\\ \\ ", &values[..1]), "ABC ");
+        assert_eq!(
+            format_using("# ", &[UsingValue::Number(1.0), values[1].clone()]),
+            "1 7 "
+        );
+    }
+
+    fn messages(errors: Vec<SyntaxError>) -> Vec<String> {
+        errors.into_iter().map(|e| e.message).collect()
+    }
+
+    #[test]
+    fn play_arguments_are_range_checked() {
+        let errors = check_play("O7 L0 T20 C65 N85 MX Q");
+        let spans: Vec<(usize, usize)> = errors.iter().map(|e| (e.start, e.end)).collect();
+        assert_eq!(
+            messages(errors),
+            [
+                "Octave 7 is out of range (0 to 6)",
+                "Length 0 is out of range (1 to 64)",
+                "Tempo 20 is out of range (32 to 255)",
+                "Note length 65 is out of range (1 to 64)",
+                "Note number 85 is out of range (0 to 84)",
+                "Unknown music mode 'MX'",
+                "Unknown PLAY command 'Q'",
+            ]
+        );
+        assert_eq!(spans[0], (1, 2));
+        assert!(check_play("T255 O0 L64 C#16. N0 P1 MB ML X$A; O=N;").is_empty());
+    }
+
+    #[test]
+    fn play_commands_need_their_arguments() {
+        assert_eq!(messages(check_play("O")), ["Missing octave after 'O'"]);
+        assert_eq!(messages(check_play("M")), ["Missing music mode after 'M'"]);
+    }
+
+    #[test]
+    fn draw_arguments_are_checked() {
+        assert_eq!(
+            messages(check_draw("M10 A4 TB C256 P1 K")),
+            [
+                "Expected ',' between the M coordinates",
+                "Angle 4 is out of range (0 to 3)",
+                "Expected 'TA' (turn angle)",
+                "Colour 256 is out of range (0 to 255)",
+                "Missing border colour after 'P'",
+                "Unknown DRAW command 'K'",
+            ]
+        );
+        assert!(check_draw("BM-5,+5 NU10 TA-90 S8 P1,2 X$A;").is_empty());
+    }
+
+    #[test]
+    fn using_formats_need_a_field() {
+        let errors = check_using("Total");
+        assert_eq!((errors[0].start, errors[0].end), (0, 5));
+        assert!(check_using("Total ###.##").is_empty());
+        assert!(check_using("").is_empty());
+    }
+}
@@ -1,5 +1,8 @@
+use std::collections::HashSet;
 use tower_lsp::lsp_types::*;
 
+use crate::renumber::{byte_offset, leading_line_number, line_references, utf16_len};
+
 /// Find all references to a variable or line number
 pub fn find_references(source: &str, position: Position, uri: Url) -> Vec<Location> {
     let lines: Vec<&str> = source.lines().collect();
@@ -8,7 +11,7 @@ pub fn find_references(source: &str, position: Position, uri: Url) -> Vec<Locati
         None => return vec![],
     };
 
-    let word = match get_word_at_position(line, position.character as usize) {
+    let word = match get_word_at_position(line, byte_offset(line, position.character)) {
         Some(w) => w,
         None => return vec![],
     };
@@ -19,71 +22,31 @@ pub fn find_references(source: &str, position: Position, uri: Url) -> Vec<Locati
     }
 
     // It's a variable - find all occurrences
-    let var_upper = word.to_uppercase();
+    let var_upper = word.to_ascii_uppercase();
     find_variable_references(source, &var_upper, &uri)
 }
 
 /// Find all references to a BASIC line number (GOTO, GOSUB, THEN, RESTORE, etc.)
-fn find_line_references(source: &str, target_line: u32, uri: &Url) -> Vec<Location> {
+pub fn find_line_references(source: &str, target_line: u32, uri: &Url) -> Vec<Location> {
     let mut refs = Vec::new();
-    let target_str = target_line.to_string();
 
     for (line_idx, line) in source.lines().enumerate() {
-        let upper = line.to_uppercase();
-
         // Check if this line IS the target line (definition)
-        let trimmed = line.trim_start();
-        if let Some(first_word) = trimmed.split_whitespace().next() {
-            if first_word == target_str {
-                refs.push(Location {
-                    uri: uri.clone(),
-                    range: Range {
-                        start: Position {
-                            line: line_idx as u32,
-                            character: 0,
-                        },
-                        end: Position {
-                            line: line_idx as u32,
-                            character: first_word.len() as u32,
-                        },
-                    },
-                });
+        if let Some((num, start, end)) = leading_line_number(line) {
+            if num == target_line {
+                refs.push(line_location(uri, line_idx, line, start, end));
             }
         }
 
-        // Find references in GOTO, GOSUB, THEN, RESTORE, ON...GOTO/GOSUB
-        for keyword in &["GOTO ", "GOSUB ", "THEN ", "RESTORE "] {
-            let mut search_start = 0;
-            while let Some(kw_pos) = upper[search_start..].find(keyword) {
-                let abs_pos = search_start + kw_pos + keyword.len();
-                let after = &line[abs_pos..];
-
-                // Parse line numbers (comma-separated for ON...GOTO/GOSUB)
-                for num_part in after.split(',') {
-                    let num_str = num_part.trim().split_whitespace().next().unwrap_or("");
-                    if num_str == target_str {
-                        let char_start = abs_pos + (num_part.len() - num_part.trim_start().len());
-                        refs.push(Location {
-                            uri: uri.clone(),
-                            range: Range {
-                                start: Position {
-                                    line: line_idx as u32,
-                                    character: char_start as u32,
-                                },
-                                end: Position {
-                                    line: line_idx as u32,
-                                    character: (char_start + num_str.len()) as u32,
-                                },
-                            },
-                        });
-                    }
-                    // Stop if we hit a non-number (end of line number list)
-                    if num_str.parse::<u32>().is_err() {
-                        break;
-                    }
-                }
-
-                search_start = abs_pos;
+        for (start, num_str) in line_number_references(line) {
+            if num_str.parse() == Ok(target_line) {
+                refs.push(line_location(
+                    uri,
+                    line_idx,
+                    line,
+                    start,
+                    start + num_str.len(),
+                ));
             }
         }
     }
@@ -91,12 +54,57 @@ fn find_line_references(source: &str, target_line: u32, uri: &Url) -> Vec<Locati
     refs
 }
 
+/// A byte range of a source line as a location
+fn line_location(uri: &Url, line_idx: usize, line: &str, start: usize, end: usize) -> Location {
+    Location {
+        uri: uri.clone(),
+        range: Range {
+            start: Position {
+                line: line_idx as u32,
+                character: utf16_len(&line[..start]),
+            },
+            end: Position {
+                line: line_idx as u32,
+                character: utf16_len(&line[..end]),
+            },
+        },
+    }
+}
+
+/// All line numbers the program refers to, without their locations
+pub fn referenced_line_numbers(source: &str) -> HashSet<u32> {
+    source
+        .lines()
+        .flat_map(line_number_references)
+        .filter_map(|(_, num_str)| num_str.parse().ok())
+        .collect()
+}
+
+/// Line numbers a source line refers to, with their byte offsets
+fn line_number_references(line: &str) -> Vec<(usize, &str)> {
+    line_references(line)
+        .into_iter()
+        .map(|(start, end)| (start, &line[start..end]))
+        .collect()
+}
+
 /// Find all references to a variable
 fn find_variable_references(source: &str, var_name: &str, uri: &Url) -> Vec<Location> {
+    find_variable_ranges(source, var_name)
+        .into_iter()
+        .map(|range| Location {
+            uri: uri.clone(),
+            range,
+        })
+        .collect()
+}
+
+/// Find the ranges of all occurrences of a variable
+pub fn find_variable_ranges(source: &str, var_name: &str) -> Vec<Range> {
     let mut refs = Vec::new();
 
     for (line_idx, line) in source.lines().enumerate() {
-        let upper = line.to_uppercase();
+        let upper = line.to_ascii_uppercase();
         let mut search_start = 0;
 
         while let Some(pos) = upper[search_start..].find(var_name) {
@@ -123,17 +131,14 @@ fn find_variable_references(source: &str, var_name: &str, uri: &Url) -> Vec<Loca
                     after_pos
                 };
 
-                refs.push(Location {
-                    uri: uri.clone(),
-                    range: Range {
-                        start: Position {
-                            line: line_idx as u32,
-                            character: abs_pos as u32,
-                        },
-                        end: Position {
-                            line: line_idx as u32,
-                            character: end_pos as u32,
-                        },
+                refs.push(Range {
+                    start: Position {
+                        line: line_idx as u32,
+                        character: utf16_len(&line[..abs_pos]),
+                    },
+                    end: Position {
+                        line: line_idx as u32,
+                        character: utf16_len(&line[..end_pos]),
                     },
                 });
             }
@@ -169,3 +174,52 @@ fn get_word_at_position(line: &str, char_pos: usize) -> Option<&str> {
 fn is_word_char(b: u8) -> bool {
     b.is_ascii_alphanumeric() || b == b'_' || b == b'$'
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn numbers_run_into_the_next_statement() {
+        let mut refs = line_number_references("10 GOSUB 100: GOTO 200");
+        refs.sort();
+        assert_eq!(refs, [(9, "100"), (19, "200")]);
+    }
+
+    #[test]
+    fn on_goto_lists() {
+        let line = "10 ON X GOTO 100, 200,300";
+        let refs = line_number_references(line);
+        assert_eq!(refs, [(13, "100"), (18, "200"), (22, "300")]);
+        for (start, number) in refs {
+            assert_eq!(&line[start..start + number.len()], number);
+        }
+    }
+
+    #[test]
+    fn every_jump_keyword_counts() {
+        let source = "10 IF A THEN 20 ELSE 30\n\
+            20 ON ERROR GOTO 40: RESUME 30\n\
+            30 GOSUB30: IF ERL = 30 THEN RUN 30\n\
+            40 REMARK GOTO 30\n";
+        let uri = Url::parse("file:///test.bas").unwrap();
+        let lines: Vec<u32> = find_line_references(source, 30, &uri)
+            .iter()
+            .map(|l| l.range.start.line)
+            .collect();
+        assert_eq!(lines, [0, 1, 2, 2, 2, 2]);
+        assert_eq!(referenced_line_numbers(source), HashSet::from([20, 30, 40]));
+    }
+
+    #[test]
+    fn columns_count_utf16_units() {
+        let source = "10 PRINT \"é😀\": GOTO 10\n";
+        let uri = Url::parse("file:///test.bas").unwrap();
+        let refs = find_line_references(source, 10, &uri);
+        assert_eq!(refs[1].range.start.character, 21);
+        assert_eq!(refs[1].range.end.character, 23);
+
+        let refs = find_references(source, Position::new(0, 22), uri);
+        assert_eq!(refs.len(), 2);
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+use tower_lsp::lsp_types::*;
+
+use crate::dialect::Dialect;
+use crate::semantic_tokens::{lex_line, LexKind, Lexeme};
+
+/// Highest line number a program can have
+pub const MAX_LINE_NUMBER: u32 = 65529;
+
+/// Keywords followed by one or more line-number references
+pub const JUMP_KEYWORDS: &[&str] = &["GOTO", "GOSUB", "THEN", "ELSE", "RESTORE", "RESUME", "RUN"];
+
+/// Renumber a program, returning one edit per changed line.
+///
+/// Lines are numbered in source order starting at `start`, and every
+/// GOTO/GOSUB/THEN/ELSE/RESTORE/RESUME/RUN/ERL reference is updated to match.
+/// References to lines that don't exist are left untouched. Fails like
+/// RENUM, with "Illegal function call", if the new numbers would run past
+/// 65529.
+pub fn renumber_edits(source: &str, start: u32, step: u32) -> Result<Vec<TextEdit>, String> {
+    let mapping = build_mapping(source, start, step)?;
+    let mut edits = Vec::new();
+
+    for (line_idx, line) in source.lines().enumerate() {
+        let new_text = renumber_line(line, &mapping);
+        if new_text != line {
+            edits.push(TextEdit {
+                range: Range {
+                    start: Position {
+                        line: line_idx as u32,
+                        character: 0,
+                    },
+                    end: Position {
+                        line: line_idx as u32,
+                        character: utf16_len(line),
+                    },
+                },
+                new_text,
+            });
+        }
+    }
+
+    Ok(edits)
+}
+
+/// Map old line numbers to new ones, in source order
+fn build_mapping(source: &str, start: u32, step: u32) -> Result<HashMap<u32, u32>, String> {
+    let mut mapping = HashMap::new();
+    let mut next = Some(start);
+
+    for line in source.lines() {
+        if let Some((num, _, _)) = leading_line_number(line) {
+            let new = next.filter(|&n| n <= MAX_LINE_NUMBER).ok_or_else(|| {
+                format!(
+                    "Illegal function call: line {} would be numbered past {}",
+                    num, MAX_LINE_NUMBER
+                )
+            })?;
+            mapping.entry(num).or_insert(new);
+            next = new.checked_add(step);
+        }
+    }
+
+    Ok(mapping)
+}
+
+/// Rewrite the line number and all line references on a single line
+fn renumber_line(line: &str, mapping: &HashMap<u32, u32>) -> String {
+    let mut out = String::with_capacity(line.len());
+    let mut pos = 0;
+
+    let number = leading_line_number(line).map(|(_, start, end)| (start, end));
+    for (start, end) in number.into_iter().chain(line_references(line)) {
+        out.push_str(&line[pos..start]);
+        out.push_str(&map_number(&line[start..end], mapping));
+        pos = end;
+    }
+    out.push_str(&line[pos..]);
+
+    out
+}
+
+/// Where a scan for line references is in a statement
+#[derive(Clone, Copy, PartialEq)]
+enum Scan {
+    Other,
+    /// After a jump keyword or a `,` in its list
+    Target,
+    /// After a target, where a `,` continues an ON ... GOTO list
+    AfterTarget,
+}
+
+/// The line numbers a source line refers to, as byte ranges: the targets
+/// of GOTO, GOSUB, THEN, ELSE, RESTORE, RESUME and RUN, every number of an
+/// ON ... GOTO list, and the line an ERL is compared with. The basica
+/// lexer reads the line, so `GOTO100` counts and `REMARK GOTO 10` doesn't
+pub fn line_references(line: &str) -> Vec<(usize, usize)> {
+    let upper = line.to_ascii_uppercase();
+    let lexemes = lex_line(&upper, Dialect::Basica);
+    let mut references = Vec::new();
+    let mut scan = Scan::Other;
+
+    for (idx, lexeme) in lexemes.iter().enumerate() {
+        let text = &upper[lexeme.start..lexeme.end];
+        scan = match (lexeme.kind, scan) {
+            (LexKind::Keyword, _) if JUMP_KEYWORDS.contains(&text) => Scan::Target,
+            (LexKind::Number, Scan::Target) => {
+                if is_line_number(&upper, lexeme) {
+                    references.push((lexeme.start, lexeme.end));
+                }
+                Scan::AfterTarget
+            }
+            (LexKind::Operator(b','), Scan::AfterTarget) => Scan::Target,
+            (LexKind::Keyword, _) if text == "ERL" => {
+                // ERL = 100, ERL <> 100
+                let operators = lexemes[idx + 1..]
+                    .iter()
+                    .take_while(|l| is_comparison(l))
+                    .count();
+                if let Some(number) = lexemes.get(idx + 1 + operators) {
+                    if operators > 0 && is_line_number(&upper, number) {
+                        references.push((number.start, number.end));
+                    }
+                }
+                Scan::Other
+            }
+            (LexKind::Number, _) => {
+                // 100 = ERL
+                let operators = lexemes[idx + 1..]
+                    .iter()
+                    .take_while(|l| is_comparison(l))
+                    .count();
+                let erl = lexemes
+                    .get(idx + 1 + operators)
+                    .is_some_and(|l| l.kind == LexKind::Keyword && &upper[l.start..l.end] == "ERL");
+                if operators > 0 && erl && is_line_number(&upper, lexeme) {
+                    references.push((lexeme.start, lexeme.end));
+                }
+                Scan::Other
+            }
+            _ => Scan::Other,
+        };
+    }
+
+    references
+}
+
+fn is_comparison(lexeme: &Lexeme) -> bool {
+    matches!(lexeme.kind, LexKind::Operator(b'=' | b'<' | b'>'))
+}
+
+/// Whether a lexeme is a number that can name a line, like `100` but not `1.5`
+fn is_line_number(upper: &str, lexeme: &Lexeme) -> bool {
+    lexeme.kind == LexKind::Number
+        && upper[lexeme.start..lexeme.end]
+            .bytes()
+            .all(|b| b.is_ascii_digit())
+}
+
+fn map_number(digits: &str, mapping: &HashMap<u32, u32>) -> String {
+    match digits.parse::<u32>() {
+        Ok(num) => mapping
+            .get(&num)
+            .map_or_else(|| digits.to_string(), |n| n.to_string()),
+        Err(_) => digits.to_string(),
+    }
+}
+
+/// Get the line number at the start of a line, with its byte range
+pub fn leading_line_number(line: &str) -> Option<(u32, usize, usize)> {
+    let start = line.len() - line.trim_start().len();
+    let digits = line[start..]
+        .bytes()
+        .take_while(|b| b.is_ascii_digit())
+        .count();
+    if digits == 0 {
+        return None;
+    }
+    let end = start + digits;
+    line[start..end].parse().ok().map(|num| (num, start, end))
+}
+
+/// Length of some text in UTF-16 code units, which LSP columns count
+pub fn utf16_len(text: &str) -> u32 {
+    text.encode_utf16().count() as u32
+}
+
+/// Byte offset of an LSP column in a line, clamped to the end of the line
+pub fn byte_offset(line: &str, column: u32) -> usize {
+    let mut units = 0;
+    for (offset, c) in line.char_indices() {
+        if units >= column as usize {
+            return offset;
+        }
+        units += c.len_utf16();
+    }
+    line.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mapping_follows_source_order() {
+        let source = "5 PRINT\n7 GOSUB 30\n30 RETURN\n";
+        let mapping = build_mapping(source, 100, 20).unwrap();
+        assert_eq!(mapping, HashMap::from([(5, 100), (7, 120), (30, 140)]));
+    }
+
+    #[test]
+    fn references_are_rewritten() {
+        let source = "1 ON X GOTO 2, 3\n2 IF ERL = 3 THEN 1 ELSE RESUME 3\n3 GOTO 99: REM GOTO 1\n";
+        let edits = renumber_edits(source, 10, 10).unwrap();
+        let lines: Vec<&str> = edits.iter().map(|e| e.new_text.as_str()).collect();
+        assert_eq!(
+            lines,
+            [
+                "10 ON X GOTO 20, 30",
+                "20 IF ERL = 30 THEN 10 ELSE RESUME 30",
+                "30 GOTO 99: REM GOTO 1",
+            ]
+        );
+    }
+
+    #[test]
+    fn numbers_past_65529_fail() {
+        let source = "10 PRINT\n20 PRINT\n30 PRINT\n";
+        assert!(build_mapping(source, 65519, 5).is_ok());
+        assert!(build_mapping(source, 65520, 10)
+            .unwrap_err()
+            .starts_with("Illegal function call"));
+        assert!(build_mapping(source, 1, u32::MAX).is_err());
+    }
+
+    #[test]
+    fn crunched_and_remark_lines() {
+        let source = "10 GOTO20:REMARK GOTO 10\n20 IF X THEN10ELSE 20\n";
+        let edits = renumber_edits(source, 100, 100).unwrap();
+        let lines: Vec<&str> = edits.iter().map(|e| e.new_text.as_str()).collect();
+        assert_eq!(
+            lines,
+            ["100 GOTO200:REMARK GOTO 10", "200 IF X THEN100ELSE 200"]
+        );
+    }
+
+    #[test]
+    fn references_are_found_by_the_lexer() {
+        let line = "10 ON N GOSUB 100,200: RUN 300: x$ = \"GOTO 5\": RESTORE";
+        let numbers: Vec<&str> = line_references(line)
+            .into_iter()
+            .map(|(start, end)| &line[start..end])
+            .collect();
+        assert_eq!(numbers, ["100", "200", "300"]);
+        assert_eq!(
+            line_references("10 IF ERL<>40 THEN RESUME NEXT"),
+            [(11, 13)]
+        );
+        assert!(line_references("10 GOTO X: PRINT 1.5").is_empty());
+    }
+
+    #[test]
+    fn edits_end_in_utf16_columns() {
+        let source = "10 PRINT \"é😀\": GOTO 10\n";
+        let edits = renumber_edits(source, 20, 10).unwrap();
+        assert_eq!(edits[0].range.end.character, 23);
+        assert_eq!(edits[0].new_text, "20 PRINT \"é😀\": GOTO 20");
+    }
+
+    #[test]
+    fn columns_map_to_byte_offsets() {
+        assert_eq!(byte_offset("é😀x", 1), 2);
+        assert_eq!(byte_offset("é😀x", 3), 6);
+        assert_eq!(byte_offset("é😀x", 99), 7);
+        assert_eq!(utf16_len("é😀x"), 4);
+    }
+}
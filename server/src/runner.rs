@@ -0,0 +1,553 @@
+use basica::interpreter::{Interpreter, Sound, Step, Value};
+use basica::lexer::Lexer;
+use basica::parser::Parser;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tower_lsp::lsp_types::*;
+
+use crate::codes;
+use crate::config::RunConfig;
+use crate::diagnostics::find_source_line_for_basic_line;
+use crate::renumber::utf16_len;
+use crate::tokenized;
+
+/// Statements to run between looks at the clock and the stop flag
+const CHECK_EVERY: u64 = 1024;
+
+/// How a run ended
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    Finished,
+    /// A runtime error, with the BASIC line number it happened on
+    Error {
+        message: String,
+        line: Option<u32>,
+    },
+    TimedOut {
+        line: Option<u32>,
+    },
+    StepLimit {
+        line: Option<u32>,
+    },
+    /// Stopped by the caller, e.g. because the program was run again
+    Stopped,
+}
+
+/// How many times each BASIC line was entered, like TRON would print it
+pub type Hits = HashMap<u32, u64>;
+
+/// Run a program with the basica interpreter in a sandbox, which refuses
+/// file, device and port access, passing everything it prints to `output`
+/// and answering INPUT from `input`. Runs until the program ends, fails,
+/// hits a limit or `stop` is set
+pub fn run(
+    source: &str,
+    input: Vec<String>,
+    limits: &RunConfig,
+    output: Box<dyn FnMut(&str) + Send>,
+    stop: &AtomicBool,
+) -> (Outcome, Hits) {
+    run_with(source, input, limits, output, stop, |_, _, _| {})
+}
+
+/// `run`, calling `observe` with the program and the number of statements
+/// run so far after every statement, and once more with `true` when the
+/// run has ended
+pub fn run_with(
+    source: &str,
+    input: Vec<String>,
+    limits: &RunConfig,
+    output: Box<dyn FnMut(&str) + Send>,
+    stop: &AtomicBool,
+    observe: impl FnMut(&Debuggee, u64, bool),
+) -> (Outcome, Hits) {
+    let mut debuggee = match Debuggee::new(source, output) {
+        Ok(debuggee) => debuggee,
+        Err(message) => {
+            let outcome = Outcome::Error {
+                message,
+                line: None,
+            };
+            return (outcome, Hits::new());
+        }
+    };
+    debuggee.set_input(input);
+    drive(&mut debuggee, limits, stop, observe)
+}
+
+/// Step a loaded program until it ends, fails or hits a limit
+fn drive(
+    debuggee: &mut Debuggee,
+    limits: &RunConfig,
+    stop: &AtomicBool,
+    mut observe: impl FnMut(&Debuggee, u64, bool),
+) -> (Outcome, Hits) {
+    let mut hits = Hits::new();
+    let timeout = Duration::from_secs(limits.timeout);
+    let started = Instant::now();
+    let mut steps: u64 = 0;
+    let outcome = loop {
+        // Every entry counts, even a jump back to the same line
+        if let Some(line) = debuggee.line().filter(|_| debuggee.line_started()) {
+            *hits.entry(line).or_insert(0) += 1;
+        }
+        if let Some(outcome) = debuggee.step() {
+            break outcome;
+        }
+
+        steps += 1;
+        observe(debuggee, steps, false);
+        if steps >= limits.max_steps {
+            break Outcome::StepLimit {
+                line: debuggee.line(),
+            };
+        }
+        if steps.is_multiple_of(CHECK_EVERY) {
+            if stop.load(Ordering::Relaxed) {
+                break Outcome::Stopped;
+            }
+            if started.elapsed() >= timeout {
+                break Outcome::TimedOut {
+                    line: debuggee.line(),
+                };
+            }
+        }
+    };
+    observe(debuggee, steps, true);
+    (outcome, hits)
+}
+
+/// An immediate-mode session like BASICA's `Ok` prompt: direct statements
+/// run one at a time on an empty program, keeping variables between them
+pub struct Immediate {
+    debuggee: Debuggee,
+    printed: Arc<Mutex<String>>,
+}
+
+impl Immediate {
+    pub fn new() -> Result<Self, String> {
+        let printed = Arc::new(Mutex::new(String::new()));
+        let output = {
+            let printed = printed.clone();
+            Box::new(move |text: &str| printed.lock().unwrap().push_str(text))
+        };
+        Ok(Self {
+            debuggee: Debuggee::new("", output)?,
+            printed,
+        })
+    }
+
+    /// Run a direct statement, or print an expression when it isn't one,
+    /// returning what it printed and how it ended
+    pub fn execute(&mut self, text: &str, limits: &RunConfig) -> (String, Outcome) {
+        let outcome = self.direct(text, limits);
+        let printed = std::mem::take(&mut *self.printed.lock().unwrap());
+
+        // `HEX$(255)` on its own is a syntax error; show its value instead
+        let is_syntax_error =
+            matches!(&outcome, Outcome::Error { message, .. } if message == "Syntax error");
+        if is_syntax_error && printed.is_empty() {
+            let printing = self.direct(&format!("PRINT {}", text), limits);
+            let printed = std::mem::take(&mut *self.printed.lock().unwrap());
+            if !matches!(printing, Outcome::Error { .. }) {
+                return (printed, printing);
+            }
+        }
+        (printed, outcome)
+    }
+
+    fn direct(&mut self, text: &str, limits: &RunConfig) -> Outcome {
+        if let Err(message) = self.debuggee.start_direct(text) {
+            return runtime_error(&message);
+        }
+        let (outcome, _) = drive(
+            &mut self.debuggee,
+            limits,
+            &AtomicBool::new(false),
+            |_, _, _| {},
+        );
+        outcome
+    }
+}
+
+/// A program loaded into the interpreter, run one statement at a time
+pub struct Debuggee {
+    interpreter: Interpreter,
+    /// First line of each subroutine on the GOSUB stack, outermost first
+    entries: Vec<u32>,
+    /// What PLAY, SOUND and BEEP sent to the speaker so far
+    tones: Arc<Mutex<Vec<Tone>>>,
+}
+
+/// One level of the GOSUB stack
+pub struct Frame {
+    /// `main`, or `GOSUB <line>` for a subroutine
+    pub name: String,
+    /// BASIC line number being run at this level
+    pub line: u32,
+}
+
+/// The graphics screen: one color attribute per pixel, row by row
+#[derive(Clone, PartialEq)]
+pub struct Screen {
+    /// The `SCREEN` mode, e.g. 1 for 320x200 in 4 colors
+    pub mode: u8,
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+    /// Background color and CGA palette set by `COLOR` in SCREEN 1
+    pub background: u8,
+    pub palette: u8,
+}
+
+/// A tone of the PC speaker, or a silence when `frequency` is 0
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Tone {
+    /// In Hz
+    pub frequency: f64,
+    pub seconds: f64,
+}
+
+/// An array's elements, as `(subscripts, value)` pairs
+pub struct ArrayValues {
+    pub name: String,
+    pub elements: Vec<(String, String)>,
+}
+
+impl Debuggee {
+    /// Parse a program and load it, sandboxed, with no console input
+    pub fn new(source: &str, output: Box<dyn FnMut(&str) + Send>) -> Result<Self, String> {
+        let tokens = Lexer::new(source).tokenize();
+        let program = Parser::new(tokens).parse()?;
+
+        let mut interpreter = Interpreter::new(program);
+        interpreter.set_sandboxed(true);
+        interpreter.set_output(output);
+        // There's no console to type into, so INPUT sees the end of input
+        interpreter.set_input(Box::new(|| None));
+        // There's no speaker either; keep the tones for exporting
+        let tones = Arc::new(Mutex::new(Vec::new()));
+        {
+            let tones = tones.clone();
+            interpreter.set_sound(Box::new(move |sound: Sound| {
+                tones.lock().unwrap().push(Tone {
+                    frequency: sound.frequency,
+                    seconds: sound.seconds,
+                });
+            }));
+        }
+        Ok(Self {
+            interpreter,
+            entries: Vec::new(),
+            tones,
+        })
+    }
+
+    /// Answer INPUT with these lines, then with the end of input
+    pub fn set_input(&mut self, lines: Vec<String>) {
+        let mut lines = lines.into_iter();
+        self.interpreter.set_input(Box::new(move || lines.next()));
+    }
+
+    /// Make a direct statement, typed without a line number, the next to
+    /// run; stepping then runs it instead of the program
+    pub fn start_direct(&mut self, text: &str) -> Result<(), String> {
+        self.entries.clear();
+        self.interpreter.start_direct(text)
+    }
+
+    /// Run one statement, returning how the program ended if it did
+    pub fn step(&mut self) -> Option<Outcome> {
+        match self.interpreter.step() {
+            Ok(Step::Continue) => {}
+            Ok(Step::Finished) => return Some(Outcome::Finished),
+            Err(message) => return Some(runtime_error(&message)),
+        }
+
+        // Remember where each subroutine started, to name its frame
+        let depth = self.depth();
+        self.entries.truncate(depth);
+        if depth > self.entries.len() {
+            let line = self.line().unwrap_or(0);
+            self.entries.resize(depth, line);
+        }
+        None
+    }
+
+    /// The BASIC line number of the next statement to run
+    pub fn line(&self) -> Option<u32> {
+        self.interpreter.current_line()
+    }
+
+    /// Whether the next statement is the first of its line, so running it
+    /// enters the line, as after a GOTO to the line it is on
+    pub fn line_started(&self) -> bool {
+        self.interpreter.at_line_start()
+    }
+
+    /// How many GOSUBs are waiting for a RETURN
+    pub fn depth(&self) -> usize {
+        self.interpreter.gosub_stack().len()
+    }
+
+    /// The GOSUB stack as frames, innermost first
+    pub fn frames(&self) -> Vec<Frame> {
+        let mut frames = Vec::new();
+        let mut line = self.line().unwrap_or(0);
+        // Each GOSUB's own line is where the level below it is running
+        let mut calls = self.interpreter.gosub_stack();
+        for level in (0..=calls.len()).rev() {
+            let name = match level.checked_sub(1).and_then(|i| self.entries.get(i)) {
+                Some(entry) => format!("GOSUB {}", entry),
+                None if level == 0 => "main".to_string(),
+                None => "GOSUB".to_string(),
+            };
+            frames.push(Frame { name, line });
+            if let Some(call) = calls.pop() {
+                line = call;
+            }
+        }
+        frames
+    }
+
+    /// The graphics screen, or `None` in text mode
+    pub fn screen(&self) -> Option<Screen> {
+        let screen = self.interpreter.screen();
+        if screen.mode == 0 || screen.pixels.is_empty() {
+            return None;
+        }
+        Some(Screen {
+            mode: screen.mode,
+            width: screen.width,
+            height: screen.height,
+            pixels: screen.pixels,
+            background: screen.background,
+            palette: screen.palette,
+        })
+    }
+
+    /// The tones played so far, in order
+    pub fn tones(&self) -> Vec<Tone> {
+        self.tones.lock().unwrap().clone()
+    }
+
+    /// Scalar variables and their values, as PRINT would show them
+    pub fn variables(&self) -> Vec<(String, String)> {
+        let mut variables: Vec<(String, String)> = self
+            .interpreter
+            .variables()
+            .into_iter()
+            .map(|(name, value)| (name, display(&value)))
+            .collect();
+        variables.sort();
+        variables
+    }
+
+    /// Arrays with every element, subscripts written like `(2,3)`
+    pub fn arrays(&self) -> Vec<ArrayValues> {
+        let mut arrays: Vec<ArrayValues> = self
+            .interpreter
+            .arrays()
+            .into_iter()
+            .map(|array| {
+                // Elements are stored with the last subscript varying fastest
+                let elements = array
+                    .values
+                    .iter()
+                    .enumerate()
+                    .map(|(mut index, value)| {
+                        let mut subscripts = Vec::new();
+                        for &upper in array.dims.iter().rev() {
+                            let size = (upper + 1).saturating_sub(array.base).max(1);
+                            subscripts.push((array.base + index % size).to_string());
+                            index /= size;
+                        }
+                        subscripts.reverse();
+                        (format!("({})", subscripts.join(",")), display(value))
+                    })
+                    .collect();
+                ArrayValues {
+                    name: array.name,
+                    elements,
+                }
+            })
+            .collect();
+        arrays.sort_by(|a, b| a.name.cmp(&b.name));
+        arrays
+    }
+
+    /// Evaluate an expression as immediate mode would
+    pub fn evaluate(&mut self, expression: &str) -> Result<String, String> {
+        self.interpreter
+            .evaluate(expression)
+            .map(|value| display(&value))
+    }
+
+    /// Whether a condition holds: any non-zero number or non-empty string
+    pub fn is_true(&mut self, condition: &str) -> Result<bool, String> {
+        Ok(match self.interpreter.evaluate(condition)? {
+            Value::Integer(n) => n != 0,
+            Value::Single(n) => n != 0.0,
+            Value::Double(n) => n != 0.0,
+            Value::String(s) => !s.is_empty(),
+        })
+    }
+}
+
+fn display(value: &Value) -> String {
+    match value {
+        Value::Integer(n) => n.to_string(),
+        Value::Single(n) => tokenized::format_number(*n as f64, true),
+        Value::Double(n) => tokenized::format_number(*n, false),
+        Value::String(s) => format!("\"{}\"", s),
+    }
+}
+
+/// Split an interpreter error like "Subscript out of range in 230" into
+/// the message and line number
+fn runtime_error(message: &str) -> Outcome {
+    if let Some((text, line)) = message.rsplit_once(" in ") {
+        if let Ok(line) = line.trim().parse() {
+            return Outcome::Error {
+                message: text.to_string(),
+                line: Some(line),
+            };
+        }
+    }
+    Outcome::Error {
+        message: message.to_string(),
+        line: None,
+    }
+}
+
+impl Outcome {
+    /// Short name for the `basica.run` result
+    pub fn status(&self) -> &'static str {
+        match self {
+            Outcome::Finished => "finished",
+            Outcome::Error { .. } => "error",
+            Outcome::TimedOut { .. } => "timeout",
+            Outcome::StepLimit { .. } => "stepLimit",
+            Outcome::Stopped => "stopped",
+        }
+    }
+
+    /// The message BASICA would print when the run ends, if any
+    pub fn message(&self, limits: &RunConfig) -> Option<String> {
+        let in_line = |line: &Option<u32>| line.map(|l| format!(" in {}", l)).unwrap_or_default();
+        match self {
+            Outcome::Finished | Outcome::Stopped => None,
+            Outcome::Error { message, line } => Some(format!("{}{}", message, in_line(line))),
+            Outcome::TimedOut { line } => Some(format!(
+                "Stopped after {} seconds{}",
+                limits.timeout,
+                in_line(line)
+            )),
+            Outcome::StepLimit { line } => Some(format!(
+                "Stopped after {} statements{}",
+                limits.max_steps,
+                in_line(line)
+            )),
+        }
+    }
+
+    /// A runtime error as a diagnostic covering the line it happened on
+    pub fn diagnostic(&self, source: &str) -> Option<Diagnostic> {
+        let Outcome::Error {
+            message,
+            line: Some(line),
+        } = self
+        else {
+            return None;
+        };
+        let source_line = find_source_line_for_basic_line(source, *line);
+        let length = source
+            .lines()
+            .nth(source_line as usize)
+            .map_or(0, |l| utf16_len(l.trim_end()));
+
+        let mut diagnostic = Diagnostic {
+            range: Range {
+                start: Position {
+                    line: source_line,
+                    character: 0,
+                },
+                end: Position {
+                    line: source_line,
+                    character: length,
+                },
+            },
+            severity: Some(DiagnosticSeverity::ERROR),
+            source: Some("basica".to_string()),
+            message: format!("{} in {}", message, line),
+            ..Default::default()
+        };
+        if let Some(check) = codes::find("runtime-error") {
+            check.tag(&mut diagnostic);
+        }
+        Some(diagnostic)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runtime_errors_carry_their_line() {
+        assert_eq!(
+            runtime_error("Subscript out of range in 230"),
+            Outcome::Error {
+                message: "Subscript out of range".to_string(),
+                line: Some(230),
+            }
+        );
+        assert_eq!(
+            runtime_error("Type mismatch"),
+            Outcome::Error {
+                message: "Type mismatch".to_string(),
+                line: None,
+            }
+        );
+    }
+
+    #[test]
+    fn outcomes_read_like_basica() {
+        let limits = RunConfig {
+            timeout: 5,
+            max_steps: 100,
+        };
+        assert_eq!(Outcome::Finished.message(&limits), None);
+        assert_eq!(
+            Outcome::TimedOut { line: Some(40) }.message(&limits),
+            Some("Stopped after 5 seconds in 40".to_string())
+        );
+        assert_eq!(
+            Outcome::StepLimit { line: None }.message(&limits),
+            Some("Stopped after 100 statements".to_string())
+        );
+        assert_eq!(Outcome::StepLimit { line: None }.status(), "stepLimit");
+    }
+
+    #[test]
+    fn errors_point_at_their_source_line() {
+        let outcome = runtime_error("Type mismatch in 20");
+        let diagnostic = outcome
+            .diagnostic("10 A = 1\n20 PRINT 1 / \"é\"  \n")
+            .unwrap();
+        assert_eq!(diagnostic.range.start.line, 1);
+        assert_eq!(diagnostic.range.end.character, 16);
+        assert_eq!(diagnostic.message, "Type mismatch in 20");
+        assert!(runtime_error("Division by zero")
+            .diagnostic("10 A = 1\n")
+            .is_none());
+    }
+
+    #[test]
+    fn values_display_like_print() {
+        assert_eq!(display(&Value::Integer(-3)), "-3");
+        assert_eq!(display(&Value::String("HI".to_string())), "\"HI\"");
+    }
+}
@@ -0,0 +1,273 @@
+use std::sync::atomic::AtomicBool;
+
+use crate::config::RunConfig;
+use crate::runner::{self, Outcome, Screen};
+
+/// The 16 CGA colors as RGB
+const CGA_COLORS: [[u8; 3]; 16] = [
+    [0x00, 0x00, 0x00],
+    [0x00, 0x00, 0xAA],
+    [0x00, 0xAA, 0x00],
+    [0x00, 0xAA, 0xAA],
+    [0xAA, 0x00, 0x00],
+    [0xAA, 0x00, 0xAA],
+    [0xAA, 0x55, 0x00],
+    [0xAA, 0xAA, 0xAA],
+    [0x55, 0x55, 0x55],
+    [0x55, 0x55, 0xFF],
+    [0x55, 0xFF, 0x55],
+    [0x55, 0xFF, 0xFF],
+    [0xFF, 0x55, 0x55],
+    [0xFF, 0x55, 0xFF],
+    [0xFF, 0xFF, 0x55],
+    [0xFF, 0xFF, 0xFF],
+];
+
+/// Colors 1 to 3 of the two SCREEN 1 palettes: green, red, brown and
+/// cyan, magenta, white
+const SCREEN1_PALETTES: [[u8; 3]; 2] = [[2, 4, 6], [3, 5, 7]];
+
+/// Statements between snapshots of the screen for a frame sequence
+const FRAME_EVERY: u64 = 2000;
+
+/// Frames to keep; later ones replace the oldest
+const MAX_FRAMES: usize = 100;
+
+/// The screens a program drew, as PNG images, and how its run ended
+pub struct Render {
+    pub outcome: Outcome,
+    pub width: usize,
+    pub height: usize,
+    /// The final screen last
+    pub frames: Vec<Vec<u8>>,
+}
+
+/// Run a program headlessly and capture its graphics screen at the end,
+/// and every few thousand statements when `frames` is set
+pub fn render(source: &str, limits: &RunConfig, frames: bool) -> Render {
+    let mut screens: Vec<Screen> = Vec::new();
+
+    let (outcome, _) = runner::run_with(
+        source,
+        Vec::new(),
+        limits,
+        Box::new(|_| {}),
+        &AtomicBool::new(false),
+        |debuggee, steps, done| {
+            if done || (frames && steps.is_multiple_of(FRAME_EVERY)) {
+                keep_frame(&mut screens, debuggee.screen());
+            }
+        },
+    );
+
+    let (width, height) = screens.last().map_or((0, 0), |s| (s.width, s.height));
+    Render {
+        outcome,
+        width,
+        height,
+        frames: screens.iter().map(encode_screen).collect(),
+    }
+}
+
+/// Add a snapshot unless nothing changed since the last one
+fn keep_frame(screens: &mut Vec<Screen>, screen: Option<Screen>) {
+    let Some(screen) = screen else {
+        return;
+    };
+    if screens.last() == Some(&screen) {
+        return;
+    }
+    if screens.len() == MAX_FRAMES {
+        screens.remove(0);
+    }
+    screens.push(screen);
+}
+
+/// The CGA color (0-15) of each attribute, as `SCREEN` and `COLOR` set it
+fn palette(screen: &Screen) -> Vec<u8> {
+    match screen.mode {
+        1 => {
+            let colors = SCREEN1_PALETTES[(screen.palette & 1) as usize];
+            vec![screen.background & 15, colors[0], colors[1], colors[2]]
+        }
+        2 => vec![0, 15],
+        // EGA modes start out with the CGA colors
+        _ => (0..16).collect(),
+    }
+}
+
+fn encode_screen(screen: &Screen) -> Vec<u8> {
+    let colors: Vec<[u8; 3]> = palette(screen)
+        .into_iter()
+        .map(|c| CGA_COLORS[c as usize])
+        .collect();
+    let indices: Vec<u8> = screen
+        .pixels
+        .iter()
+        .map(|&p| p.min(colors.len() as u8 - 1))
+        .collect();
+    encode_png(screen.width, screen.height, &indices, &colors)
+}
+
+/// An 8-bit indexed PNG, with the image data stored uncompressed
+fn encode_png(width: usize, height: usize, indices: &[u8], colors: &[[u8; 3]]) -> Vec<u8> {
+    let mut png = b"\x89PNG\r\n\x1a\n".to_vec();
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    // Bit depth 8, indexed color, default compression, filter, no interlace
+    header.extend_from_slice(&[8, 3, 0, 0, 0]);
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"PLTE", &colors.concat());
+
+    // Each row starts with filter type 0
+    let mut raw = Vec::with_capacity((width + 1) * height);
+    for row in indices.chunks(width.max(1)).take(height) {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// A zlib stream of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let blocks: Vec<&[u8]> = data.chunks(0xFFFF).collect();
+    if blocks.is_empty() {
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    for (idx, block) in blocks.iter().enumerate() {
+        out.push((idx == blocks.len() - 1) as u8);
+        let len = block.len() as u16;
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xEDB8_8320
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+/// Standard base64, for sending images in JSON
+pub fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn screen(mode: u8, pixels: Vec<u8>) -> Screen {
+        Screen {
+            mode,
+            width: 2,
+            height: 2,
+            pixels,
+            background: 1,
+            palette: 1,
+        }
+    }
+
+    #[test]
+    fn checksums_match_the_references() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+        assert_eq!(zlib_stored(&[]), [0x78, 1, 1, 0, 0, 0xFF, 0xFF, 0, 0, 0, 1]);
+    }
+
+    #[test]
+    fn base64_pads_short_chunks() {
+        assert_eq!(base64(b"Man"), "TWFu");
+        assert_eq!(base64(b"Ma"), "TWE=");
+        assert_eq!(base64(b"M"), "TQ==");
+        assert_eq!(base64(b""), "");
+    }
+
+    #[test]
+    fn palettes_follow_the_screen_mode() {
+        assert_eq!(palette(&screen(1, vec![])), [1, 3, 5, 7]);
+        assert_eq!(palette(&screen(2, vec![])), [0, 15]);
+        assert_eq!(palette(&screen(7, vec![])).len(), 16);
+    }
+
+    #[test]
+    fn screens_encode_as_indexed_pngs() {
+        let png = encode_screen(&screen(2, vec![0, 1, 1, 9]));
+        assert!(png.starts_with(b"\x89PNG\r\n\x1a\n"));
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..24], [0, 0, 0, 2, 0, 0, 0, 2]);
+        assert!(png.ends_with(&[b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]));
+
+        // Out of range pixels are clamped to the last color
+        let raw = [0, 0, 1, 0, 1, 1];
+        let idat = zlib_stored(&raw);
+        assert!(png.windows(idat.len()).any(|w| w == idat.as_slice()));
+    }
+
+    #[test]
+    fn unchanged_screens_are_not_kept_twice() {
+        let mut screens = Vec::new();
+        keep_frame(&mut screens, Some(screen(1, vec![0; 4])));
+        keep_frame(&mut screens, Some(screen(1, vec![0; 4])));
+        keep_frame(&mut screens, None);
+        assert_eq!(screens.len(), 1);
+
+        for n in 0..MAX_FRAMES as u8 + 5 {
+            keep_frame(&mut screens, Some(screen(1, vec![n; 4])));
+        }
+        assert_eq!(screens.len(), MAX_FRAMES);
+        assert_eq!(screens[0].pixels, [5; 4]);
+    }
+}
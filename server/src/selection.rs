@@ -0,0 +1,337 @@
+use basica::lexer::is_function;
+use tower_lsp::lsp_types::*;
+
+use crate::dialect::Dialect;
+use crate::folding;
+use crate::renumber::{byte_offset, utf16_len};
+use crate::semantic_tokens::{lex_line, LexKind, Lexeme};
+
+/// Reserved words that join operands rather than end an expression
+const OPERATOR_KEYWORDS: &[&str] = &["AND", "OR", "NOT", "XOR", "EQV", "IMP", "MOD"];
+
+/// Statements whose first top-level `=` assigns rather than compares
+const ASSIGNING_KEYWORDS: &[&str] = &["LET", "FOR", "DEF"];
+
+/// Selection ranges growing from each position: token, expression,
+/// statement between colons, numbered line, enclosing blocks and
+/// subroutines, then the whole program
+pub fn selection_ranges(
+    source: &str,
+    positions: &[Position],
+    dialect: Dialect,
+) -> Vec<SelectionRange> {
+    let lines: Vec<&str> = source.lines().collect();
+    let folds: Vec<FoldingRange> = folding::get_folding_ranges(source)
+        .into_iter()
+        // Comment and DATA runs aren't structure
+        .filter(|f| f.kind == Some(FoldingRangeKind::Region))
+        .filter(|f| f.collapsed_text.as_deref() != Some("DATA..."))
+        .collect();
+
+    positions
+        .iter()
+        .map(|&position| {
+            let mut ranges = Vec::new();
+            if let Some(line) = lines.get(position.line as usize) {
+                let spans = line_spans(line, byte_offset(line, position.character), dialect);
+                ranges.extend(spans.into_iter().map(|(start, end)| Range {
+                    start: Position::new(position.line, utf16_len(&line[..start])),
+                    end: Position::new(position.line, utf16_len(&line[..end])),
+                }));
+            }
+
+            let mut enclosing: Vec<&FoldingRange> = folds
+                .iter()
+                .filter(|f| f.start_line <= position.line && position.line <= f.end_line)
+                .collect();
+            enclosing.sort_by_key(|f| f.end_line - f.start_line);
+            ranges.extend(
+                enclosing
+                    .into_iter()
+                    .map(|f| lines_range(&lines, f.start_line, f.end_line)),
+            );
+
+            let last_line = lines.len().saturating_sub(1) as u32;
+            ranges.push(lines_range(&lines, 0, last_line));
+            nest(position, ranges)
+        })
+        .collect()
+}
+
+/// Spans of a source line around a byte offset, innermost first
+fn line_spans(line: &str, offset: usize, dialect: Dialect) -> Vec<(usize, usize)> {
+    let upper = line.to_ascii_uppercase();
+    let lexemes = lex_line(&upper, dialect);
+    let mut spans = Vec::new();
+
+    // The statement holding the offset, between colons
+    let code: Vec<&Lexeme> = lexemes
+        .iter()
+        .filter(|l| l.kind != LexKind::LineNumber)
+        .collect();
+    let statements: Vec<&[&Lexeme]> = code
+        .split(|l| l.kind == LexKind::Operator(b':'))
+        .filter(|s| !s.is_empty())
+        .collect();
+    let statement = statements
+        .iter()
+        .find(|s| s[0].start <= offset && offset <= s[s.len() - 1].end);
+
+    if let Some(statement) = statement {
+        let token = statement
+            .iter()
+            .position(|l| l.start <= offset && offset < l.end)
+            .or_else(|| statement.iter().position(|l| l.end == offset));
+        if let Some(index) = token {
+            spans.push((statement[index].start, statement[index].end));
+            spans.extend(expression_spans(&upper, statement, index));
+        }
+        spans.push((statement[0].start, statement[statement.len() - 1].end));
+    }
+
+    let indent = line.len() - line.trim_start().len();
+    spans.push((indent, line.trim_end().len()));
+    spans
+}
+
+/// Expressions around a token, innermost first: the argument between
+/// commas, the parenthesized list, the call with its name, and so on out
+/// to the whole expression
+fn expression_spans(upper: &str, statement: &[&Lexeme], index: usize) -> Vec<(usize, usize)> {
+    let mut spans = Vec::new();
+    let assignment = assignment(upper, statement);
+    let is_separator = |i: usize, depth: i32| {
+        let lexeme = statement[i];
+        match lexeme.kind {
+            LexKind::Operator(b',' | b';') => true,
+            LexKind::Operator(b'=') => depth == 0 && assignment == Some(i),
+            LexKind::Keyword => {
+                let word = &upper[lexeme.start..lexeme.end];
+                !is_function(word) && !OPERATOR_KEYWORDS.contains(&word)
+            }
+            LexKind::Comment => true,
+            _ => false,
+        }
+    };
+
+    // Nesting of each lexeme in parentheses
+    let mut depths = Vec::with_capacity(statement.len());
+    let mut depth = 0i32;
+    for lexeme in statement {
+        if lexeme.kind == LexKind::Operator(b')') {
+            depth -= 1;
+        }
+        depths.push(depth);
+        if lexeme.kind == LexKind::Operator(b'(') {
+            depth += 1;
+        }
+    }
+
+    if is_separator(index, depths[index]) {
+        return spans;
+    }
+    let is_name = |i: usize| {
+        let lexeme = statement[i];
+        lexeme.kind == LexKind::Identifier
+            || (lexeme.kind == LexKind::Keyword && is_function(&upper[lexeme.start..lexeme.end]))
+    };
+
+    let (mut first, mut last) = (index, index);
+    // A function or array name grows to its call first
+    let is_call = statement
+        .get(index + 1)
+        .is_some_and(|l| l.kind == LexKind::Operator(b'('));
+    if is_name(index) && is_call {
+        last += 1;
+        while last + 1 < statement.len() && depths[last + 1] > depths[index] {
+            last += 1;
+        }
+        if last + 1 < statement.len() {
+            last += 1;
+        }
+        spans.push((statement[first].start, statement[last].end));
+    }
+
+    loop {
+        // Grow to the separators or parentheses around this level
+        let level = depths[first];
+        while first > 0 && depths[first - 1] >= level {
+            if depths[first - 1] == level && is_separator(first - 1, level) {
+                break;
+            }
+            first -= 1;
+        }
+        while last + 1 < statement.len() && depths[last + 1] >= level {
+            if depths[last + 1] == level && is_separator(last + 1, level) {
+                break;
+            }
+            last += 1;
+        }
+        spans.push((statement[first].start, statement[last].end));
+        if level <= 0 || first == 0 {
+            break;
+        }
+
+        // The whole list inside the parentheses, then the parentheses with
+        // a function or array name in front of them
+        while first > 0 && depths[first - 1] >= level {
+            first -= 1;
+        }
+        while last + 1 < statement.len() && depths[last + 1] >= level {
+            last += 1;
+        }
+        spans.push((statement[first].start, statement[last].end));
+        first -= 1;
+        if last + 1 < statement.len() {
+            last += 1;
+        }
+        if first.checked_sub(1).is_some_and(is_name) {
+            first -= 1;
+        }
+        spans.push((statement[first].start, statement[last].end));
+    }
+
+    spans
+}
+
+/// The `=` of `X = 1`, `LET X = 1`, `FOR I = 1 TO 9` or `DEF FNA(X) = 1`
+fn assignment(upper: &str, statement: &[&Lexeme]) -> Option<usize> {
+    let first = statement.first()?;
+    let assigns = match first.kind {
+        LexKind::Identifier => true,
+        LexKind::Keyword => ASSIGNING_KEYWORDS.contains(&&upper[first.start..first.end]),
+        _ => false,
+    };
+    if !assigns {
+        return None;
+    }
+
+    let mut depth = 0;
+    for (i, lexeme) in statement.iter().enumerate() {
+        match lexeme.kind {
+            LexKind::Operator(b'(') => depth += 1,
+            LexKind::Operator(b')') => depth -= 1,
+            LexKind::Operator(b'=') if depth == 0 => return Some(i),
+            _ => {}
+        }
+    }
+    None
+}
+
+/// Whole lines `start..=end`
+fn lines_range(lines: &[&str], start: u32, end: u32) -> Range {
+    let end_len = lines.get(end as usize).map_or(0, |l| utf16_len(l));
+    Range {
+        start: Position::new(start, 0),
+        end: Position::new(end, end_len),
+    }
+}
+
+/// Chain ranges into parents, keeping only those that grow the selection
+fn nest(position: Position, ranges: Vec<Range>) -> SelectionRange {
+    let contains = |outer: &Range, inner: &Range| {
+        (outer.start.line, outer.start.character) <= (inner.start.line, inner.start.character)
+            && (inner.end.line, inner.end.character) <= (outer.end.line, outer.end.character)
+    };
+
+    let mut chain: Vec<Range> = Vec::new();
+    for range in ranges {
+        let grows = match chain.last() {
+            Some(last) => *last != range && contains(&range, last),
+            None => contains(
+                &range,
+                &Range {
+                    start: position,
+                    end: position,
+                },
+            ),
+        };
+        if grows {
+            chain.push(range);
+        }
+    }
+
+    let mut selection: Option<SelectionRange> = None;
+    for range in chain.into_iter().rev() {
+        selection = Some(SelectionRange {
+            range,
+            parent: selection.map(Box::new),
+        });
+    }
+    selection.unwrap_or(SelectionRange {
+        range: Range {
+            start: position,
+            end: position,
+        },
+        parent: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The text of each range growing from a position on one line
+    fn chain(source: &str, line: u32, character: u32) -> Vec<String> {
+        let lines: Vec<&str> = source.lines().collect();
+        let selection =
+            selection_ranges(source, &[Position::new(line, character)], Dialect::Basica);
+        let mut texts = Vec::new();
+        let mut range = Some(&selection[0]);
+        while let Some(r) = range {
+            let text = if r.range.start.line == r.range.end.line {
+                let line = lines[r.range.start.line as usize];
+                line[byte_offset(line, r.range.start.character)
+                    ..byte_offset(line, r.range.end.character)]
+                    .to_string()
+            } else {
+                format!("lines {}-{}", r.range.start.line, r.range.end.line)
+            };
+            texts.push(text);
+            range = r.parent.as_deref();
+        }
+        texts
+    }
+
+    #[test]
+    fn arguments_grow_to_their_call() {
+        let source = "10 PRINT MID$(A$, 2, 3) + B$: GOTO 10\n20 END";
+        assert_eq!(
+            chain(source, 0, 18),
+            [
+                "2",
+                "A$, 2, 3",
+                "MID$(A$, 2, 3)",
+                "MID$(A$, 2, 3) + B$",
+                "PRINT MID$(A$, 2, 3) + B$",
+                "10 PRINT MID$(A$, 2, 3) + B$: GOTO 10",
+                "lines 0-1",
+            ]
+        );
+    }
+
+    #[test]
+    fn assignments_split_at_the_equals() {
+        assert_eq!(
+            chain("10 LET X = A * 2\n", 0, 11),
+            ["A", "A * 2", "LET X = A * 2", "10 LET X = A * 2"]
+        );
+        assert_eq!(
+            chain("10 X = (A + 1) * 2\n", 0, 8),
+            [
+                "A",
+                "A + 1",
+                "(A + 1)",
+                "(A + 1) * 2",
+                "X = (A + 1) * 2",
+                "10 X = (A + 1) * 2"
+            ]
+        );
+    }
+
+    #[test]
+    fn columns_count_utf16_units() {
+        let source = "10 PRINT \"😀\": X = 1\n";
+        assert_eq!(chain(source, 0, 15), ["X", "X = 1", source.trim_end()]);
+    }
+}
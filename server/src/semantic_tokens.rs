@@ -1,5 +1,10 @@
+use basica::lexer::{is_function, Lexer, TokenKind};
+use std::collections::HashSet;
 use tower_lsp::lsp_types::*;
 
+use crate::dialect::Dialect;
+use crate::renumber::{line_references, utf16_len, JUMP_KEYWORDS};
+
 /// Token types for semantic highlighting
 pub const TOKEN_TYPES: &[SemanticTokenType] = &[
     SemanticTokenType::KEYWORD,
@@ -9,12 +14,16 @@ pub const TOKEN_TYPES: &[SemanticTokenType] = &[
     SemanticTokenType::NUMBER,
     SemanticTokenType::COMMENT,
     SemanticTokenType::OPERATOR,
+    SemanticTokenType::new("label"),
 ];
 
 /// Token modifiers
 pub const TOKEN_MODIFIERS: &[SemanticTokenModifier] = &[
     SemanticTokenModifier::DECLARATION,
     SemanticTokenModifier::DEFINITION,
+    SemanticTokenModifier::MODIFICATION,
+    SemanticTokenModifier::READONLY,
+    SemanticTokenModifier::DEFAULT_LIBRARY,
 ];
 
 const TYPE_KEYWORD: u32 = 0;
@@ -24,383 +33,550 @@ const TYPE_STRING: u32 = 3;
 const TYPE_NUMBER: u32 = 4;
 const TYPE_COMMENT: u32 = 5;
 const TYPE_OPERATOR: u32 = 6;
+const TYPE_LABEL: u32 = 7;
 
-/// Get semantic tokens for a document
-pub fn get_semantic_tokens(source: &str) -> SemanticTokensResult {
-    let mut tokens = Vec::new();
-    let mut prev_line = 0u32;
-    let mut prev_char = 0u32;
+const MOD_DECLARATION: u32 = 1 << 0;
+const MOD_DEFINITION: u32 = 1 << 1;
+const MOD_MODIFICATION: u32 = 1 << 2;
+const MOD_READONLY: u32 = 1 << 3;
+const MOD_DEFAULT_LIBRARY: u32 = 1 << 4;
 
-    for (line_idx, line) in source.lines().enumerate() {
-        let line_num = line_idx as u32;
-        let upper = line.to_uppercase();
-
-        // Skip leading whitespace
-        let trimmed_start = line.len() - line.trim_start().len();
-        let mut char_pos = trimmed_start;
-
-        // Check for line number at start
-        let trimmed = line.trim_start();
-        if let Some(first_word) = trimmed.split_whitespace().next() {
-            if first_word.parse::<u32>().is_ok() {
-                // Line number token
-                add_token(
-                    &mut tokens,
-                    &mut prev_line,
-                    &mut prev_char,
-                    line_num,
-                    char_pos as u32,
-                    first_word.len() as u32,
-                    TYPE_NUMBER,
-                    0,
-                );
-                char_pos += first_word.len();
-            }
-        }
+/// A classified token at an absolute position
+struct Token {
+    line: u32,
+    start: u32,
+    length: u32,
+    token_type: u32,
+    modifiers: u32,
+}
 
-        // Check for REM comment
-        let after_linenum = &upper[char_pos..];
-        if after_linenum.trim_start().starts_with("REM")
-            || after_linenum.trim_start().starts_with("'")
-        {
-            let comment_start = char_pos + (after_linenum.len() - after_linenum.trim_start().len());
-            add_token(
-                &mut tokens,
-                &mut prev_line,
-                &mut prev_char,
-                line_num,
-                comment_start as u32,
-                (line.len() - comment_start) as u32,
-                TYPE_COMMENT,
-                0,
-            );
-            continue;
-        }
+/// Lexical category of a span of source text
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LexKind {
+    LineNumber,
+    Keyword,
+    Identifier,
+    Number,
+    String,
+    Comment,
+    Operator(u8),
+}
 
-        // Tokenize the rest of the line
-        tokenize_line(
-            line,
-            &upper,
-            char_pos,
-            line_num,
-            &mut tokens,
-            &mut prev_line,
-            &mut prev_char,
-        );
+/// A span of a source line, in byte offsets
+pub struct Lexeme {
+    pub start: usize,
+    pub end: usize,
+    pub kind: LexKind,
+}
+
+/// What the identifiers following a keyword mean
+#[derive(Clone, Copy, PartialEq)]
+enum Context {
+    None,
+    /// DIM: names at paren depth 0 are declared
+    Dim,
+    /// DEF: the next FN name is declared
+    DefFn,
+    /// Parameter list of a DEF FN
+    DefParams,
+    /// FOR: the next name is the loop variable
+    For,
+    /// NEXT: names close loops
+    Next,
+    /// INPUT, READ, SWAP: names at paren depth 0 are assigned
+    Assign,
+    /// GOTO, GOSUB, THEN, ...: numbers are line references
+    Jump,
+}
+
+/// Get semantic tokens for a whole document
+pub fn get_semantic_tokens(source: &str, dialect: Dialect) -> SemanticTokens {
+    SemanticTokens {
+        result_id: None,
+        data: encode(&collect_tokens(source, dialect)),
     }
+}
+
+/// Get semantic tokens for the part of a document inside `range`
+pub fn get_semantic_tokens_range(source: &str, range: Range, dialect: Dialect) -> SemanticTokens {
+    let tokens: Vec<Token> = collect_tokens(source, dialect)
+        .into_iter()
+        .filter(|t| {
+            let after_start = t.line > range.start.line
+                || (t.line == range.start.line && t.start + t.length > range.start.character);
+            let before_end = t.line < range.end.line
+                || (t.line == range.end.line && t.start < range.end.character);
+            after_start && before_end
+        })
+        .collect();
 
-    SemanticTokensResult::Tokens(SemanticTokens {
+    SemanticTokens {
         result_id: None,
-        data: tokens,
-    })
+        data: encode(&tokens),
+    }
 }
 
-fn tokenize_line(
-    line: &str,
-    upper: &str,
-    start_pos: usize,
-    line_num: u32,
-    tokens: &mut Vec<SemanticToken>,
-    prev_line: &mut u32,
-    prev_char: &mut u32,
-) {
-    let bytes = line.as_bytes();
-    let mut pos = start_pos;
+/// Compute the edit that turns a previous token list into a new one
+pub fn diff_tokens(old: &[SemanticToken], new: &[SemanticToken]) -> Vec<SemanticTokensEdit> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
 
-    while pos < line.len() {
-        let b = bytes[pos];
+    let deleted = old.len() - prefix - suffix;
+    let inserted = &new[prefix..new.len() - suffix];
+    if deleted == 0 && inserted.is_empty() {
+        return vec![];
+    }
 
-        // Skip whitespace
-        if b.is_ascii_whitespace() {
-            pos += 1;
-            continue;
-        }
+    // Offsets and counts are in integers, five per token
+    vec![SemanticTokensEdit {
+        start: (prefix * 5) as u32,
+        delete_count: (deleted * 5) as u32,
+        data: Some(inserted.to_vec()),
+    }]
+}
+
+fn collect_tokens(source: &str, dialect: Dialect) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let lines: Vec<(String, Vec<Lexeme>)> = source
+        .lines()
+        .map(|line| {
+            let upper = line.to_ascii_uppercase();
+            let lexemes = lex_line(&upper, dialect);
+            (upper, lexemes)
+        })
+        .collect();
+    let jump_targets = find_jump_targets(source);
+    let mut loop_vars: Vec<String> = Vec::new();
 
-        // String literal
-        if b == b'"' {
-            let start = pos;
-            pos += 1;
-            while pos < line.len() && bytes[pos] != b'"' {
-                pos += 1;
+    for (line_idx, (upper, lexemes)) in lines.iter().enumerate() {
+        classify_line(
+            lexemes,
+            upper,
+            line_idx as u32,
+            &jump_targets,
+            &mut loop_vars,
+            &mut tokens,
+        );
+    }
+
+    tokens
+}
+
+/// Split an (uppercased) line into lexemes from the basica lexer's tokens.
+///
+/// The interpreter's own lexer decides what is reserved, so crunched code
+/// like `FORI=1TO10` splits into `FOR I = 1 TO 10` exactly as it is run.
+/// A reserved word the dialect lacks is an ordinary name, together with
+/// whatever the lexer split off after it.
+pub fn lex_line(upper: &str, dialect: Dialect) -> Vec<Lexeme> {
+    let mut lexemes: Vec<Lexeme> = Vec::new();
+    // Whether the last lexeme is a name made from an unsupported word
+    let mut unreserved = false;
+
+    for token in Lexer::new(upper).tokenize() {
+        let (start, end) = (token.span.start, token.span.end);
+        let text = &upper[start..end];
+        let kind = match token.kind {
+            TokenKind::LineNumber => LexKind::LineNumber,
+            TokenKind::Keyword if dialect.supports(text) => LexKind::Keyword,
+            TokenKind::Keyword | TokenKind::Identifier => LexKind::Identifier,
+            TokenKind::Number => LexKind::Number,
+            TokenKind::String => LexKind::String,
+            TokenKind::Comment => LexKind::Comment,
+            TokenKind::Operator => {
+                // One lexeme per character, so `<=` is `<` then `=`
+                lexemes.extend(text.bytes().enumerate().map(|(i, b)| Lexeme {
+                    start: start + i,
+                    end: start + i + 1,
+                    kind: LexKind::Operator(b),
+                }));
+                unreserved = false;
+                continue;
             }
-            if pos < line.len() {
-                pos += 1; // Include closing quote
+            TokenKind::Newline => continue,
+        };
+
+        let joins = matches!(
+            token.kind,
+            TokenKind::Keyword | TokenKind::Identifier | TokenKind::Number
+        );
+        match lexemes.last_mut() {
+            Some(last) if unreserved && joins && last.end == start => last.end = end,
+            _ => {
+                unreserved = token.kind == TokenKind::Keyword && kind == LexKind::Identifier;
+                lexemes.push(Lexeme { start, end, kind });
             }
-            add_token(
-                tokens,
-                prev_line,
-                prev_char,
-                line_num,
-                start as u32,
-                (pos - start) as u32,
-                TYPE_STRING,
-                0,
-            );
-            continue;
         }
+    }
+
+    lexemes
+}
 
-        // Number (including hex &H)
-        if b.is_ascii_digit() || (b == b'&' && pos + 1 < line.len() && bytes[pos + 1] == b'H') {
-            let start = pos;
-            if b == b'&' {
-                pos += 2; // Skip &H
-                while pos < line.len() && bytes[pos].is_ascii_hexdigit() {
-                    pos += 1;
+/// Assign token types and modifiers to the lexemes of one line
+fn classify_line(
+    lexemes: &[Lexeme],
+    upper: &str,
+    line_num: u32,
+    jump_targets: &HashSet<u32>,
+    loop_vars: &mut Vec<String>,
+    tokens: &mut Vec<Token>,
+) {
+    let mut context = Context::None;
+    let mut stmt_start = true;
+    let mut depth = 0i32;
+    let mut after_fn = false;
+
+    for (idx, lexeme) in lexemes.iter().enumerate() {
+        let text = &upper[lexeme.start..lexeme.end];
+        let mut modifiers = 0;
+        let mut was_fn = false;
+
+        let token_type = match lexeme.kind {
+            LexKind::LineNumber => {
+                if text.parse().is_ok_and(|n: u32| jump_targets.contains(&n)) {
+                    // The line a jump lands on defines that label
+                    modifiers |= MOD_DEFINITION;
+                    TYPE_LABEL
+                } else {
+                    TYPE_NUMBER
                 }
-            } else {
-                while pos < line.len()
-                    && (bytes[pos].is_ascii_digit()
-                        || bytes[pos] == b'.'
-                        || bytes[pos] == b'E'
-                        || bytes[pos] == b'e'
-                        || bytes[pos] == b'-'
-                        || bytes[pos] == b'+')
-                {
-                    // Handle scientific notation carefully
-                    if (bytes[pos] == b'-' || bytes[pos] == b'+')
-                        && pos > start
-                        && bytes[pos - 1] != b'E'
-                        && bytes[pos - 1] != b'e'
-                    {
-                        break;
+            }
+            LexKind::Comment => TYPE_COMMENT,
+            LexKind::String => TYPE_STRING,
+            LexKind::Number => {
+                if context == Context::Jump {
+                    TYPE_LABEL
+                } else {
+                    TYPE_NUMBER
+                }
+            }
+            LexKind::Operator(op) => {
+                match op {
+                    b'(' => depth += 1,
+                    b')' => {
+                        depth -= 1;
+                        if context == Context::DefParams && depth == 0 {
+                            context = Context::None;
+                        }
                     }
-                    pos += 1;
+                    b':' => {
+                        context = Context::None;
+                        stmt_start = true;
+                        depth = 0;
+                    }
+                    _ => {}
+                }
+                // Only a comma continues an ON ... GOTO list
+                if context == Context::Jump && op != b',' {
+                    context = Context::None;
                 }
+                TYPE_OPERATOR
             }
-            add_token(
-                tokens,
-                prev_line,
-                prev_char,
-                line_num,
-                start as u32,
-                (pos - start) as u32,
-                TYPE_NUMBER,
-                0,
-            );
-            continue;
-        }
-
-        // Identifier (keyword, function, or variable)
-        if b.is_ascii_alphabetic() || b == b'_' {
-            let start = pos;
-            while pos < line.len()
-                && (bytes[pos].is_ascii_alphanumeric() || bytes[pos] == b'_' || bytes[pos] == b'$')
-            {
-                pos += 1;
+            LexKind::Keyword if is_function(text) => {
+                modifiers |= MOD_DEFAULT_LIBRARY;
+                stmt_start = false;
+                TYPE_FUNCTION
             }
+            LexKind::Keyword => {
+                context = match text {
+                    "DIM" => Context::Dim,
+                    "DEF" => Context::DefFn,
+                    "FN" if context == Context::DefFn => Context::DefFn,
+                    "FOR" => Context::For,
+                    "NEXT" => Context::Next,
+                    "INPUT" | "READ" | "SWAP" => Context::Assign,
+                    _ if JUMP_KEYWORDS.contains(&text) => Context::Jump,
+                    _ => Context::None,
+                };
+                stmt_start = matches!(text, "LET" | "THEN" | "ELSE");
+                was_fn = text == "FN";
 
-            let word = &upper[start..pos];
-            let token_type = if is_keyword(word) {
+                // NEXT with no variable closes the innermost loop
+                let next_is_var = lexemes
+                    .get(idx + 1)
+                    .is_some_and(|l| l.kind == LexKind::Identifier);
+                if text == "NEXT" && !next_is_var {
+                    loop_vars.pop();
+                }
                 TYPE_KEYWORD
-            } else if is_function(word) {
-                TYPE_FUNCTION
-            } else {
-                TYPE_VARIABLE
-            };
-
-            add_token(
-                tokens,
-                prev_line,
-                prev_char,
-                line_num,
-                start as u32,
-                (pos - start) as u32,
-                token_type,
-                0,
-            );
-            continue;
-        }
-
-        // Operators
-        if is_operator(b) {
-            add_token(
-                tokens,
-                prev_line,
-                prev_char,
-                line_num,
-                pos as u32,
-                1,
-                TYPE_OPERATOR,
-                0,
-            );
-        }
+            }
+            LexKind::Identifier => {
+                let token_type = if after_fn {
+                    // User-defined function
+                    if context == Context::DefFn {
+                        modifiers |= MOD_DECLARATION | MOD_DEFINITION;
+                        context = Context::DefParams;
+                    }
+                    TYPE_FUNCTION
+                } else {
+                    match context {
+                        Context::Dim | Context::DefParams if depth == context_depth(context) => {
+                            modifiers |= MOD_DECLARATION;
+                        }
+                        Context::For => {
+                            modifiers |= MOD_MODIFICATION;
+                            loop_vars.push(text.to_string());
+                            context = Context::None;
+                        }
+                        Context::Next => {
+                            modifiers |= MOD_READONLY;
+                            if let Some(i) = loop_vars.iter().rposition(|v| v == text) {
+                                loop_vars.truncate(i);
+                            }
+                        }
+                        Context::Assign if depth == 0 => modifiers |= MOD_MODIFICATION,
+                        _ if stmt_start && is_assignment_target(lexemes, idx) => {
+                            modifiers |= MOD_MODIFICATION;
+                        }
+                        _ if loop_vars.iter().any(|v| v == text) => modifiers |= MOD_READONLY,
+                        _ => {}
+                    }
+                    TYPE_VARIABLE
+                };
+                stmt_start = false;
+                if context == Context::Jump {
+                    context = Context::None;
+                }
+                token_type
+            }
+        };
 
-        pos += 1;
+        after_fn = was_fn;
+        tokens.push(Token {
+            line: line_num,
+            start: utf16_len(&upper[..lexeme.start]),
+            length: utf16_len(&upper[lexeme.start..lexeme.end]),
+            token_type,
+            modifiers,
+        });
     }
 }
 
-fn add_token(
-    tokens: &mut Vec<SemanticToken>,
-    prev_line: &mut u32,
-    prev_char: &mut u32,
-    line: u32,
-    char_pos: u32,
-    length: u32,
-    token_type: u32,
-    token_modifiers: u32,
-) {
-    let delta_line = line - *prev_line;
-    let delta_start = if delta_line == 0 {
-        char_pos - *prev_char
-    } else {
-        char_pos
-    };
-
-    tokens.push(SemanticToken {
-        delta_line,
-        delta_start,
-        length,
-        token_type,
-        token_modifiers_bitset: token_modifiers,
-    });
-
-    *prev_line = line;
-    *prev_char = char_pos;
+/// Paren depth at which names are declared in a DIM or DEF FN context
+fn context_depth(context: Context) -> i32 {
+    match context {
+        Context::DefParams => 1,
+        _ => 0,
+    }
 }
 
-fn is_operator(b: u8) -> bool {
+/// Check whether the identifier at `idx` is followed by `=` (or `(...) =`)
+fn is_assignment_target(lexemes: &[Lexeme], idx: usize) -> bool {
+    let mut i = idx + 1;
+    if let Some(Lexeme {
+        kind: LexKind::Operator(b'('),
+        ..
+    }) = lexemes.get(i)
+    {
+        let mut depth = 0;
+        while let Some(lexeme) = lexemes.get(i) {
+            match lexeme.kind {
+                LexKind::Operator(b'(') => depth += 1,
+                LexKind::Operator(b')') => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+        i += 1;
+    }
     matches!(
-        b,
-        b'+' | b'-' | b'*' | b'/' | b'^' | b'=' | b'<' | b'>' | b'(' | b')' | b',' | b';' | b':'
+        lexemes.get(i),
+        Some(Lexeme {
+            kind: LexKind::Operator(b'='),
+            ..
+        })
     )
 }
 
-fn is_keyword(word: &str) -> bool {
-    matches!(
-        word,
-        "REM"
-            | "LET"
-            | "DIM"
-            | "PRINT"
-            | "LPRINT"
-            | "INPUT"
-            | "LINE"
-            | "IF"
-            | "THEN"
-            | "ELSE"
-            | "ELSEIF"
-            | "END"
-            | "ENDIF"
-            | "FOR"
-            | "TO"
-            | "STEP"
-            | "NEXT"
-            | "WHILE"
-            | "WEND"
-            | "DO"
-            | "LOOP"
-            | "UNTIL"
-            | "EXIT"
-            | "SELECT"
-            | "CASE"
-            | "GOTO"
-            | "GOSUB"
-            | "RETURN"
-            | "ON"
-            | "READ"
-            | "DATA"
-            | "RESTORE"
-            | "DEF"
-            | "FN"
-            | "OPEN"
-            | "CLOSE"
-            | "GET"
-            | "PUT"
-            | "WRITE"
-            | "FIELD"
-            | "LSET"
-            | "RSET"
-            | "AS"
-            | "OUTPUT"
-            | "APPEND"
-            | "RANDOM"
-            | "BINARY"
-            | "SCREEN"
-            | "COLOR"
-            | "CLS"
-            | "LOCATE"
-            | "WIDTH"
-            | "CIRCLE"
-            | "PAINT"
-            | "PSET"
-            | "PRESET"
-            | "DRAW"
-            | "PLAY"
-            | "SOUND"
-            | "BEEP"
-            | "SWAP"
-            | "RANDOMIZE"
-            | "CLEAR"
-            | "STOP"
-            | "POKE"
-            | "PEEK"
-            | "OUT"
-            | "INP"
-            | "WAIT"
-            | "AND"
-            | "OR"
-            | "XOR"
-            | "NOT"
-            | "MOD"
-            | "IMP"
-            | "EQV"
-            | "KILL"
-            | "NAME"
-            | "MKDIR"
-            | "RMDIR"
-            | "CHDIR"
-            | "FILES"
-            | "CALL"
-            | "CHAIN"
-            | "COMMON"
-            | "SHARED"
-            | "STATIC"
-    )
+/// Delta-encode tokens for the LSP wire format
+fn encode(tokens: &[Token]) -> Vec<SemanticToken> {
+    let mut data = Vec::with_capacity(tokens.len());
+    let mut prev_line = 0u32;
+    let mut prev_char = 0u32;
+
+    for token in tokens {
+        let delta_line = token.line - prev_line;
+        let delta_start = if delta_line == 0 {
+            token.start - prev_char
+        } else {
+            token.start
+        };
+
+        data.push(SemanticToken {
+            delta_line,
+            delta_start,
+            length: token.length,
+            token_type: token.token_type,
+            token_modifiers_bitset: token.modifiers,
+        });
+
+        prev_line = token.line;
+        prev_char = token.start;
+    }
+
+    data
 }
 
-fn is_function(word: &str) -> bool {
-    matches!(
-        word,
-        "CHR$"
-            | "ASC"
-            | "LEN"
-            | "LEFT$"
-            | "RIGHT$"
-            | "MID$"
-            | "STR$"
-            | "VAL"
-            | "STRING$"
-            | "SPACE$"
-            | "INSTR"
-            | "UCASE$"
-            | "LCASE$"
-            | "LTRIM$"
-            | "RTRIM$"
-            | "HEX$"
-            | "OCT$"
-            | "ABS"
-            | "SGN"
-            | "INT"
-            | "FIX"
-            | "CINT"
-            | "SQR"
-            | "SIN"
-            | "COS"
-            | "TAN"
-            | "ATN"
-            | "LOG"
-            | "EXP"
-            | "RND"
-            | "PEEK"
-            | "TIMER"
-            | "DATE$"
-            | "TIME$"
-            | "INKEY$"
-            | "EOF"
-            | "CSRLIN"
-            | "POS"
-            | "POINT"
-            | "TAB"
-            | "SPC"
-    )
+/// Find all line numbers referenced by GOTO, GOSUB, THEN, ELSE, RESTORE,
+/// RESUME, RUN or an ERL comparison
+fn find_jump_targets(source: &str) -> HashSet<u32> {
+    source
+        .lines()
+        .flat_map(|line| {
+            line_references(line)
+                .into_iter()
+                .filter_map(move |(start, end)| line[start..end].parse().ok())
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// (line, start, length, type, modifiers) of each token
+    fn absolute(source: &str) -> Vec<(u32, u32, u32, u32, u32)> {
+        collect_tokens(source, Dialect::Basica)
+            .into_iter()
+            .map(|t| (t.line, t.start, t.length, t.token_type, t.modifiers))
+            .collect()
+    }
+
+    #[test]
+    fn jump_targets_define_labels() {
+        let tokens = absolute("10 GOSUB 30\n20 END\n30 RETURN\n");
+        assert_eq!(tokens[0], (0, 0, 2, TYPE_NUMBER, 0));
+        assert_eq!(tokens[2], (0, 9, 2, TYPE_LABEL, 0));
+        assert_eq!(tokens[5], (2, 0, 2, TYPE_LABEL, MOD_DEFINITION));
+    }
+
+    #[test]
+    fn def_fn_names_are_defined() {
+        let tokens = absolute("10 DEF FNA(X) = X * 2: PRINT FNA(1)\n");
+        let names: Vec<_> = tokens
+            .iter()
+            .filter(|t| t.3 == TYPE_FUNCTION)
+            .map(|t| t.4)
+            .collect();
+        assert_eq!(names, [MOD_DECLARATION | MOD_DEFINITION, 0]);
+    }
+
+    #[test]
+    fn variables_carry_their_role() {
+        let tokens = absolute("10 DIM A(5): FOR I = 1 TO 3: B = A(I): NEXT I\n");
+        let vars: Vec<_> = tokens
+            .iter()
+            .filter(|t| t.3 == TYPE_VARIABLE)
+            .map(|t| t.4)
+            .collect();
+        assert_eq!(
+            vars,
+            [
+                MOD_DECLARATION,
+                MOD_MODIFICATION,
+                MOD_MODIFICATION,
+                0,
+                MOD_READONLY,
+                MOD_READONLY
+            ]
+        );
+    }
+
+    #[test]
+    fn columns_count_utf16_units() {
+        let tokens = absolute("10 PRINT \"😀é\": X = 1\n");
+        // The string is 5 units long and X follows ": "
+        assert_eq!(tokens[2], (0, 9, 5, TYPE_STRING, 0));
+        assert_eq!(tokens[4].1, 16);
+    }
+
+    #[test]
+    fn ranges_keep_overlapping_tokens() {
+        let source = "10 PRINT 1\n20 PRINT 2\n30 PRINT 3\n";
+        let range = Range {
+            start: Position {
+                line: 1,
+                character: 4,
+            },
+            end: Position {
+                line: 2,
+                character: 1,
+            },
+        };
+        let tokens = get_semantic_tokens_range(source, range, Dialect::Basica).data;
+        // PRINT and 2 on line 20, then the 30
+        assert_eq!(tokens.len(), 3);
+        assert_eq!((tokens[0].delta_line, tokens[0].delta_start), (1, 3));
+        assert_eq!((tokens[2].delta_line, tokens[2].delta_start), (1, 0));
+    }
+
+    #[test]
+    fn deltas_replace_only_what_changed() {
+        let old = get_semantic_tokens("10 PRINT 1\n20 END\n", Dialect::Basica).data;
+        let new = get_semantic_tokens("10 PRINT X\n20 END\n", Dialect::Basica).data;
+        let edits = diff_tokens(&old, &new);
+        assert_eq!(edits.len(), 1);
+        assert_eq!((edits[0].start, edits[0].delete_count), (10, 5));
+        assert_eq!(edits[0].data.as_deref(), Some(&new[2..3]));
+        assert!(diff_tokens(&new, &new).is_empty());
+    }
+
+    fn lexed(line: &str, dialect: Dialect) -> Vec<(&str, LexKind)> {
+        lex_line(line, dialect)
+            .into_iter()
+            .map(|l| (&line[l.start..l.end], l.kind))
+            .collect()
+    }
+
+    #[test]
+    fn crunched_code_splits_like_the_interpreter() {
+        let words: Vec<&str> = lexed("10 FORI=1TO10", Dialect::Basica)
+            .into_iter()
+            .map(|(text, _)| text)
+            .collect();
+        assert_eq!(words, ["10", "FOR", "I", "=", "1", "TO", "10"]);
+    }
+
+    #[test]
+    fn operators_are_one_character_each() {
+        let ops: Vec<LexKind> = lexed("10 IF A<=B THEN 20", Dialect::Basica)
+            .into_iter()
+            .filter(|(_, kind)| matches!(kind, LexKind::Operator(_)))
+            .map(|(_, kind)| kind)
+            .collect();
+        assert_eq!(ops, [LexKind::Operator(b'<'), LexKind::Operator(b'=')]);
+    }
+
+    #[test]
+    fn unsupported_words_are_names() {
+        assert_eq!(
+            lexed("10 DOG = 1", Dialect::GwBasic)[1],
+            ("DOG", LexKind::Identifier)
+        );
+        assert_eq!(
+            lexed("10 DO: LOOP", Dialect::GwBasic)[1],
+            ("DO", LexKind::Identifier)
+        );
+        assert_eq!(
+            lexed("10 DO: LOOP", Dialect::Basica)[1],
+            ("DO", LexKind::Keyword)
+        );
+    }
+
+    #[test]
+    fn remarks_and_strings_stay_whole() {
+        let lexemes = lexed("10 PRINT \"GOTO 5\": REM GOTO 5", Dialect::Basica);
+        assert_eq!(lexemes[2], ("\"GOTO 5\"", LexKind::String));
+        assert_eq!(lexemes.last().unwrap().1, LexKind::Comment);
+    }
 }
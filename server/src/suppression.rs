@@ -0,0 +1,266 @@
+use tower_lsp::lsp_types::*;
+
+use crate::codes::{self, Check};
+use crate::config::{DiagnosticsConfig, Severity};
+use crate::dialect::Dialect;
+use crate::semantic_tokens::{lex_line, LexKind};
+
+/// Directive that suppresses diagnostics on its own line
+const IGNORE: &str = "basica-ignore";
+
+/// Directive that suppresses diagnostics anywhere in the file
+const IGNORE_FILE: &str = "basica-ignore-file";
+
+/// One code named by a `REM basica-ignore` or `REM basica-ignore-file`
+/// directive
+struct Suppression {
+    line: u32,
+    start: usize,
+    end: usize,
+    /// `None` when the directive names no codes and suppresses everything
+    code: Option<String>,
+    /// The check `code` names, if it's a known id or name
+    check: Option<&'static Check>,
+    whole_file: bool,
+    used: bool,
+}
+
+impl Suppression {
+    fn covers(&self, diagnostic: &Diagnostic) -> bool {
+        (self.whole_file || self.line == diagnostic.range.start.line)
+            && (self.code.is_none()
+                || self
+                    .check
+                    .zip(codes::check_of(diagnostic))
+                    .is_some_and(|(a, b)| a.id == b.id))
+    }
+}
+
+/// Drop diagnostics covered by a suppression directive, and warn about
+/// directives that suppressed nothing when `report_unused` is set
+pub fn apply(
+    source: &str,
+    diagnostics: Vec<Diagnostic>,
+    report_unused: bool,
+    config: &DiagnosticsConfig,
+) -> Vec<Diagnostic> {
+    let mut suppressions = find_suppressions(source);
+    if suppressions.is_empty() {
+        return diagnostics;
+    }
+
+    let mut kept: Vec<Diagnostic> = diagnostics
+        .into_iter()
+        .filter(|diagnostic| {
+            let mut suppressed = false;
+            for suppression in suppressions.iter_mut().filter(|s| s.covers(diagnostic)) {
+                suppression.used = true;
+                suppressed = true;
+            }
+            !suppressed
+        })
+        .collect();
+
+    if !report_unused {
+        return kept;
+    }
+    for suppression in suppressions.iter().filter(|s| !s.used) {
+        // A check that's turned off can't be expected to fire
+        let turned_off = suppression
+            .check
+            .and_then(|check| config.severity_of(check))
+            == Some(Severity::Off);
+        if turned_off {
+            continue;
+        }
+
+        let message = match (&suppression.code, suppression.check) {
+            (Some(code), Some(_)) => format!("Suppression of '{}' is never used", code),
+            (Some(code), None) => format!("Unknown check '{}' in suppression", code),
+            (None, _) => "Suppression is never used".to_string(),
+        };
+        let mut diagnostic = Diagnostic {
+            range: Range {
+                start: Position {
+                    line: suppression.line,
+                    character: suppression.start as u32,
+                },
+                end: Position {
+                    line: suppression.line,
+                    character: suppression.end as u32,
+                },
+            },
+            severity: Some(DiagnosticSeverity::WARNING),
+            source: Some("basica".to_string()),
+            message,
+            tags: Some(vec![DiagnosticTag::UNNECESSARY]),
+            ..Default::default()
+        };
+        if let Some(check) = codes::find("unused-suppression") {
+            check.tag(&mut diagnostic);
+        }
+        kept.push(diagnostic);
+    }
+
+    kept
+}
+
+/// Find the directives in `REM` and `'` comments
+fn find_suppressions(source: &str) -> Vec<Suppression> {
+    let mut suppressions = Vec::new();
+
+    for (line_idx, line) in source.lines().enumerate() {
+        let upper = line.to_ascii_uppercase();
+        let Some(comment) = lex_line(&upper, Dialect::Basica)
+            .into_iter()
+            .find(|l| l.kind == LexKind::Comment)
+        else {
+            continue;
+        };
+
+        // Skip the comment marker and leading space
+        let marker = if upper[comment.start..].starts_with("REM") {
+            3
+        } else {
+            1
+        };
+        let body = &line[comment.start + marker..comment.end];
+        let offset = comment.start + marker + (body.len() - body.trim_start().len());
+        let body = body.trim_start().to_ascii_lowercase();
+
+        let (directive, whole_file) = if body.starts_with(IGNORE_FILE) {
+            (IGNORE_FILE, true)
+        } else if body.starts_with(IGNORE) {
+            (IGNORE, false)
+        } else {
+            continue;
+        };
+        let rest = &body[directive.len()..];
+        if rest.starts_with(|c: char| !c.is_whitespace() && c != ',') {
+            continue;
+        }
+
+        let mut codes = Vec::new();
+        let mut pos = directive.len();
+        for code in rest.split(|c: char| c.is_whitespace() || c == ',') {
+            if !code.is_empty() {
+                codes.push((pos, code));
+            }
+            pos += code.len() + 1;
+        }
+
+        let mut push = |start: usize, end: usize, code: Option<String>| {
+            suppressions.push(Suppression {
+                line: line_idx as u32,
+                start: offset + start,
+                end: offset + end,
+                check: code.as_deref().and_then(codes::find),
+                code,
+                whole_file,
+                used: false,
+            })
+        };
+        if codes.is_empty() {
+            push(0, directive.len(), None);
+        }
+        for (start, code) in codes {
+            push(start, start + code.len(), Some(code.to_string()));
+        }
+    }
+
+    suppressions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tagged(line: u32, code: &str) -> Diagnostic {
+        let mut diagnostic = Diagnostic {
+            range: Range {
+                start: Position { line, character: 0 },
+                end: Position { line, character: 1 },
+            },
+            message: code.to_string(),
+            ..Default::default()
+        };
+        codes::find(code).unwrap().tag(&mut diagnostic);
+        diagnostic
+    }
+
+    fn remaining(source: &str, diagnostics: Vec<Diagnostic>) -> Vec<(u32, String)> {
+        apply(source, diagnostics, true, &DiagnosticsConfig::default())
+            .into_iter()
+            .map(|d| (d.range.start.line, d.message))
+            .collect()
+    }
+
+    #[test]
+    fn line_directives_cover_their_own_line() {
+        let source = "10 X = 1 ' basica-ignore unused-variable\n20 Y = 2\n";
+        let diagnostics = vec![
+            tagged(0, "unused-variable"),
+            tagged(0, "undefined-line"),
+            tagged(1, "unused-variable"),
+        ];
+        assert_eq!(
+            remaining(source, diagnostics),
+            [
+                (0, "undefined-line".to_string()),
+                (1, "unused-variable".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn file_directives_and_bare_directives() {
+        let source = "10 REM basica-ignore-file B001\n20 GOTO 99: REM basica-ignore\n";
+        let diagnostics = vec![tagged(1, "undefined-line"), tagged(1, "unused-variable")];
+        assert_eq!(remaining(source, diagnostics), []);
+    }
+
+    #[test]
+    fn unused_and_unknown_codes_are_reported() {
+        let source = "10 REM basica-ignore unused-variable, no-such-check\n";
+        let diagnostics = apply(source, Vec::new(), true, &DiagnosticsConfig::default());
+        let reported: Vec<(u32, u32, &str)> = diagnostics
+            .iter()
+            .map(|d| {
+                (
+                    d.range.start.character,
+                    d.range.end.character,
+                    d.message.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            reported,
+            [
+                (21, 36, "Suppression of 'unused-variable' is never used"),
+                (38, 51, "Unknown check 'no-such-check' in suppression"),
+            ]
+        );
+        assert_eq!(
+            codes::check_of(&diagnostics[0]).map(|c| c.name),
+            Some("unused-suppression")
+        );
+        assert!(apply(source, Vec::new(), false, &DiagnosticsConfig::default()).is_empty());
+    }
+
+    #[test]
+    fn checks_turned_off_need_no_suppression() {
+        let source = "10 REM basica-ignore unused-variable\n";
+        let config: DiagnosticsConfig = serde_json::from_value(serde_json::json!({
+            "severity": { "unused-variable": "off" }
+        }))
+        .unwrap();
+        assert!(apply(source, Vec::new(), true, &config).is_empty());
+    }
+
+    #[test]
+    fn other_words_are_not_directives() {
+        let source = "10 REM basica-ignored\n20 PRINT \"' basica-ignore\"\n";
+        let diagnostics = vec![tagged(0, "unused-variable"), tagged(1, "unused-variable")];
+        assert_eq!(remaining(source, diagnostics).len(), 2);
+    }
+}
@@ -7,6 +7,7 @@ pub fn get_document_symbols(source: &str) -> Vec<DocumentSymbol> {
 
     // Find all GOSUB targets to mark as subroutines
     let subroutine_lines = find_gosub_targets(source);
+    let handler_lines = find_error_handlers(source);
 
     for (line_idx, line) in source.lines().enumerate() {
         let trimmed = line.trim_start();
@@ -17,7 +18,13 @@ pub fn get_document_symbols(source: &str) -> Vec<DocumentSymbol> {
                 let rest = trimmed[first_word.len()..].trim_start();
 
                 // Determine symbol kind and name
-                let (name, kind, detail) = if subroutine_lines.contains(&line_num) {
+                let (name, kind, detail) = if handler_lines.contains(&line_num) {
+                    (
+                        format!("{} (ON ERROR)", line_num),
+                        SymbolKind::EVENT,
+                        Some("Error handler".to_string()),
+                    )
+                } else if subroutine_lines.contains(&line_num) {
                     (
                         format!("{} (SUB)", line_num),
                         SymbolKind::FUNCTION,
@@ -142,3 +149,30 @@ fn find_gosub_targets(source: &str) -> HashSet<u32> {
 
     targets
 }
+
+/// Find all line numbers that are targets of ON ERROR GOTO
+fn find_error_handlers(source: &str) -> HashSet<u32> {
+    let mut targets = HashSet::new();
+
+    for line in source.lines() {
+        let upper = line.to_uppercase();
+
+        for part in upper.split("ERROR").skip(1) {
+            if let Some(after) = part.trim_start().strip_prefix("GOTO") {
+                let num_str: String = after
+                    .trim_start()
+                    .chars()
+                    .take_while(|c| c.is_ascii_digit())
+                    .collect();
+                // ON ERROR GOTO 0 turns error trapping off
+                if let Ok(num) = num_str.parse::<u32>() {
+                    if num != 0 {
+                        targets.insert(num);
+                    }
+                }
+            }
+        }
+    }
+
+    targets
+}
@@ -0,0 +1,833 @@
+//! Programs saved by BASICA/GW-BASIC in tokenized form.
+//!
+//! A tokenized file starts with 0xFF (0xFE if saved with `,P`), followed by
+//! one record per line: a two-byte link to the next line (0 ends the
+//! program), the two-byte line number, the crunched statement bytes and a 0.
+//! Keywords and operators are single bytes from 0x81 up, or two bytes after
+//! a 0xFD/0xFE/0xFF prefix, and numeric constants are stored in binary.
+
+/// First byte of a tokenized program
+const TOKENIZED: u8 = 0xFF;
+
+/// First byte of a program saved with `SAVE "...",P`
+const PROTECTED: u8 = 0xFE;
+
+/// Address BASICA loads programs at; it relinks lines on load, so the link
+/// values we write only need to be non-zero
+const PROGRAM_START: usize = 0x126E;
+
+/// One-byte keyword and operator tokens
+const TOKENS: &[(u8, &str)] = &[
+    (0x81, "END"),
+    (0x82, "FOR"),
+    (0x83, "NEXT"),
+    (0x84, "DATA"),
+    (0x85, "INPUT"),
+    (0x86, "DIM"),
+    (0x87, "READ"),
+    (0x88, "LET"),
+    (0x89, "GOTO"),
+    (0x8A, "RUN"),
+    (0x8B, "IF"),
+    (0x8C, "RESTORE"),
+    (0x8D, "GOSUB"),
+    (0x8E, "RETURN"),
+    (0x8F, "REM"),
+    (0x90, "STOP"),
+    (0x91, "PRINT"),
+    (0x92, "CLEAR"),
+    (0x93, "LIST"),
+    (0x94, "NEW"),
+    (0x95, "ON"),
+    (0x96, "WAIT"),
+    (0x97, "DEF"),
+    (0x98, "POKE"),
+    (0x99, "CONT"),
+    (0x9C, "OUT"),
+    (0x9D, "LPRINT"),
+    (0x9E, "LLIST"),
+    (0xA0, "WIDTH"),
+    (0xA1, "ELSE"),
+    (0xA2, "TRON"),
+    (0xA3, "TROFF"),
+    (0xA4, "SWAP"),
+    (0xA5, "ERASE"),
+    (0xA6, "EDIT"),
+    (0xA7, "ERROR"),
+    (0xA8, "RESUME"),
+    (0xA9, "DELETE"),
+    (0xAA, "AUTO"),
+    (0xAB, "RENUM"),
+    (0xAC, "DEFSTR"),
+    (0xAD, "DEFINT"),
+    (0xAE, "DEFSNG"),
+    (0xAF, "DEFDBL"),
+    (0xB0, "LINE"),
+    (0xB1, "WHILE"),
+    (0xB2, "WEND"),
+    (0xB3, "CALL"),
+    (0xB7, "WRITE"),
+    (0xB8, "OPTION"),
+    (0xB9, "RANDOMIZE"),
+    (0xBA, "OPEN"),
+    (0xBB, "CLOSE"),
+    (0xBC, "LOAD"),
+    (0xBD, "MERGE"),
+    (0xBE, "SAVE"),
+    (0xBF, "COLOR"),
+    (0xC0, "CLS"),
+    (0xC1, "MOTOR"),
+    (0xC2, "BSAVE"),
+    (0xC3, "BLOAD"),
+    (0xC4, "SOUND"),
+    (0xC5, "BEEP"),
+    (0xC6, "PSET"),
+    (0xC7, "PRESET"),
+    (0xC8, "SCREEN"),
+    (0xC9, "KEY"),
+    (0xCA, "LOCATE"),
+    (0xCC, "TO"),
+    (0xCD, "THEN"),
+    (0xCE, "TAB("),
+    (0xCF, "STEP"),
+    (0xD0, "USR"),
+    (0xD1, "FN"),
+    (0xD2, "SPC("),
+    (0xD3, "NOT"),
+    (0xD4, "ERL"),
+    (0xD5, "ERR"),
+    (0xD6, "STRING$"),
+    (0xD7, "USING"),
+    (0xD8, "INSTR"),
+    (0xD9, "'"),
+    (0xDA, "VARPTR"),
+    (0xDB, "CSRLIN"),
+    (0xDC, "POINT"),
+    (0xDD, "OFF"),
+    (0xDE, "INKEY$"),
+    (0xE6, ">"),
+    (0xE7, "="),
+    (0xE8, "<"),
+    (0xE9, "+"),
+    (0xEA, "-"),
+    (0xEB, "*"),
+    (0xEC, "/"),
+    (0xED, "^"),
+    (0xEE, "AND"),
+    (0xEF, "OR"),
+    (0xF0, "XOR"),
+    (0xF1, "EQV"),
+    (0xF2, "IMP"),
+    (0xF3, "MOD"),
+    (0xF4, "\\"),
+];
+
+/// Two-byte tokens, by prefix and second byte
+const EXTENDED_TOKENS: &[(u8, u8, &str)] = &[
+    (0xFD, 0x81, "CVI"),
+    (0xFD, 0x82, "CVS"),
+    (0xFD, 0x83, "CVD"),
+    (0xFD, 0x84, "MKI$"),
+    (0xFD, 0x85, "MKS$"),
+    (0xFD, 0x86, "MKD$"),
+    (0xFD, 0x8B, "EXTERR"),
+    (0xFE, 0x81, "FILES"),
+    (0xFE, 0x82, "FIELD"),
+    (0xFE, 0x83, "SYSTEM"),
+    (0xFE, 0x84, "NAME"),
+    (0xFE, 0x85, "LSET"),
+    (0xFE, 0x86, "RSET"),
+    (0xFE, 0x87, "KILL"),
+    (0xFE, 0x88, "PUT"),
+    (0xFE, 0x89, "GET"),
+    (0xFE, 0x8A, "RESET"),
+    (0xFE, 0x8B, "COMMON"),
+    (0xFE, 0x8C, "CHAIN"),
+    (0xFE, 0x8D, "DATE$"),
+    (0xFE, 0x8E, "TIME$"),
+    (0xFE, 0x8F, "PAINT"),
+    (0xFE, 0x90, "COM"),
+    (0xFE, 0x91, "CIRCLE"),
+    (0xFE, 0x92, "DRAW"),
+    (0xFE, 0x93, "PLAY"),
+    (0xFE, 0x94, "TIMER"),
+    (0xFE, 0x95, "ERDEV"),
+    (0xFE, 0x96, "IOCTL"),
+    (0xFE, 0x97, "CHDIR"),
+    (0xFE, 0x98, "MKDIR"),
+    (0xFE, 0x99, "RMDIR"),
+    (0xFE, 0x9A, "SHELL"),
+    (0xFE, 0x9B, "ENVIRON"),
+    (0xFE, 0x9C, "VIEW"),
+    (0xFE, 0x9D, "WINDOW"),
+    (0xFE, 0x9E, "PMAP"),
+    (0xFE, 0x9F, "PALETTE"),
+    (0xFE, 0xA0, "LCOPY"),
+    (0xFE, 0xA1, "CALLS"),
+    (0xFE, 0xA4, "NOISE"),
+    (0xFE, 0xA5, "PCOPY"),
+    (0xFE, 0xA6, "TERM"),
+    (0xFE, 0xA7, "LOCK"),
+    (0xFE, 0xA8, "UNLOCK"),
+    (0xFF, 0x81, "LEFT$"),
+    (0xFF, 0x82, "RIGHT$"),
+    (0xFF, 0x83, "MID$"),
+    (0xFF, 0x84, "SGN"),
+    (0xFF, 0x85, "INT"),
+    (0xFF, 0x86, "ABS"),
+    (0xFF, 0x87, "SQR"),
+    (0xFF, 0x88, "RND"),
+    (0xFF, 0x89, "SIN"),
+    (0xFF, 0x8A, "LOG"),
+    (0xFF, 0x8B, "EXP"),
+    (0xFF, 0x8C, "COS"),
+    (0xFF, 0x8D, "TAN"),
+    (0xFF, 0x8E, "ATN"),
+    (0xFF, 0x8F, "FRE"),
+    (0xFF, 0x90, "INP"),
+    (0xFF, 0x91, "POS"),
+    (0xFF, 0x92, "LEN"),
+    (0xFF, 0x93, "STR$"),
+    (0xFF, 0x94, "VAL"),
+    (0xFF, 0x95, "ASC"),
+    (0xFF, 0x96, "CHR$"),
+    (0xFF, 0x97, "PEEK"),
+    (0xFF, 0x98, "SPACE$"),
+    (0xFF, 0x99, "OCT$"),
+    (0xFF, 0x9A, "HEX$"),
+    (0xFF, 0x9B, "LPOS"),
+    (0xFF, 0x9C, "CINT"),
+    (0xFF, 0x9D, "CSNG"),
+    (0xFF, 0x9E, "CDBL"),
+    (0xFF, 0x9F, "FIX"),
+    (0xFF, 0xA0, "PEN"),
+    (0xFF, 0xA1, "STICK"),
+    (0xFF, 0xA2, "STRIG"),
+    (0xFF, 0xA3, "EOF"),
+    (0xFF, 0xA4, "LOC"),
+    (0xFF, 0xA5, "LOF"),
+];
+
+/// Keywords whose numeric arguments are line numbers
+const LINE_NUMBER_KEYWORDS: &[&str] = &[
+    "GOTO", "GOSUB", "THEN", "ELSE", "RESTORE", "RESUME", "RUN", "LIST", "LLIST", "DELETE",
+    "RENUM", "EDIT", "AUTO",
+];
+
+/// Numeric constant prefixes
+const OCTAL: u8 = 0x0B;
+const HEX: u8 = 0x0C;
+const LINE_POINTER: u8 = 0x0D;
+const LINE_NUMBER: u8 = 0x0E;
+const BYTE: u8 = 0x0F;
+const DIGIT_0: u8 = 0x11;
+const DIGIT_9: u8 = 0x1B;
+const INTEGER: u8 = 0x1C;
+const SINGLE: u8 = 0x1D;
+const DOUBLE: u8 = 0x1F;
+
+/// Keys PC-BASIC found BASICA uses to scramble protected programs
+const KEY1: [u8; 13] = [
+    0xA9, 0x84, 0x8D, 0xCD, 0x75, 0x83, 0x43, 0x63, 0x24, 0x83, 0x19, 0xF7, 0x9A,
+];
+const KEY2: [u8; 11] = [
+    0x1E, 0x1D, 0xC4, 0x77, 0x26, 0x97, 0xE0, 0x74, 0x59, 0x88, 0x7C,
+];
+
+/// Code page 437 characters for bytes 0x80 to 0xFF
+const CP437_HIGH: &str = "ÇüéâäàåçêëèïîìÄÅÉæÆôöòûùÿÖÜ¢£¥₧ƒáíóúñÑªº¿⌐¬½¼¡«»\
+░▒▓│┤╡╢╖╕╣║╗╝╜╛┐└┴┬├─┼╞╟╚╔╩╦╠═╬╧╨╤╥╙╘╒╓╫╪┘┌█▄▌▐▀\
+αßΓπΣσµτΦΘΩδ∞φε∩≡±≥≤⌠⌡÷≈°∙·√ⁿ²■\u{a0}";
+
+/// Whether a file's contents are a tokenized (possibly protected) program
+pub fn is_tokenized(bytes: &[u8]) -> bool {
+    matches!(bytes.first(), Some(&TOKENIZED) | Some(&PROTECTED))
+}
+
+/// Turn a tokenized program back into the text LIST would show
+pub fn detokenize(bytes: &[u8]) -> Result<String, String> {
+    let bytes = match bytes.first() {
+        Some(&TOKENIZED) => bytes[1..].to_vec(),
+        Some(&PROTECTED) => unprotect(&bytes[1..]),
+        _ => return Err("Not a tokenized BASIC program".to_string()),
+    };
+
+    let mut text = String::new();
+    let mut pos = 0;
+    // Some files end without a final zero link
+    while let Some(link) = read_u16(&bytes, pos) {
+        if link == 0 {
+            break;
+        }
+        let number = read_u16(&bytes, pos + 2)
+            .ok_or_else(|| format!("Truncated line at offset {}", pos + 1))?;
+        let (line, len) =
+            detokenize_line(&bytes[pos + 4..]).map_err(|e| format!("Line {}: {}", number, e))?;
+
+        text.push_str(&number.to_string());
+        text.push(' ');
+        text.push_str(&line);
+        text.push('\n');
+        pos += 4 + len + 1;
+    }
+
+    Ok(text)
+}
+
+/// Tokenize a program as `SAVE "..."` would
+pub fn tokenize(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = vec![TOKENIZED];
+
+    for (idx, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let digits = line.bytes().take_while(u8::is_ascii_digit).count();
+        let number: u16 = line[..digits]
+            .parse()
+            .ok()
+            .filter(|n| *n <= 65529)
+            .ok_or_else(|| format!("Line {} has no valid line number", idx + 1))?;
+
+        let body = tokenize_line(line[digits..].trim_start());
+        // Link to the next line: this line's address plus its length
+        let next = u16::try_from(PROGRAM_START + bytes.len() - 1 + 4 + body.len() + 1)
+            .map_err(|_| format!("Line {} ends past the 64K program space", number))?;
+        bytes.extend_from_slice(&next.to_le_bytes());
+        bytes.extend_from_slice(&number.to_le_bytes());
+        bytes.extend_from_slice(&body);
+        bytes.push(0);
+    }
+
+    bytes.extend_from_slice(&[0, 0, 0x1A]);
+    Ok(bytes)
+}
+
+fn read_u16(bytes: &[u8], pos: usize) -> Option<u16> {
+    Some(u16::from_le_bytes([*bytes.get(pos)?, *bytes.get(pos + 1)?]))
+}
+
+/// Undo the scrambling of a protected program
+fn unprotect(bytes: &[u8]) -> Vec<u8> {
+    bytes
+        .iter()
+        .enumerate()
+        .map(|(i, &b)| {
+            let mut c = b.wrapping_sub(11 - (i % 11) as u8);
+            c ^= KEY1[i % 13];
+            c ^= KEY2[i % 11];
+            c.wrapping_add(13 - (i % 13) as u8)
+        })
+        .collect()
+}
+
+/// Detokenize the statements of one line, up to the 0 that ends it,
+/// returning the text and the number of bytes before that 0
+fn detokenize_line(bytes: &[u8]) -> Result<(String, usize), String> {
+    let mut out = String::new();
+    let mut pos = 0;
+    let mut in_data = false;
+
+    loop {
+        let b = *bytes.get(pos).ok_or("Line is not terminated")?;
+        if b == 0 {
+            return Ok((out, pos));
+        }
+        pos += 1;
+
+        // Strings are stored as typed
+        if b == b'"' {
+            out.push('"');
+            while let Some(&c) = bytes.get(pos).filter(|&&c| c != 0) {
+                pos += 1;
+                out.push(cp437_char(c));
+                if c == b'"' {
+                    break;
+                }
+            }
+            continue;
+        }
+
+        if in_data {
+            in_data = b != b':';
+            out.push(cp437_char(b));
+            continue;
+        }
+
+        match b {
+            // ELSE and ' are stored with a colon in front
+            b':' if bytes[pos..].starts_with(&[0xA1]) => {
+                out.push_str("ELSE");
+                pos += 1;
+            }
+            b':' if bytes[pos..].starts_with(&[0x8F, 0xD9]) => {
+                out.push('\'');
+                pos += 2;
+                pos += copy_comment(&bytes[pos..], &mut out);
+            }
+            0x8F | 0xD9 => {
+                out.push_str(if b == 0x8F { "REM" } else { "'" });
+                pos += copy_comment(&bytes[pos..], &mut out);
+            }
+            // WHILE is stored followed by a + token
+            0xB1 if bytes.get(pos) == Some(&0xE9) => {
+                out.push_str("WHILE");
+                pos += 1;
+            }
+            OCTAL | HEX | LINE_POINTER | LINE_NUMBER | INTEGER => {
+                let value = read_u16(bytes, pos).ok_or("Truncated number")?;
+                pos += 2;
+                match b {
+                    OCTAL => out.push_str(&format!("&O{:o}", value)),
+                    HEX => out.push_str(&format!("&H{:X}", value)),
+                    INTEGER => out.push_str(&(value as i16).to_string()),
+                    _ => out.push_str(&value.to_string()),
+                }
+            }
+            BYTE => {
+                let value = *bytes.get(pos).ok_or("Truncated number")?;
+                pos += 1;
+                out.push_str(&value.to_string());
+            }
+            DIGIT_0..=DIGIT_9 => out.push_str(&(b - DIGIT_0).to_string()),
+            SINGLE => {
+                let mbf = bytes.get(pos..pos + 4).ok_or("Truncated number")?;
+                pos += 4;
+                out.push_str(&format_single(from_mbf(mbf)));
+            }
+            DOUBLE => {
+                let mbf = bytes.get(pos..pos + 8).ok_or("Truncated number")?;
+                pos += 8;
+                out.push_str(&format_double(from_mbf(mbf)));
+            }
+            0xFD..=0xFF => {
+                let second = *bytes.get(pos).ok_or("Truncated keyword")?;
+                pos += 1;
+                match EXTENDED_TOKENS
+                    .iter()
+                    .find(|(p, s, _)| *p == b && *s == second)
+                {
+                    Some((_, _, word)) => out.push_str(word),
+                    None => return Err(format!("Unknown token {:02X} {:02X}", b, second)),
+                }
+            }
+            0x80..=0xFC => match TOKENS.iter().find(|(t, _)| *t == b) {
+                Some((_, word)) => {
+                    out.push_str(word);
+                    in_data = b == 0x84;
+                }
+                None => return Err(format!("Unknown token {:02X}", b)),
+            },
+            _ => out.push(b as char),
+        }
+    }
+}
+
+/// Copy the text of a comment up to the end of the line, returning its length
+fn copy_comment(bytes: &[u8], out: &mut String) -> usize {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    out.extend(bytes[..len].iter().map(|&c| cp437_char(c)));
+    len
+}
+
+fn tokenize_line(line: &str) -> Vec<u8> {
+    let chars: Vec<char> = line.chars().collect();
+    let mut out = Vec::new();
+    let mut pos = 0;
+    let mut line_numbers = false;
+
+    while pos < chars.len() {
+        let c = chars[pos];
+
+        if c == '"' {
+            let end = chars[pos + 1..]
+                .iter()
+                .position(|&c| c == '"')
+                .map_or(chars.len(), |p| pos + p + 2);
+            out.extend(chars[pos..end].iter().map(|&c| cp437_byte(c)));
+            pos = end;
+            continue;
+        }
+
+        if c == '\'' {
+            out.extend_from_slice(&[b':', 0x8F, 0xD9]);
+            out.extend(chars[pos + 1..].iter().map(|&c| cp437_byte(c)));
+            break;
+        }
+
+        if c.is_ascii_alphabetic() {
+            if let Some((word, token)) = keyword_at(&chars[pos..]) {
+                pos += word.chars().count();
+                line_numbers = LINE_NUMBER_KEYWORDS.contains(&word);
+                match word {
+                    "ELSE" => out.push(b':'),
+                    "WHILE" => {
+                        out.extend_from_slice(&token);
+                        out.push(0xE9);
+                        continue;
+                    }
+                    _ => {}
+                }
+                out.extend_from_slice(&token);
+
+                // The rest of a REM, and DATA items up to a colon, are literal
+                if word == "REM" {
+                    out.extend(chars[pos..].iter().map(|&c| cp437_byte(c)));
+                    break;
+                }
+                if word == "DATA" {
+                    let mut quoted = false;
+                    while pos < chars.len() && (quoted || chars[pos] != ':') {
+                        quoted ^= chars[pos] == '"';
+                        out.push(cp437_byte(chars[pos]));
+                        pos += 1;
+                    }
+                }
+                continue;
+            }
+
+            // A variable name, stored in capitals
+            while pos < chars.len() && (chars[pos].is_ascii_alphanumeric() || chars[pos] == '.') {
+                out.push(chars[pos].to_ascii_uppercase() as u8);
+                pos += 1;
+            }
+            if pos < chars.len() && matches!(chars[pos], '$' | '%' | '!' | '#') {
+                out.push(chars[pos] as u8);
+                pos += 1;
+            }
+            line_numbers = false;
+            continue;
+        }
+
+        if c.is_ascii_digit() || (c == '.' && chars.get(pos + 1).is_some_and(char::is_ascii_digit))
+        {
+            let end = number_end(&chars, pos);
+            let literal: String = chars[pos..end].iter().collect();
+            out.extend(encode_number(&literal, line_numbers));
+            pos = end;
+            continue;
+        }
+
+        if c == '&' {
+            let radix_char = chars.get(pos + 1).map(char::to_ascii_uppercase);
+            let (radix, prefix, skip) = match radix_char {
+                Some('H') => (16, HEX, 2),
+                Some('O') => (8, OCTAL, 2),
+                _ => (8, OCTAL, 1),
+            };
+            let digits: String = chars[pos + skip..]
+                .iter()
+                .take_while(|c| c.is_digit(radix))
+                .collect();
+            if let Ok(value) = u16::from_str_radix(&digits, radix) {
+                out.push(prefix);
+                out.extend_from_slice(&value.to_le_bytes());
+                pos += skip + digits.len();
+                continue;
+            }
+        }
+
+        if let Some(&(token, _)) = TOKENS.iter().find(|(_, word)| {
+            word.len() == 1 && !word.starts_with(|w: char| w.is_alphabetic()) && word.starts_with(c)
+        }) {
+            out.push(token);
+        } else {
+            out.push(cp437_byte(c));
+        }
+        // Line number lists continue over commas and spaces (ON X GOTO 10, 20)
+        line_numbers &= c == ',' || c == ' ';
+        pos += 1;
+    }
+
+    out
+}
+
+/// The longest keyword at the start of `chars`, with its token bytes
+fn keyword_at(chars: &[char]) -> Option<(&'static str, Vec<u8>)> {
+    let matches = |word: &str| {
+        word.chars().count() <= chars.len()
+            && word
+                .chars()
+                .zip(chars)
+                .all(|(w, c)| w == c.to_ascii_uppercase())
+    };
+    let simple = TOKENS
+        .iter()
+        .filter(|(_, word)| word.starts_with(|c: char| c.is_ascii_alphabetic()) && matches(word))
+        .map(|&(t, word)| (word, vec![t]));
+    let extended = EXTENDED_TOKENS
+        .iter()
+        .filter(|(_, _, word)| matches(word))
+        .map(|&(p, s, word)| (word, vec![p, s]));
+    simple.chain(extended).max_by_key(|(word, _)| word.len())
+}
+
+/// End of a numeric literal: digits, a point, an exponent and a type suffix
+fn number_end(chars: &[char], mut pos: usize) -> usize {
+    while pos < chars.len() && (chars[pos].is_ascii_digit() || chars[pos] == '.') {
+        pos += 1;
+    }
+    if pos < chars.len() && matches!(chars[pos].to_ascii_uppercase(), 'E' | 'D') {
+        let mut end = pos + 1;
+        if end < chars.len() && matches!(chars[end], '+' | '-') {
+            end += 1;
+        }
+        if end < chars.len() && chars[end].is_ascii_digit() {
+            pos = end;
+            while pos < chars.len() && chars[pos].is_ascii_digit() {
+                pos += 1;
+            }
+        }
+    }
+    if pos < chars.len() && matches!(chars[pos], '%' | '!' | '#') {
+        pos += 1;
+    }
+    pos
+}
+
+/// Encode a numeric literal in the smallest form BASICA would use
+fn encode_number(literal: &str, line_number: bool) -> Vec<u8> {
+    let upper = literal.to_ascii_uppercase();
+    let suffix = upper
+        .chars()
+        .last()
+        .filter(|c| matches!(c, '%' | '!' | '#'));
+    let body = upper.trim_end_matches(['%', '!', '#']);
+    let is_integer = body.bytes().all(|b| b.is_ascii_digit());
+
+    if is_integer && suffix.is_none_or(|s| s == '%') {
+        if let Ok(value) = body.parse::<u32>() {
+            if line_number && value <= 65529 {
+                let mut out = vec![LINE_NUMBER];
+                out.extend_from_slice(&(value as u16).to_le_bytes());
+                return out;
+            }
+            match value {
+                0..=9 => return vec![DIGIT_0 + value as u8],
+                10..=255 => return vec![BYTE, value as u8],
+                256..=32767 => {
+                    let mut out = vec![INTEGER];
+                    out.extend_from_slice(&(value as u16).to_le_bytes());
+                    return out;
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let value: f64 = body.replace('D', "E").parse().unwrap_or(0.0);
+    let digits = body
+        .split(['E', 'D'])
+        .next()
+        .unwrap_or("")
+        .trim_start_matches(['0', '.'])
+        .bytes()
+        .filter(u8::is_ascii_digit)
+        .count();
+    let double = suffix == Some('#') || body.contains('D') || (suffix.is_none() && digits > 7);
+
+    let mut out = vec![if double { DOUBLE } else { SINGLE }];
+    out.extend(to_mbf(value, if double { 8 } else { 4 }));
+    out
+}
+
+/// Decode a Microsoft Binary Format number (4 or 8 bytes, exponent last)
+fn from_mbf(bytes: &[u8]) -> f64 {
+    let (exponent, mantissa) = bytes.split_last().unwrap();
+    if *exponent == 0 {
+        return 0.0;
+    }
+    let (high, rest) = mantissa.split_last().unwrap();
+    let mut value = (*high | 0x80) as u64;
+    for &b in rest.iter().rev() {
+        value = (value << 8) | b as u64;
+    }
+    let bits = 8 * mantissa.len() as i32;
+    let result = value as f64 * 2f64.powi(*exponent as i32 - 128 - bits);
+    if high & 0x80 != 0 {
+        -result
+    } else {
+        result
+    }
+}
+
+/// Encode a number in Microsoft Binary Format
+fn to_mbf(value: f64, len: usize) -> Vec<u8> {
+    if value == 0.0 || !value.is_finite() {
+        return vec![0; len];
+    }
+    let bits = 8 * (len - 1) as i32;
+    let magnitude = value.abs();
+    // magnitude = fraction * 2^exponent with 0.5 <= fraction < 1
+    let mut exponent = magnitude.log2().floor() as i32 + 1;
+    let mut mantissa = (magnitude * 2f64.powi(bits - exponent)).round() as u64;
+    if mantissa >= 1 << bits {
+        mantissa >>= 1;
+        exponent += 1;
+    }
+    let exponent = (exponent + 128).clamp(1, 255) as u8;
+
+    let mut out: Vec<u8> = (0..len - 1).map(|i| (mantissa >> (8 * i)) as u8).collect();
+    out[len - 2] &= 0x7F;
+    if value < 0.0 {
+        out[len - 2] |= 0x80;
+    }
+    out.push(exponent);
+    out
+}
+
+fn format_single(value: f64) -> String {
+    let text = format_number(value, true);
+    // Whole numbers need the suffix to stay single precision
+    if text.contains(['.', 'E']) {
+        text
+    } else {
+        text + "!"
+    }
+}
+
+fn format_double(value: f64) -> String {
+    let text = format_number(value, false).replace('E', "D");
+    let digits = text
+        .split('D')
+        .next()
+        .unwrap_or("")
+        .bytes()
+        .filter(u8::is_ascii_digit)
+        .count();
+    // Short constants would read back as single precision
+    if digits <= 7 || !text.contains(['.', 'D']) {
+        text + "#"
+    } else {
+        text
+    }
+}
+
+/// Write very large and small numbers with an exponent, and drop the
+/// leading zero of fractions as LIST does
+pub fn format_number(value: f64, single: bool) -> String {
+    let magnitude = value.abs();
+    let text = if magnitude >= 1e16 || (magnitude != 0.0 && magnitude < 1e-7) {
+        let text = if single {
+            format!("{:E}", value as f32)
+        } else {
+            format!("{:E}", value)
+        };
+        if text.contains("E-") {
+            text
+        } else {
+            text.replace('E', "E+")
+        }
+    } else if single {
+        format!("{}", value as f32)
+    } else {
+        format!("{}", value)
+    };
+    match text.strip_prefix("0.") {
+        Some(fraction) => format!(".{}", fraction),
+        None => text.replacen("-0.", "-.", 1),
+    }
+}
+
+fn cp437_char(b: u8) -> char {
+    if b < 0x80 {
+        b as char
+    } else {
+        CP437_HIGH.chars().nth((b - 0x80) as usize).unwrap_or('?')
+    }
+}
+
+fn cp437_byte(c: char) -> u8 {
+    if c.is_ascii() {
+        c as u8
+    } else {
+        CP437_HIGH
+            .chars()
+            .position(|h| h == c)
+            .map_or(b'?', |p| 0x80 + p as u8)
+    }
+}
+
+/// Convert a file between tokenized and ASCII form, returning the new
+/// contents and whether they are tokenized. ASCII files are code page 437,
+/// as BASICA reads and writes them
+pub fn convert(bytes: &[u8]) -> Result<(Vec<u8>, bool), String> {
+    if is_tokenized(bytes) {
+        // ASCII saves use DOS line endings
+        let text = detokenize(bytes)?.replace('\n', "\r\n");
+        Ok((text.chars().map(cp437_byte).collect(), false))
+    } else {
+        let text: String = bytes.iter().map(|&b| cp437_char(b)).collect();
+        Ok((tokenize(&text)?, true))
+    }
+}
+
+/// Read a program from disk as text, detokenizing it if needed
+pub fn read_program(path: &std::path::Path) -> Result<String, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if is_tokenized(&bytes) {
+        detokenize(&bytes)
+    } else {
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mbf_round_trips() {
+        for value in [1.0, 0.5, -3.25, 100.0, 65535.0, 1e10, -0.001, 3.140625] {
+            assert_eq!(from_mbf(&to_mbf(value, 4)), value as f32 as f64);
+            assert_eq!(from_mbf(&to_mbf(value, 8)), value);
+        }
+        assert_eq!(to_mbf(0.0, 4), [0, 0, 0, 0]);
+        assert_eq!(from_mbf(&[0, 0, 0, 0]), 0.0);
+    }
+
+    #[test]
+    fn detokenize_undoes_tokenize() {
+        // As LIST shows it, so every literal keeps its spelling
+        let program = "10 REM Test\n\
+            20 PRINT \"HELLO\"; 3.5; 40000!; &HFF\n\
+            30 FOR I=1 TO 10 STEP 2: X=X+I*1.5#: NEXT I\n\
+            40 IF X>10 THEN 60 ELSE GOSUB 100\n\
+            50 ' comment\n\
+            60 ON X GOTO 10, 20, 30\n\
+            100 A$=LEFT$(\"ABC\",2): RETURN\n";
+        let bytes = tokenize(program).unwrap();
+        assert!(is_tokenized(&bytes));
+        assert_eq!(detokenize(&bytes).unwrap(), program);
+    }
+
+    #[test]
+    fn conversion_uses_code_page_437() {
+        // "é" and "─" are 0x82 and 0xC4 in code page 437
+        let ascii = b"10 PRINT \"\x82\xC4\"\r\n";
+        let (bytes, is_tokenized) = convert(ascii).unwrap();
+        assert!(is_tokenized);
+        assert!(bytes.windows(2).any(|w| w == [0x82, 0xC4]));
+        assert_eq!(detokenize(&bytes).unwrap(), "10 PRINT \"é─\"\n");
+
+        let (back, is_tokenized) = convert(&bytes).unwrap();
+        assert!(!is_tokenized);
+        assert_eq!(back, ascii);
+    }
+
+    #[test]
+    fn programs_past_64k_fail() {
+        let line = format!("PRINT \"{}\"", "X".repeat(240));
+        let program: String = (1..=300).map(|n| format!("{} {}\n", n, line)).collect();
+        let error = tokenize(&program).unwrap_err();
+        assert!(
+            error.ends_with("ends past the 64K program space"),
+            "{}",
+            error
+        );
+        assert!(tokenize("10 GOTO 10\n65530 END\n")
+            .unwrap_err()
+            .starts_with("Line 2"));
+    }
+}
@@ -1,13 +1,42 @@
 import * as path from 'path';
-import { workspace, ExtensionContext } from 'vscode';
+import {
+    commands,
+    debug,
+    languages,
+    DebugAdapterExecutable,
+    DebugConfiguration,
+    OutputChannel,
+    window,
+    workspace,
+    ExtensionContext,
+    TextDocument,
+    TextDocumentContentProvider,
+    Uri,
+    ViewColumn,
+} from 'vscode';
 import {
     LanguageClient,
     LanguageClientOptions,
     ServerOptions,
     Executable,
+    ExecuteCommandRequest,
+    Location as ProtocolLocation,
+    Position as ProtocolPosition,
 } from 'vscode-languageclient/node';
 
 let client: LanguageClient | undefined;
+let output: OutputChannel | undefined;
+
+// One entry of the `basica/testResults` notification
+interface TestResult {
+    uri: string;
+    expectedUri: string;
+    passed: boolean;
+    message?: string;
+}
+
+// Scheme of the read-only text view of a tokenized program
+const DETOKENIZED_SCHEME = 'basica-detokenized';
 
 export function activate(context: ExtensionContext) {
     const config = workspace.getConfiguration('basica.lsp');
@@ -34,16 +63,45 @@ export function activate(context: ExtensionContext) {
         args: [],
     };
 
+    // The same binary speaks the Debug Adapter Protocol in `dap` mode
+    const debugServer = serverPath;
+    context.subscriptions.push(
+        debug.registerDebugAdapterDescriptorFactory('basica', {
+            createDebugAdapterDescriptor: () => new DebugAdapterExecutable(debugServer, ['dap']),
+        }),
+        debug.registerDebugConfigurationProvider('basica', {
+            // F5 without a launch.json debugs the current program
+            resolveDebugConfiguration(_folder, config: DebugConfiguration) {
+                if (!config.type && !config.request && !config.name) {
+                    const editor = window.activeTextEditor;
+                    if (editor?.document.languageId !== 'basica') {
+                        return config;
+                    }
+                    config.type = 'basica';
+                    config.request = 'launch';
+                    config.name = 'Debug BASIC program';
+                    config.program = '${file}';
+                }
+                return config;
+            },
+        })
+    );
+
     const serverOptions: ServerOptions = {
         run: serverExecutable,
         debug: serverExecutable,
     };
 
     const clientOptions: LanguageClientOptions = {
-        documentSelector: [{ scheme: 'file', language: 'basica' }],
+        documentSelector: [
+            { scheme: 'file', language: 'basica' },
+            { scheme: DETOKENIZED_SCHEME, language: 'basica' },
+        ],
         synchronize: {
             fileEvents: workspace.createFileSystemWatcher('**/*.bas'),
+            configurationSection: 'basica',
         },
+        initializationOptions: serverSettings(),
     };
 
     client = new LanguageClient(
@@ -53,7 +111,358 @@ export function activate(context: ExtensionContext) {
         clientOptions
     );
 
+    context.subscriptions.push(
+        commands.registerCommand('basica.renumberProgram', async () => {
+            const editor = window.activeTextEditor;
+            if (!client || !editor || editor.document.languageId !== 'basica') {
+                return;
+            }
+            await client.sendRequest(ExecuteCommandRequest.type, {
+                command: 'basica.renumber',
+                arguments: [editor.document.uri.toString()],
+            });
+        }),
+        commands.registerCommand('basica.runProgram', async () => {
+            const editor = window.activeTextEditor;
+            if (!client || !editor || editor.document.languageId !== 'basica') {
+                return;
+            }
+            output ??= window.createOutputChannel('BASIC Output');
+            output.clear();
+            output.show(true);
+            const result = await client.sendRequest(ExecuteCommandRequest.type, {
+                command: 'basica.run',
+                arguments: [editor.document.uri.toString()],
+            });
+            if (result?.status === 'error') {
+                window.showErrorMessage(result.message);
+            } else if (result?.status === 'timeout' || result?.status === 'stepLimit') {
+                window.showWarningMessage(result.message);
+            }
+        }),
+        commands.registerCommand('basica.traceProgram', async () => {
+            const editor = window.activeTextEditor;
+            if (!client || !editor || editor.document.languageId !== 'basica') {
+                return;
+            }
+            const script = await window.showOpenDialog({
+                title: 'Input script for INPUT statements (cancel to run without one)',
+                canSelectMany: false,
+            });
+            output ??= window.createOutputChannel('BASIC Output');
+            output.clear();
+            output.show(true);
+            const args = [editor.document.uri.toString()];
+            if (script?.[0]) {
+                args.push(script[0].toString());
+            }
+            const result = await client.sendRequest(ExecuteCommandRequest.type, {
+                command: 'basica.trace',
+                arguments: args,
+            });
+            if (result) {
+                const summary = `${result.executed} of ${result.lines} lines ran`;
+                window.showInformationMessage(result.message ? `${result.message}. ${summary}` : summary);
+            }
+        }),
+        commands.registerCommand('basica.runTests', async (uri?: Uri) => {
+            if (!client) {
+                return;
+            }
+            output ??= window.createOutputChannel('BASIC Output');
+            output.clear();
+            const result = await client.sendRequest(ExecuteCommandRequest.type, {
+                command: 'basica.test',
+                arguments: uri ? [uri.toString()] : [],
+            });
+            const results: TestResult[] = result?.results ?? [];
+            const failed = results.filter((r) => !r.passed).length;
+            if (results.length === 0) {
+                window.showInformationMessage('No programs with expected output (PROG.BAS and PROG.out) found');
+            } else if (failed > 0) {
+                output.show(true);
+                window.showErrorMessage(`${failed} of ${results.length} BASIC tests failed`);
+            } else {
+                window.showInformationMessage(`All ${results.length} BASIC tests passed`);
+            }
+        }),
+        commands.registerCommand('basica.previewScreen', async () => {
+            const editor = window.activeTextEditor;
+            if (!client || !editor || editor.document.languageId !== 'basica') {
+                return;
+            }
+            const choice = await window.showQuickPick(['Final screen', 'Frames while it draws'], {
+                placeHolder: 'What to capture',
+            });
+            if (!choice) {
+                return;
+            }
+            const result = await client.sendRequest(ExecuteCommandRequest.type, {
+                command: 'basica.render',
+                arguments: [editor.document.uri.toString(), choice !== 'Final screen'],
+            });
+            if (!result?.frames?.length) {
+                window.showWarningMessage(result?.message ?? 'The program drew nothing');
+                return;
+            }
+            if (result.message) {
+                window.showWarningMessage(result.message);
+            }
+            showScreen(path.basename(editor.document.fileName), result);
+        }),
+        commands.registerCommand('basica.exportAudio', async () => {
+            const editor = window.activeTextEditor;
+            if (!client || !editor || editor.document.languageId !== 'basica') {
+                return;
+            }
+            const choice = await window.showQuickPick(['From constant PLAY and SOUND statements', 'By running the program'], {
+                placeHolder: 'Where to take the music from',
+            });
+            if (!choice) {
+                return;
+            }
+            const document = editor.document.uri;
+            const target = await window.showSaveDialog({
+                defaultUri: document.with({ path: document.path.replace(/\.[^./]*$/, '') + '.wav' }),
+                filters: { 'WAV audio': ['wav'] },
+            });
+            if (!target) {
+                return;
+            }
+            const result = await client.sendRequest(ExecuteCommandRequest.type, {
+                command: 'basica.exportAudio',
+                arguments: [document.toString(), target.toString(), choice === 'By running the program'],
+            });
+            if (!result?.seconds) {
+                window.showWarningMessage(result?.message ?? 'Nothing to export');
+                return;
+            }
+            const saved = `Saved ${result.seconds.toFixed(1)} seconds of audio to ${path.basename(target.fsPath)}`;
+            window.showInformationMessage(result.message ? `${result.message}. ${saved}` : saved);
+        }),
+        // Reference count lenses run this with LSP values; the peek wants VS Code ones
+        commands.registerCommand(
+            'basica.showReferences',
+            (uri: string, position: ProtocolPosition, locations: ProtocolLocation[]) => {
+                const converter = client?.protocol2CodeConverter;
+                if (!converter) {
+                    return;
+                }
+                return commands.executeCommand(
+                    'editor.action.showReferences',
+                    Uri.parse(uri),
+                    converter.asPosition(position),
+                    locations.map((location) => converter.asLocation(location))
+                );
+            }
+        ),
+        commands.registerCommand('basica.openImmediate', () => {
+            if (client) {
+                showImmediate(client);
+            }
+        }),
+        workspace.registerTextDocumentContentProvider(DETOKENIZED_SCHEME, detokenizedProvider),
+        commands.registerCommand('basica.showDetokenized', async (uri?: Uri) => {
+            const file = uri ?? window.activeTextEditor?.document.uri;
+            if (file) {
+                await showDetokenized(file);
+            }
+        }),
+        commands.registerCommand('basica.convertFile', async (uri?: Uri) => {
+            const file = uri ?? window.activeTextEditor?.document.uri;
+            if (!client || !file || file.scheme !== 'file') {
+                return;
+            }
+            const target = await window.showSaveDialog({ defaultUri: file });
+            if (!target) {
+                return;
+            }
+            try {
+                const result = await client.sendRequest(ExecuteCommandRequest.type, {
+                    command: 'basica.convert',
+                    arguments: [file.toString(), target.toString()],
+                });
+                const form = result?.tokenized ? 'tokenized' : 'ASCII';
+                window.showInformationMessage(`Saved ${path.basename(target.fsPath)} in ${form} form`);
+            } catch (error) {
+                window.showErrorMessage(`Convert failed: ${error instanceof Error ? error.message : error}`);
+            }
+        }),
+        workspace.onDidOpenTextDocument(openTokenized)
+    );
+
+    client.onNotification('basica/output', (params: { uri: string; text: string }) => {
+        output?.append(params.text);
+    });
+    client.onNotification('basica/testResults', (params: { results: TestResult[] }) => {
+        for (const result of params.results) {
+            const name = Uri.parse(result.uri).fsPath;
+            output?.appendLine(result.passed ? `PASS ${name}` : `FAIL ${name}: ${result.message}`);
+        }
+    });
     client.start();
+    workspace.textDocuments.forEach(openTokenized);
+}
+
+const detokenizedProvider: TextDocumentContentProvider = {
+    async provideTextDocumentContent(uri: Uri): Promise<string> {
+        if (!client) {
+            return '';
+        }
+        const result = await client.sendRequest<{ text: string }>('basica/detokenize', {
+            uri: uri.query,
+        });
+        return result.text;
+    },
+};
+
+// Show the PNG frames of `basica.render` in a panel, scaled up without
+// smoothing, with a slider to step through them
+function showScreen(name: string, result: { width: number; height: number; frames: string[] }) {
+    const panel = window.createWebviewPanel('basicaScreen', `Screen: ${name}`, ViewColumn.Beside, {
+        enableScripts: result.frames.length > 1,
+    });
+    const last = result.frames.length - 1;
+    const slider =
+        last > 0
+            ? `<input id="frame" type="range" min="0" max="${last}" value="${last}">
+<script>
+const frames = ${JSON.stringify(result.frames)};
+const image = document.getElementById('screen');
+document.getElementById('frame').addEventListener('input', (event) => {
+    image.src = 'data:image/png;base64,' + frames[event.target.value];
+});
+</script>`
+            : '';
+    panel.webview.html = `<!DOCTYPE html>
+<html>
+<head>
+<meta http-equiv="Content-Security-Policy" content="default-src 'none'; img-src data:; style-src 'unsafe-inline'; script-src 'unsafe-inline';">
+<style>
+img { width: ${result.width * 2}px; max-width: 100%; image-rendering: pixelated; }
+input { display: block; width: ${result.width * 2}px; max-width: 100%; }
+</style>
+</head>
+<body>
+<img id="screen" src="data:image/png;base64,${result.frames[last]}">
+${slider}
+</body>
+</html>`;
+}
+
+// A panel with BASICA's Ok prompt: each line typed is sent to
+// `basica/evaluate`, and what it prints is shown under it
+function showImmediate(languageClient: LanguageClient) {
+    const panel = window.createWebviewPanel('basicaImmediate', 'BASIC Immediate', ViewColumn.Beside, {
+        enableScripts: true,
+        retainContextWhenHidden: true,
+    });
+    panel.webview.onDidReceiveMessage(async (message: { text: string; reset?: boolean }) => {
+        try {
+            const result = await languageClient.sendRequest<{ output: string; error: string | null }>(
+                'basica/evaluate',
+                message
+            );
+            panel.webview.postMessage(result);
+        } catch (error) {
+            // Still answer, or the prompt stays disabled
+            const text = error instanceof Error ? error.message : String(error);
+            panel.webview.postMessage({ output: '', error: text });
+        }
+    });
+    panel.webview.html = `<!DOCTYPE html>
+<html>
+<head>
+<meta http-equiv="Content-Security-Policy" content="default-src 'none'; style-src 'unsafe-inline'; script-src 'unsafe-inline';">
+<style>
+body { font-family: var(--vscode-editor-font-family); font-size: var(--vscode-editor-font-size); }
+pre { margin: 0; white-space: pre-wrap; }
+input { width: 100%; font: inherit; color: inherit; background: transparent; border: none; outline: none; }
+</style>
+</head>
+<body>
+<pre id="log">Ok\n</pre>
+<input id="line" autofocus spellcheck="false">
+<script>
+const vscode = acquireVsCodeApi();
+const log = document.getElementById('log');
+const line = document.getElementById('line');
+line.addEventListener('keydown', (event) => {
+    if (event.key !== 'Enter' || !line.value.trim()) {
+        return;
+    }
+    const text = line.value;
+    line.value = '';
+    line.disabled = true;
+    log.textContent += text + '\\n';
+    // NEW forgets the session's variables
+    vscode.postMessage({ text, reset: /^\\s*NEW\\s*$/i.test(text) });
+});
+window.addEventListener('message', (event) => {
+    const { output, error } = event.data;
+    log.textContent += output;
+    if (output && !output.endsWith('\\n')) {
+        log.textContent += '\\n';
+    }
+    log.textContent += (error ? error + '\\n' : '') + 'Ok\\n';
+    line.disabled = false;
+    line.focus();
+    line.scrollIntoView();
+});
+</script>
+</body>
+</html>`;
+}
+
+// Show the text of a program, detokenized if it's saved in binary form
+async function showDetokenized(file: Uri) {
+    const uri = Uri.from({ scheme: DETOKENIZED_SCHEME, path: file.path, query: file.toString() });
+    const document = await workspace.openTextDocument(uri);
+    await languages.setTextDocumentLanguage(document, 'basica');
+    await window.showTextDocument(document, { preview: false });
+}
+
+// Open tokenized programs as text instead of the raw bytes
+async function openTokenized(document: TextDocument) {
+    if (document.languageId !== 'basica' || document.uri.scheme !== 'file') {
+        return;
+    }
+    const bytes = await workspace.fs.readFile(document.uri);
+    if (bytes[0] === 0xff || bytes[0] === 0xfe) {
+        await showDetokenized(document.uri);
+    }
+}
+
+function serverSettings() {
+    const config = workspace.getConfiguration('basica');
+    return {
+        autoNumber: {
+            enabled: config.get<boolean>('autoNumber.enabled', true),
+            step: config.get<number>('autoNumber.step', 0),
+        },
+        dialect: config.get<string>('dialect', 'basica'),
+        diagnostics: {
+            severity: config.get<Record<string, string>>('diagnostics.severity', {}),
+        },
+        format: {
+            keywordCase: config.get<string>('format.keywordCase', 'upper'),
+            operatorSpacing: config.get<string>('format.operatorSpacing', 'preserve'),
+        },
+        run: {
+            timeout: config.get<number>('run.timeout', 10),
+            maxSteps: config.get<number>('run.maxSteps', 10000000),
+        },
+        features: {
+            diagnostics: config.get<boolean>('features.diagnostics', true),
+            hover: config.get<boolean>('features.hover', true),
+            completion: config.get<boolean>('features.completion', true),
+            signatureHelp: config.get<boolean>('features.signatureHelp', true),
+            semanticTokens: config.get<boolean>('features.semanticTokens', true),
+            folding: config.get<boolean>('features.folding', true),
+            formatting: config.get<boolean>('features.formatting', true),
+            codeLens: config.get<boolean>('features.codeLens', true),
+        },
+    };
 }
 
 export function deactivate(): Thenable<void> | undefined {